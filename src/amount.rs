@@ -0,0 +1,230 @@
+use web3::types::U256;
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, AddAssign, Neg, Sub, SubAssign};
+
+/// Signed token amount in raw token units.
+///
+/// Holdings go negative for the tokens a trader sold, so the accounting path
+/// needs a sign next to the exact `U256` magnitude. Zero is never negative.
+/// Sums saturate at a magnitude of `U256::MAX` rather than overflow, since
+/// spam tokens can move amounts close to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct I256 {
+    negative: bool,
+    abs: U256,
+}
+
+impl I256 {
+    pub fn zero() -> I256 {
+        I256 { negative: false, abs: U256::zero() }
+    }
+
+    pub fn from_parts(negative: bool, abs: U256) -> I256 {
+        I256 { negative: negative && !abs.is_zero(), abs }
+    }
+
+    /// Read an ABI `int256` word, which is in two's complement.
+    pub fn from_word(word: U256) -> I256 {
+        match word.bit(255) {
+            true => I256::from_parts(true, (!word).overflowing_add(U256::one()).0),
+            false => I256::from_parts(false, word),
+        }
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.negative
+    }
+
+    pub fn is_positive(&self) -> bool {
+        !self.negative && !self.abs.is_zero()
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.abs.is_zero()
+    }
+
+    pub fn abs(&self) -> U256 {
+        self.abs
+    }
+
+    /// Lossy conversion, only meant for display and ratios.
+    pub fn to_f64(&self) -> f64 {
+        let magnitude = u256_to_f64(self.abs);
+        match self.negative {
+            true => -magnitude,
+            false => magnitude,
+        }
+    }
+}
+
+impl From<U256> for I256 {
+    fn from(abs: U256) -> I256 {
+        I256 { negative: false, abs }
+    }
+}
+
+impl Neg for I256 {
+    type Output = I256;
+    fn neg(self) -> I256 {
+        I256::from_parts(!self.negative, self.abs)
+    }
+}
+
+impl Add for I256 {
+    type Output = I256;
+    fn add(self, other: I256) -> I256 {
+        match self.negative == other.negative {
+            true => I256::from_parts(self.negative, self.abs.saturating_add(other.abs)),
+            false => match self.abs >= other.abs {
+                true => I256::from_parts(self.negative, self.abs - other.abs),
+                false => I256::from_parts(other.negative, other.abs - self.abs),
+            },
+        }
+    }
+}
+
+impl Sub for I256 {
+    type Output = I256;
+    #[allow(clippy::suspicious_arithmetic_impl)]
+    fn sub(self, other: I256) -> I256 {
+        self + -other
+    }
+}
+
+impl AddAssign for I256 {
+    fn add_assign(&mut self, other: I256) {
+        *self = *self + other;
+    }
+}
+
+impl SubAssign for I256 {
+    fn sub_assign(&mut self, other: I256) {
+        *self = *self - other;
+    }
+}
+
+impl Ord for I256 {
+    fn cmp(&self, other: &I256) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, false) => self.abs.cmp(&other.abs),
+            (true, true) => other.abs.cmp(&self.abs),
+            (true, false) => Ordering::Less,
+            (false, true) => Ordering::Greater,
+        }
+    }
+}
+
+impl PartialOrd for I256 {
+    fn partial_cmp(&self, other: &I256) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for I256 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.negative {
+            write!(f, "-")?;
+        }
+        write!(f, "{}", self.abs)
+    }
+}
+
+/// Lossy conversion of a raw amount to `f64`, only meant for display and ratios.
+pub fn u256_to_f64(n: U256) -> f64 {
+    // Going through the decimal string rounds correctly to the nearest f64.
+    n.to_string().parse::<f64>().unwrap_or(f64::INFINITY)
+}
+
+/// Exact decimal string of a raw amount scaled down by `decimals`, e.g.
+/// `1500000` with 6 decimals becomes `1.5`.
+pub fn format_units(amount: I256, decimals: u8) -> String {
+    let digits = amount.abs().to_string();
+    let decimals = decimals as usize;
+    let (whole, frac) = match digits.len() > decimals {
+        true => digits.split_at(digits.len() - decimals),
+        false => ("0", digits.as_str()),
+    };
+    let frac = format!("{:0>width$}", frac, width = decimals);
+    let frac = frac.trim_end_matches('0');
+    let sign = match amount.is_negative() {
+        true => "-",
+        false => "",
+    };
+    match frac.is_empty() {
+        true => format!("{}{}", sign, whole),
+        false => format!("{}{}.{}", sign, whole, frac),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn int(n: i64) -> I256 {
+        I256::from_parts(n < 0, U256::from(n.unsigned_abs()))
+    }
+
+    #[test]
+    fn sums_follow_the_signs() {
+        assert_eq!(int(5) + int(3), int(8));
+        assert_eq!(int(-5) + int(-3), int(-8));
+        assert_eq!(int(5) + int(-3), int(2));
+        assert_eq!(int(3) + int(-5), int(-2));
+        assert_eq!(int(-3) - int(-5), int(2));
+        assert_eq!(int(3) - int(5), int(-2));
+        let mut holding = int(4);
+        holding -= int(10);
+        holding += int(1);
+        assert_eq!(holding, int(-5));
+        assert_eq!(holding.to_string(), "-5");
+        assert!(int(-5) < int(-3) && int(-3) < int(0) && int(0) < int(2));
+    }
+
+    #[test]
+    fn zero_is_never_negative() {
+        let negative_zero = I256::from_parts(true, U256::zero());
+        assert_eq!(negative_zero, I256::zero());
+        assert!(!negative_zero.is_negative() && !negative_zero.is_positive());
+        assert_eq!(-I256::zero(), I256::zero());
+        assert_eq!(int(-7) + int(7), I256::zero());
+        assert_eq!(int(7) - int(7), I256::zero());
+        assert_eq!((int(-7) + int(7)).to_string(), "0");
+    }
+
+    #[test]
+    fn sums_saturate() {
+        let max = I256::from(U256::MAX);
+        assert_eq!(max + int(1), max);
+        assert_eq!(-max - max, -max);
+        assert_eq!(max + -max, I256::zero());
+        let mut holding = max;
+        holding += max;
+        assert_eq!(holding.abs(), U256::MAX);
+    }
+
+    #[test]
+    fn words_are_twos_complement() {
+        assert_eq!(I256::from_word(U256::from(5)), int(5));
+        assert_eq!(I256::from_word(U256::MAX), int(-1));
+        assert_eq!(I256::from_word(U256::MAX - U256::from(1989)), int(-1990));
+        assert_eq!(I256::from_word(U256::one() << 255), I256::from_parts(true, U256::one() << 255));
+    }
+
+    #[test]
+    fn units_are_exact() {
+        assert_eq!(format_units(int(1_500_000), 6), "1.5");
+        assert_eq!(format_units(int(-1_500_000), 6), "-1.5");
+        // no decimals, and trailing zeros of whole amounts
+        assert_eq!(format_units(int(1_500_000), 0), "1500000");
+        assert_eq!(format_units(int(2_000_000), 6), "2");
+        assert_eq!(format_units(int(1_000_100), 3), "1000.1");
+        // less than one unit
+        assert_eq!(format_units(int(5), 6), "0.000005");
+        assert_eq!(format_units(int(-50), 18), "-0.00000000000000005");
+        assert_eq!(format_units(I256::zero(), 18), "0");
+        assert_eq!(format_units(I256::from(U256::MAX), 18),
+                   "115792089237316195423570985008687907853269984665640564039457.584007913129639935");
+    }
+}
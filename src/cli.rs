@@ -0,0 +1,193 @@
+use std::path::PathBuf;
+
+use clap::{Args, Parser, Subcommand};
+use web3::types::H160;
+
+use eth_explo::{CostMethod, ExportFormat};
+
+/// Explore uniswap v2 trader profit and loss from locally saved blocks and receipts.
+#[derive(Debug, Parser)]
+#[command(name = "eth_explo", version, about)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Scan a block range and print pools, trader profiles and capture stats
+    Analyze {
+        #[command(flatten)]
+        scan: ScanArgs,
+        #[command(flatten)]
+        output: OutputArgs,
+        #[command(flatten)]
+        tokens: TokenArgs,
+        #[command(flatten)]
+        export: ExportArgs,
+    },
+    /// Scan a block range and print the final state of every pool
+    Pools {
+        #[command(flatten)]
+        scan: ScanArgs,
+        #[command(flatten)]
+        tokens: TokenArgs,
+        #[command(flatten)]
+        export: ExportArgs,
+    },
+    /// Scan a block range and print traders sorted by real gain
+    Traders {
+        #[command(flatten)]
+        scan: ScanArgs,
+        #[command(flatten)]
+        output: OutputArgs,
+        #[command(flatten)]
+        tokens: TokenArgs,
+        #[command(flatten)]
+        export: ExportArgs,
+    },
+    /// Download blocks and receipts for a block range over JSON-RPC
+    Fetch {
+        #[command(flatten)]
+        range: RangeArgs,
+        #[command(flatten)]
+        fetch: FetchArgs,
+    },
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct RangeArgs {
+    /// First block to scan (inclusive)
+    #[arg(long, env = "ETH_EXPLO_START_BLOCK")]
+    pub start_block: u64,
+    /// Last block to scan (exclusive)
+    #[arg(long, env = "ETH_EXPLO_END_BLOCK")]
+    pub end_block: u64,
+    /// Folder holding one `{number}.json` file per block
+    #[arg(long, env = "ETH_EXPLO_BLOCKS_DIR", default_value = "../../testy/blocks")]
+    pub blocks_dir: PathBuf,
+    /// Folder holding one `{block}_{hash}.json` file per receipt
+    #[arg(long, env = "ETH_EXPLO_RECEIPTS_DIR", default_value = "../../testy/receipts")]
+    pub receipts_dir: PathBuf,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct ScanArgs {
+    #[command(flatten)]
+    pub range: RangeArgs,
+    /// Print decoding details for transactions sent from this address
+    #[arg(long, value_parser = parse_h160)]
+    pub debug_addr: Option<H160>,
+    /// Print decoding details for every transaction
+    #[arg(long)]
+    pub debug_all_addr: bool,
+    /// Print the block number as each block is scanned
+    #[arg(long)]
+    pub print_progress: bool,
+    /// Print every accepted transaction and receipt hash
+    #[arg(long)]
+    pub verbose: bool,
+    /// Rebuild swaps from pair Swap/Sync/Transfer logs of every receipt
+    /// instead of decoding router calldata, so swaps made by bots,
+    /// aggregators and other contracts are captured too
+    #[arg(long)]
+    pub from_logs: bool,
+    /// JSON array of extra DEXes (V2 forks such as SushiSwap) with their
+    /// routers, factory, init code hash and fee, added to Uniswap V2 and V3
+    #[arg(long, env = "ETH_EXPLO_DEXES")]
+    pub dexes: Option<PathBuf>,
+    /// SQLite file to keep the scan in; a later run with the same file only
+    /// scans the blocks after the last checkpoint
+    #[arg(long, env = "ETH_EXPLO_DB")]
+    pub db: Option<PathBuf>,
+    /// Save a checkpoint to the database every N blocks
+    #[arg(long, default_value_t = 1000)]
+    pub checkpoint_every: u64,
+    /// Blocks read and parsed ahead in parallel, one per CPU by default. Swaps
+    /// are still decoded one block at a time
+    #[arg(long, env = "ETH_EXPLO_JOBS")]
+    pub jobs: Option<usize>,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct OutputArgs {
+    /// Only print the top N traders of the sorted list
+    #[arg(long)]
+    pub top: Option<usize>,
+    /// Skip traders with fewer captured transactions than this
+    #[arg(long, default_value_t = 1)]
+    pub min_txs: usize,
+    /// Also print the totals, holdings and trade ledger of every trader
+    #[arg(long)]
+    pub print_profiles: bool,
+    /// Lot matching for realized PnL: fifo, lifo or average
+    #[arg(long, default_value_t = CostMethod::Fifo)]
+    pub cost_basis: CostMethod,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct ExportArgs {
+    /// Folder to write `traders`, `trades` and `pools` files into
+    #[arg(long, env = "ETH_EXPLO_EXPORT_DIR")]
+    pub export_dir: Option<PathBuf>,
+    /// Export file formats, comma separated: csv, jsonl, and with the
+    /// `columnar` feature parquet and arrow
+    #[arg(long, value_delimiter = ',', default_value = "csv")]
+    pub export_format: Vec<ExportFormat>,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct TokenArgs {
+    /// Token list (`.json` array or `.csv` with address,symbol,name,decimals)
+    /// used to print amounts in human units
+    #[arg(long, env = "ETH_EXPLO_TOKENS")]
+    pub tokens: Option<PathBuf>,
+    /// JSON object of cached `decimals()` results keyed by token address
+    #[arg(long, env = "ETH_EXPLO_DECIMALS_CACHE")]
+    pub decimals_cache: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct FetchArgs {
+    /// JSON-RPC endpoint of an archive node
+    #[arg(long, env = "ETH_EXPLO_RPC_URL")]
+    pub rpc_url: String,
+    /// Requests sent per JSON-RPC batch
+    #[arg(long, default_value_t = 50)]
+    pub batch_size: usize,
+    /// Batches in flight at the same time
+    #[arg(long, default_value_t = 4)]
+    pub concurrency: usize,
+    /// Times a failed batch is retried before giving up
+    #[arg(long, default_value_t = 5)]
+    pub max_retries: u32,
+    /// Delay before the first retry in milliseconds, doubled on every retry up to a minute
+    #[arg(long, default_value_t = 500)]
+    pub retry_delay_ms: u64,
+    /// Only save receipts of transactions sent to this address (repeatable),
+    /// all receipts are saved when not given
+    #[arg(long, value_parser = parse_h160)]
+    pub only_to: Vec<H160>,
+    /// Print progress after every batch
+    #[arg(long)]
+    pub print_progress: bool,
+}
+
+impl RangeArgs {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.start_block >= self.end_block {
+            return Err(format!("--start-block ({}) must be lower than --end-block ({})",
+                               self.start_block, self.end_block));
+        }
+        Ok(())
+    }
+}
+
+pub fn parse_h160(s: &str) -> Result<H160, String> {
+    let s = s.strip_prefix("0x").unwrap_or(s);
+    let bytes = hex::decode(s).map_err(|e| format!("invalid hex address: {}", e))?;
+    if bytes.len() != 20 {
+        return Err(format!("address must be 20 bytes, got {}", bytes.len()));
+    }
+    Ok(H160::from_slice(&bytes))
+}
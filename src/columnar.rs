@@ -0,0 +1,272 @@
+use arrow_array::builder::{FixedSizeBinaryBuilder, ListBuilder};
+use arrow_array::types::Decimal256Type;
+use arrow_array::{ArrayRef, Float64Array, PrimitiveArray, RecordBatch, StringArray, UInt64Array};
+use arrow_buffer::i256;
+use arrow_schema::ArrowError;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use web3::types::{H160, H256, U256};
+
+use std::error::Error;
+use std::fs::File;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::amount::I256;
+use crate::export::ExportFormat;
+use crate::ledger::Trade;
+use crate::pools::PoolRegistry;
+use crate::Trader;
+
+// Largest precision of a Decimal256 column, two digits short of U256::MAX
+const DECIMAL_PRECISION: u8 = 76;
+
+// Amounts past 76 digits do not fit a decimal column and are written as null.
+fn decimal(amount: U256) -> Option<i256> {
+    if amount >= U256::exp10(DECIMAL_PRECISION as usize) {
+        return None;
+    }
+    let mut bytes = [0u8; 32];
+    amount.to_big_endian(&mut bytes);
+    Some(i256::from_be_bytes(bytes))
+}
+
+fn signed_decimal(amount: I256) -> Option<i256> {
+    let abs = decimal(amount.abs())?;
+    match amount.is_negative() {
+        true => Some(abs.wrapping_neg()),
+        false => Some(abs),
+    }
+}
+
+fn decimals(amounts: impl IntoIterator<Item = Option<i256>>) -> Result<ArrayRef, ArrowError> {
+    let array = amounts.into_iter()
+        .collect::<PrimitiveArray<Decimal256Type>>()
+        .with_precision_and_scale(DECIMAL_PRECISION, 0)?;
+    Ok(Arc::new(array))
+}
+
+// Built value by value rather than with `try_from_iter`, which refuses an
+// empty input, so an empty batch still gets the typed column.
+fn fixed_size_binary<'a>(width: i32, values: impl IntoIterator<Item = &'a [u8]>)
+-> Result<ArrayRef, ArrowError> {
+    let values = values.into_iter();
+    let mut builder = FixedSizeBinaryBuilder::with_capacity(values.size_hint().0, width);
+    for value in values {
+        builder.append_value(value)?;
+    }
+    Ok(Arc::new(builder.finish()))
+}
+
+fn addresses<'a>(addresses: impl IntoIterator<Item = &'a H160>) -> Result<ArrayRef, ArrowError> {
+    fixed_size_binary(20, addresses.into_iter().map(|address| address.as_bytes()))
+}
+
+fn hashes<'a>(hashes: impl IntoIterator<Item = &'a H256>) -> Result<ArrayRef, ArrowError> {
+    fixed_size_binary(32, hashes.into_iter().map(|hash| hash.as_bytes()))
+}
+
+fn address_lists<'a>(lists: impl IntoIterator<Item = &'a [H160]>) -> Result<ArrayRef, ArrowError> {
+    let mut builder = ListBuilder::new(FixedSizeBinaryBuilder::new(20));
+    for list in lists {
+        for address in list {
+            builder.values().append_value(address.as_bytes())?;
+        }
+        builder.append(true);
+    }
+    Ok(Arc::new(builder.finish()))
+}
+
+/// The trade ledger in chain order as one batch: hashes and addresses are
+/// fixed-size binary, amounts and wei values Decimal256(76, 0).
+pub fn trades_batch(trades: &[&Trade]) -> Result<RecordBatch, ArrowError> {
+    RecordBatch::try_from_iter(vec![
+        ("tx_hash", hashes(trades.iter().map(|trade| &trade.tx_hash))?),
+        ("block", Arc::new(UInt64Array::from_iter_values(trades.iter().map(|trade| trade.block)))),
+        ("tx_index", Arc::new(UInt64Array::from_iter_values(trades.iter().map(|trade| trade.tx_index)))),
+        ("log_index", Arc::new(UInt64Array::from_iter_values(
+            trades.iter().map(|trade| trade.log_index as u64)))),
+        ("trader", addresses(trades.iter().map(|trade| &trade.trader))?),
+        ("venue", Arc::new(StringArray::from_iter_values(trades.iter().map(|trade| &trade.venue)))),
+        ("token_in", addresses(trades.iter().map(|trade| &trade.path[0]))?),
+        ("token_out", addresses(trades.iter().map(|trade| &trade.path[trade.path.len() - 1]))?),
+        ("path", address_lists(trades.iter().map(|trade| trade.path.as_slice()))?),
+        ("pools", address_lists(trades.iter().map(|trade| trade.pools.as_slice()))?),
+        ("amount_in", decimals(trades.iter().map(|trade| decimal(trade.amount_in)))?),
+        ("amount_out", decimals(trades.iter().map(|trade| decimal(trade.amount_out)))?),
+        ("execution_price", Arc::new(Float64Array::from_iter_values(
+            trades.iter().map(|trade| trade.execution_price())))),
+        ("cost", decimals(trades.iter().map(|trade| trade.cost.and_then(decimal)))?),
+        ("gas_used", decimals(trades.iter().map(|trade| decimal(trade.gas.gas_used)))?),
+        ("effective_gas_price", decimals(trades.iter().map(|trade| decimal(trade.gas.effective_gas_price)))?),
+        ("gas_fee", decimals(trades.iter().map(|trade| decimal(trade.gas.total())))?),
+        ("gas_burned", decimals(trades.iter().map(|trade| decimal(trade.gas.base_fee)))?),
+        ("gas_tip", decimals(trades.iter().map(|trade| decimal(trade.gas.priority_fee)))?),
+    ])
+}
+
+/// Every recorded Sync of every tracked pair, by pair and then chain order.
+pub fn reserves_batch(pools: &PoolRegistry) -> Result<RecordBatch, ArrowError> {
+    let mut pairs = pools.iter().collect::<Vec<_>>();
+    pairs.sort_by_key(|pool| pool.address());
+    let rows = pairs.iter()
+        .flat_map(|pool| pools.history(&pool.address()).iter()
+            .map(move |snapshot| (pool.address(), pool.tokens(), snapshot)))
+        .collect::<Vec<_>>();
+    RecordBatch::try_from_iter(vec![
+        ("pool", addresses(rows.iter().map(|(pool, _, _)| pool))?),
+        ("token0", addresses(rows.iter().map(|(_, tokens, _)| &tokens.0))?),
+        ("token1", addresses(rows.iter().map(|(_, tokens, _)| &tokens.1))?),
+        ("block", Arc::new(UInt64Array::from_iter_values(rows.iter().map(|(_, _, snapshot)| snapshot.block)))
+            as ArrayRef),
+        ("tx_index", Arc::new(UInt64Array::from_iter_values(
+            rows.iter().map(|(_, _, snapshot)| snapshot.tx_index)))),
+        ("log_index", Arc::new(UInt64Array::from_iter_values(
+            rows.iter().map(|(_, _, snapshot)| snapshot.log_index)))),
+        ("reserve0", decimals(rows.iter().map(|(_, _, snapshot)| decimal(snapshot.reserve0)))?),
+        ("reserve1", decimals(rows.iter().map(|(_, _, snapshot)| decimal(snapshot.reserve1)))?),
+    ])
+}
+
+/// One row per trader with the settled totals, wei values as decimals.
+pub fn traders_batch(traders: &[&Trader]) -> Result<RecordBatch, ArrowError> {
+    RecordBatch::try_from_iter(vec![
+        ("address", addresses(traders.iter().map(|t| &t.address))?),
+        ("txs", Arc::new(UInt64Array::from_iter_values(traders.iter().map(|t| t.cum_txs as u64))) as ArrayRef),
+        ("trades", Arc::new(UInt64Array::from_iter_values(traders.iter().map(|t| t.trades.len() as u64)))),
+        ("gas_used", decimals(traders.iter().map(|t| decimal(t.cum_gas)))?),
+        ("gas_fees", decimals(traders.iter().map(|t| decimal(t.gas_fees)))?),
+        ("gas_burned", decimals(traders.iter().map(|t| decimal(t.gas_burned)))?),
+        ("gas_tips", decimals(traders.iter().map(|t| decimal(t.gas_tips)))?),
+        ("cost", decimals(traders.iter().map(|t| decimal(t.hist_cost)))?),
+        ("total_assets", decimals(traders.iter().map(|t| decimal(t.total_assets)))?),
+        ("total_debt", decimals(traders.iter().map(|t| decimal(t.total_debt)))?),
+        ("profit", decimals(traders.iter().map(|t| signed_decimal(t.profit_raw)))?),
+        ("realized_pnl", decimals(traders.iter().map(|t| signed_decimal(t.realized_pnl)))?),
+        ("unrealized_pnl", decimals(traders.iter().map(|t| signed_decimal(t.unrealized_pnl)))?),
+        ("profit_ratio", Arc::new(Float64Array::from_iter_values(traders.iter().map(|t| t.profit_percent)))),
+        ("roi", Arc::new(Float64Array::from_iter_values(traders.iter().map(|t| t.roi_percent)))),
+        ("real_gain", Arc::new(Float64Array::from_iter_values(traders.iter().map(|t| t.real_gain_percent)))),
+        ("labels", Arc::new(StringArray::from_iter_values(traders.iter().map(|t| t.labels.iter()
+            .map(|label| label.name()).collect::<Vec<&str>>().join(";"))))),
+    ])
+}
+
+/// Write `batch` to `path` as a Snappy compressed Parquet file or an Arrow
+/// IPC file, replacing it.
+pub fn write_batch<P: AsRef<Path>>(path: P, format: ExportFormat, batch: &RecordBatch)
+-> Result<(), Box<dyn Error>> {
+    let file = File::create(path)?;
+    match format {
+        ExportFormat::Parquet => {
+            let properties = WriterProperties::builder()
+                .set_compression(Compression::SNAPPY)
+                .build();
+            let mut writer = ArrowWriter::try_new(file, batch.schema(), Some(properties))?;
+            writer.write(batch)?;
+            writer.close()?;
+        },
+        ExportFormat::Arrow => {
+            let mut writer = arrow_ipc::writer::FileWriter::try_new(file, &batch.schema())?;
+            writer.write(batch)?;
+            writer.finish()?;
+        },
+        ExportFormat::Csv | ExportFormat::Jsonl =>
+            return Err(format!("{} is not a columnar format", format).into()),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_array::{Array, Decimal256Array, FixedSizeBinaryArray};
+    use arrow_schema::DataType;
+
+    use crate::dex::DexRegistry;
+    use crate::ledger::GasCost;
+
+    fn trade(amount_in: U256, cost: Option<U256>) -> Trade {
+        Trade {
+            tx_hash: H256::from_low_u64_be(0xabc),
+            block: 7,
+            tx_index: 2,
+            log_index: 5,
+            trader: H160::from_low_u64_be(0xbb),
+            venue: "uniswap-v2".to_string(),
+            path: vec![H160::from_low_u64_be(1), H160::from_low_u64_be(2)],
+            pools: vec![H160::from_low_u64_be(0x99)],
+            amount_in,
+            amount_out: U256::from(1990),
+            cost,
+            reserves_before: vec![None],
+            reserves_after: vec![None],
+            gas: GasCost::default(),
+        }
+    }
+
+    fn column<'a, A: Array + 'static>(batch: &'a RecordBatch, name: &str) -> &'a A {
+        batch.column_by_name(name).unwrap().as_any().downcast_ref::<A>().unwrap()
+    }
+
+    #[test]
+    fn empty_batches_keep_fixed_size_columns() {
+        let trades = trades_batch(&[]).unwrap();
+        assert_eq!(trades.num_rows(), 0);
+        assert_eq!(trades.schema().field_with_name("tx_hash").unwrap().data_type(),
+                   &DataType::FixedSizeBinary(32));
+        assert_eq!(trades.schema().field_with_name("trader").unwrap().data_type(),
+                   &DataType::FixedSizeBinary(20));
+
+        let reserves = reserves_batch(&PoolRegistry::new(DexRegistry::mainnet())).unwrap();
+        assert_eq!(reserves.num_rows(), 0);
+        assert_eq!(reserves.schema().field_with_name("pool").unwrap().data_type(),
+                   &DataType::FixedSizeBinary(20));
+
+        let traders = traders_batch(&[]).unwrap();
+        assert_eq!(traders.num_rows(), 0);
+    }
+
+    #[test]
+    fn batches_read_back() {
+        let largest = U256::exp10(76) - 1;
+        let trades = [trade(U256::from(1000), None), trade(largest, Some(U256::exp10(76)))];
+        let batch = trades_batch(&trades.iter().collect::<Vec<_>>()).unwrap();
+        assert_eq!(batch.num_rows(), 2);
+        assert_eq!(batch.schema().field_with_name("amount_in").unwrap().data_type(),
+                   &DataType::Decimal256(76, 0));
+
+        let tx_hashes = column::<FixedSizeBinaryArray>(&batch, "tx_hash");
+        assert_eq!(tx_hashes.value(0), H256::from_low_u64_be(0xabc).as_bytes());
+        let traders = column::<FixedSizeBinaryArray>(&batch, "trader");
+        assert_eq!(traders.value(1), H160::from_low_u64_be(0xbb).as_bytes());
+
+        let amounts_in = column::<Decimal256Array>(&batch, "amount_in");
+        assert_eq!(amounts_in.value(0), i256::from_i128(1000));
+        assert_eq!(amounts_in.value_as_string(1), "9".repeat(76));
+        // no cost, and a cost too large for 76 digits, are both null
+        let costs = column::<Decimal256Array>(&batch, "cost");
+        assert!(costs.is_null(0));
+        assert!(costs.is_null(1));
+
+        let mut trader = Trader::new();
+        trader.profit_raw = I256::from_parts(true, U256::from(250));
+        trader.realized_pnl = I256::from(U256::from(40));
+        trader.unrealized_pnl = I256::from_parts(true, U256::exp10(76));
+        let batch = traders_batch(&[&trader]).unwrap();
+        assert_eq!(column::<Decimal256Array>(&batch, "profit").value(0), i256::from_i128(-250));
+        assert_eq!(column::<Decimal256Array>(&batch, "realized_pnl").value(0), i256::from_i128(40));
+        assert!(column::<Decimal256Array>(&batch, "unrealized_pnl").is_null(0));
+    }
+
+    #[test]
+    fn empty_batches_write() {
+        let dir = std::env::temp_dir().join(format!("eth_explo_columnar_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for format in [ExportFormat::Parquet, ExportFormat::Arrow] {
+            write_batch(format.path(&dir, "trades"), format, &trades_batch(&[]).unwrap()).unwrap();
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,456 @@
+use web3::ethabi::ethereum_types::U512;
+use web3::types::{H160, H256, U256};
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::amount::I256;
+use crate::ledger::Trade;
+
+/// Which open lots a sale is matched against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum CostMethod {
+    /// Oldest lots first.
+    #[default]
+    Fifo,
+    /// Newest lots first.
+    Lifo,
+    /// One pooled lot per token at the average cost.
+    Average,
+}
+
+impl CostMethod {
+    pub fn name(&self) -> &'static str {
+        match self {
+            CostMethod::Fifo => "fifo",
+            CostMethod::Lifo => "lifo",
+            CostMethod::Average => "average",
+        }
+    }
+}
+
+impl FromStr for CostMethod {
+    type Err = String;
+    fn from_str(s: &str) -> Result<CostMethod, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "fifo" => Ok(CostMethod::Fifo),
+            "lifo" => Ok(CostMethod::Lifo),
+            "average" | "avg" => Ok(CostMethod::Average),
+            _ => Err(format!("unknown cost basis method {}, expected fifo, lifo or average", s)),
+        }
+    }
+}
+
+impl fmt::Display for CostMethod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Tokens bought in one trade and not sold yet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lot {
+    pub tx_hash: H256,
+    pub block: u64,
+    pub amount: U256,
+    // weth paid for `amount`
+    pub cost: U256,
+}
+
+/// A sale matched against open lots.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Disposal {
+    pub token: H160,
+    pub tx_hash: H256,
+    pub block: u64,
+    // amount matched against lots
+    pub amount: U256,
+    // weth received for `amount`
+    pub proceeds: U256,
+    // weth the matched lots cost
+    pub cost: U256,
+    // amount sold beyond the open lots, e.g. bought before the scanned range,
+    // which has no known cost and is left out of the PnL
+    pub unmatched: U256,
+}
+
+impl Disposal {
+    pub fn pnl(&self) -> I256 {
+        I256::from(self.proceeds) - I256::from(self.cost)
+    }
+}
+
+/// Realized and unrealized PnL of one token of a trader, in weth.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PositionPnl {
+    pub token: H160,
+    pub realized: I256,
+    // sales closed against lots, and the amount sold without a lot
+    pub disposals: usize,
+    pub unmatched: U256,
+    pub open_amount: U256,
+    pub open_cost: U256,
+    // weth the open amount sells for, None when it cannot be priced
+    pub value: Option<U256>,
+    pub unrealized: Option<I256>,
+}
+
+fn mul_div(a: U256, b: U256, c: U256) -> U256 {
+    match c.is_zero() {
+        true => U256::zero(),
+        false => U256::try_from(U512::from(a) * U512::from(b) / U512::from(c)).unwrap_or(U256::MAX),
+    }
+}
+
+/// Lot matching over a trader's ledger.
+///
+/// Every trade sells `amount_in` of one token and buys `amount_out` of
+/// another for the same weth value: the weth actually paid or received when
+/// one side is weth, else its `cost`. The bought tokens open a lot at that
+/// value, the sold tokens close lots and realize the difference. Weth
+/// is the unit of account, so it never opens lots, and a trade from a token
+/// back into itself realizes its gain or loss at once. A trade without a
+/// cost closes lots without realizing anything and opens none, so what it
+/// bought sells unmatched later.
+#[derive(Debug, Clone)]
+pub struct CostBasis {
+    method: CostMethod,
+    numeraire: H160,
+    lots: HashMap<H160, VecDeque<Lot>>,
+    pub disposals: Vec<Disposal>,
+}
+
+impl CostBasis {
+    pub fn new(method: CostMethod, numeraire: H160) -> CostBasis {
+        CostBasis {
+            method,
+            numeraire,
+            lots: HashMap::new(),
+            disposals: vec![],
+        }
+    }
+
+    /// Replay a ledger in chain order.
+    pub fn from_trades(method: CostMethod, numeraire: H160, trades: &[Trade]) -> CostBasis {
+        let mut basis = CostBasis::new(method, numeraire);
+        trades.iter().for_each(|trade| basis.apply(trade));
+        basis
+    }
+
+    pub fn method(&self) -> CostMethod {
+        self.method
+    }
+
+    pub fn apply(&mut self, trade: &Trade) {
+        if trade.token_in() == trade.token_out() {
+            return self.round_trip(trade);
+        }
+        // a weth side is the execution price, the cost only a quote before the trade
+        let value = match (trade.token_in() == self.numeraire, trade.token_out() == self.numeraire) {
+            (true, _) => Some(trade.amount_in),
+            (false, true) => Some(trade.amount_out),
+            (false, false) => trade.cost,
+        };
+        // sell first so a lot is never matched against the trade that opened it
+        if trade.token_in() != self.numeraire {
+            match value {
+                Some(proceeds) => self.dispose(trade.token_in(), trade, trade.amount_in, proceeds),
+                None => {
+                    self.take(trade.token_in(), trade.amount_in);
+                },
+            }
+        }
+        if let (true, Some(cost)) = (trade.token_out() != self.numeraire, value) {
+            self.acquire(trade.token_out(), Lot {
+                tx_hash: trade.tx_hash,
+                block: trade.block,
+                amount: trade.amount_out,
+                cost,
+            });
+        }
+    }
+
+    fn acquire(&mut self, token: H160, lot: Lot) {
+        let lots = self.lots.entry(token).or_default();
+        match (self.method, lots.front_mut()) {
+            (CostMethod::Average, Some(pooled)) => {
+                pooled.amount += lot.amount;
+                pooled.cost += lot.cost;
+            },
+            _ => lots.push_back(lot),
+        }
+    }
+
+    // A trade back into the token it sold, e.g. an arbitrage, realizes what it
+    // gained or lost on the spot. In weth that is the difference itself, in
+    // other tokens a gain opens a lot at no cost and a loss closes lots for
+    // nothing.
+    fn round_trip(&mut self, trade: &Trade) {
+        let token = trade.token_in();
+        match (token == self.numeraire, trade.amount_out >= trade.amount_in) {
+            (true, _) => self.disposals.push(Disposal {
+                token,
+                tx_hash: trade.tx_hash,
+                block: trade.block,
+                amount: trade.amount_in,
+                proceeds: trade.amount_out,
+                cost: trade.amount_in,
+                unmatched: U256::zero(),
+            }),
+            (false, true) => self.acquire(token, Lot {
+                tx_hash: trade.tx_hash,
+                block: trade.block,
+                amount: trade.amount_out - trade.amount_in,
+                cost: U256::zero(),
+            }),
+            (false, false) => self.dispose(token, trade, trade.amount_in - trade.amount_out, U256::zero()),
+        }
+    }
+
+    // Close lots of `token` for `sold` of it, which brought in `proceeds` weth.
+    fn dispose(&mut self, token: H160, trade: &Trade, sold: U256, proceeds: U256) {
+        let (cost, left) = self.take(token, sold);
+        let amount = sold - left;
+        self.disposals.push(Disposal {
+            token,
+            tx_hash: trade.tx_hash,
+            block: trade.block,
+            amount,
+            proceeds: mul_div(proceeds, amount, sold),
+            cost,
+            unmatched: left,
+        });
+    }
+
+    // Take `sold` of `token` out of the open lots in the method's order.
+    // Returns the cost of what was taken and the amount beyond the lots.
+    fn take(&mut self, token: H160, sold: U256) -> (U256, U256) {
+        let lots = self.lots.entry(token).or_default();
+        let mut left = sold;
+        let mut cost = U256::zero();
+        while !left.is_zero() {
+            let lot = match self.method {
+                CostMethod::Lifo => lots.back_mut(),
+                CostMethod::Fifo | CostMethod::Average => lots.front_mut(),
+            };
+            let lot = match lot {
+                Some(lot) => lot,
+                None => break,
+            };
+            let taken = left.min(lot.amount);
+            let taken_cost = match taken == lot.amount {
+                true => lot.cost,
+                false => mul_div(lot.cost, taken, lot.amount),
+            };
+            lot.amount -= taken;
+            lot.cost -= taken_cost;
+            cost += taken_cost;
+            left -= taken;
+            if lot.amount.is_zero() {
+                match self.method {
+                    CostMethod::Lifo => lots.pop_back(),
+                    CostMethod::Fifo | CostMethod::Average => lots.pop_front(),
+                };
+            }
+        }
+        (cost, left)
+    }
+
+    /// Open lots of a token, oldest first.
+    pub fn open_lots(&self, token: &H160) -> impl Iterator<Item = &Lot> {
+        self.lots.get(token).into_iter().flatten()
+    }
+
+    /// PnL realized by every sale matched against lots.
+    pub fn realized(&self) -> I256 {
+        self.disposals.iter()
+            .fold(I256::zero(), |acc, disposal| acc + disposal.pnl())
+    }
+
+    /// Realized PnL per token together with the open lots priced by `value`,
+    /// which gives the weth an amount of a token sells for. Sorted by token.
+    pub fn positions(&self, value: impl Fn(H160, U256) -> Option<U256>) -> Vec<PositionPnl> {
+        let mut tokens = self.lots.keys()
+            .chain(self.disposals.iter().map(|disposal| &disposal.token))
+            .copied()
+            .collect::<Vec<H160>>();
+        tokens.sort();
+        tokens.dedup();
+        tokens.into_iter()
+            .map(|token| {
+                let disposals = self.disposals.iter()
+                    .filter(|disposal| disposal.token == token)
+                    .collect::<Vec<&Disposal>>();
+                let (open_amount, open_cost) = self.open_lots(&token)
+                    .fold((U256::zero(), U256::zero()),
+                          |(amount, cost), lot| (amount + lot.amount, cost + lot.cost));
+                let value = match open_amount.is_zero() {
+                    true => Some(U256::zero()),
+                    false => value(token, open_amount),
+                };
+                PositionPnl {
+                    token,
+                    realized: disposals.iter().fold(I256::zero(), |acc, disposal| acc + disposal.pnl()),
+                    disposals: disposals.iter().filter(|disposal| !disposal.amount.is_zero()).count(),
+                    unmatched: disposals.iter().fold(U256::zero(), |acc, disposal| acc + disposal.unmatched),
+                    open_amount,
+                    open_cost,
+                    value,
+                    unrealized: value.map(|value| I256::from(value) - I256::from(open_cost)),
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ledger::GasCost;
+
+    const WETH: u64 = 1;
+    const TOKEN: u64 = 2;
+    const OTHER: u64 = 3;
+
+    fn address(n: u64) -> H160 {
+        H160::from_low_u64_be(n)
+    }
+
+    fn trade(tx: u64, token_in: u64, token_out: u64, amount_in: u64, amount_out: u64, cost: Option<u64>) -> Trade {
+        Trade {
+            tx_hash: H256::from_low_u64_be(tx),
+            block: tx,
+            tx_index: 0,
+            log_index: 0,
+            trader: address(0xa),
+            venue: "uniswap-v2".to_string(),
+            path: vec![address(token_in), address(token_out)],
+            pools: vec![address(0xaa)],
+            amount_in: U256::from(amount_in),
+            amount_out: U256::from(amount_out),
+            cost: cost.map(U256::from),
+            reserves_before: vec![None],
+            reserves_after: vec![None],
+            gas: GasCost::default(),
+        }
+    }
+
+    fn signed(amount: i64) -> I256 {
+        match amount < 0 {
+            true => -I256::from(U256::from(amount.unsigned_abs())),
+            false => I256::from(U256::from(amount as u64)),
+        }
+    }
+
+    // two buys at different prices and a sale of half
+    fn ledger() -> Vec<Trade> {
+        vec![
+            trade(1, WETH, TOKEN, 100, 100, Some(100)),
+            trade(2, WETH, TOKEN, 300, 100, Some(300)),
+            trade(3, TOKEN, WETH, 100, 250, Some(250)),
+        ]
+    }
+
+    fn open(basis: &CostBasis) -> Vec<(U256, U256)> {
+        basis.open_lots(&address(TOKEN)).map(|lot| (lot.amount, lot.cost)).collect()
+    }
+
+    #[test]
+    fn methods_pick_different_lots() {
+        let fifo = CostBasis::from_trades(CostMethod::Fifo, address(WETH), &ledger());
+        assert_eq!(fifo.realized(), signed(150));
+        assert_eq!(open(&fifo), vec![(U256::from(100), U256::from(300))]);
+
+        let lifo = CostBasis::from_trades(CostMethod::Lifo, address(WETH), &ledger());
+        assert_eq!(lifo.realized(), signed(-50));
+        assert_eq!(open(&lifo), vec![(U256::from(100), U256::from(100))]);
+
+        let average = CostBasis::from_trades(CostMethod::Average, address(WETH), &ledger());
+        assert_eq!(average.realized(), signed(50));
+        assert_eq!(open(&average), vec![(U256::from(100), U256::from(200))]);
+    }
+
+    #[test]
+    fn partial_lots_and_positions() {
+        let mut trades = ledger();
+        trades.push(trade(4, TOKEN, WETH, 50, 160, Some(160)));
+        let basis = CostBasis::from_trades(CostMethod::Fifo, address(WETH), &trades);
+        // half of the 300 lot is left
+        assert_eq!(open(&basis), vec![(U256::from(50), U256::from(150))]);
+        assert_eq!(basis.realized(), signed(150 + 10));
+
+        let positions = basis.positions(|_, amount| Some(amount * 4));
+        assert_eq!(positions.len(), 1);
+        let position = &positions[0];
+        assert_eq!((position.disposals, position.open_amount, position.open_cost),
+                   (2, U256::from(50), U256::from(150)));
+        assert_eq!(position.value, Some(U256::from(200)));
+        assert_eq!(position.unrealized, Some(signed(50)));
+        assert_eq!(basis.positions(|_, _| None)[0].unrealized, None);
+    }
+
+    #[test]
+    fn weth_sides_use_the_execution_price() {
+        // the quotes before the trades were off, the weth that changed hands was not
+        let trades = vec![
+            trade(1, WETH, TOKEN, 100, 100, Some(90)),
+            trade(2, TOKEN, WETH, 100, 130, Some(150)),
+        ];
+        let basis = CostBasis::from_trades(CostMethod::Fifo, address(WETH), &trades);
+        assert_eq!(basis.disposals.len(), 1);
+        assert_eq!((basis.disposals[0].proceeds, basis.disposals[0].cost), (U256::from(130), U256::from(100)));
+        assert_eq!(basis.realized(), signed(30));
+    }
+
+    #[test]
+    fn sales_without_lots_are_unmatched() {
+        let trades = vec![
+            trade(1, WETH, TOKEN, 100, 100, Some(100)),
+            trade(2, TOKEN, WETH, 150, 300, Some(300)),
+        ];
+        let basis = CostBasis::from_trades(CostMethod::Fifo, address(WETH), &trades);
+        let disposal = &basis.disposals[0];
+        assert_eq!((disposal.amount, disposal.unmatched), (U256::from(100), U256::from(50)));
+        // only the matched two thirds of the proceeds count
+        assert_eq!(disposal.proceeds, U256::from(200));
+        assert_eq!(basis.realized(), signed(100));
+    }
+
+    #[test]
+    fn unpriced_trades_realize_nothing() {
+        let trades = vec![
+            trade(1, WETH, TOKEN, 100, 100, Some(100)),
+            // TOKEN into a token without a weth value
+            trade(2, TOKEN, OTHER, 100, 500, None),
+            trade(3, OTHER, WETH, 500, 400, Some(400)),
+        ];
+        let basis = CostBasis::from_trades(CostMethod::Fifo, address(WETH), &trades);
+        // the TOKEN lot is closed without a disposal and OTHER opens no lot
+        assert_eq!(open(&basis), vec![]);
+        assert_eq!(basis.open_lots(&address(OTHER)).count(), 0);
+        assert_eq!(basis.disposals.len(), 1);
+        assert_eq!((basis.disposals[0].token, basis.disposals[0].unmatched), (address(OTHER), U256::from(500)));
+        assert_eq!(basis.realized(), I256::zero());
+    }
+
+    #[test]
+    fn round_trips_realize_at_once() {
+        let trades = vec![
+            trade(1, WETH, WETH, 1_000, 1_097, None),
+            trade(2, WETH, TOKEN, 100, 100, Some(100)),
+            trade(3, TOKEN, TOKEN, 100, 90, None),
+        ];
+        let basis = CostBasis::from_trades(CostMethod::Fifo, address(WETH), &trades);
+        // the weth cycle gains 97, the token cycle gives away 10 of a lot that cost 10
+        assert_eq!(basis.realized(), signed(97 - 10));
+        assert_eq!(open(&basis), vec![(U256::from(90), U256::from(90))]);
+    }
+
+    #[test]
+    fn methods_parse() {
+        assert_eq!("FIFO".parse(), Ok(CostMethod::Fifo));
+        assert_eq!("avg".parse(), Ok(CostMethod::Average));
+        assert!("hifo".parse::<CostMethod>().is_err());
+    }
+}
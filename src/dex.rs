@@ -0,0 +1,233 @@
+use serde::Deserialize;
+use web3::ethabi::{self, Token};
+use web3::signing::keccak256;
+use web3::types::{H160, H256};
+
+use std::error::Error;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use crate::v3::{FEE_TIERS, V3_FACTORY, V3_POOL_INIT_CODE_HASH, V3_SWAP_ROUTER, V3_SWAP_ROUTER_02};
+
+/// Swap fee of Uniswap V2 pairs, in basis points, assumed for V2 DEXes
+/// whose config leaves it out.
+pub const DEFAULT_FEE_BPS: u32 = 30;
+
+// A fee of all of the input leaves nothing to swap.
+const MAX_FEE_BPS: u32 = 10_000;
+
+/// Contract family of a DEX, which decides how its router calls and pool
+/// events are decoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DexKind {
+    /// UniswapV2Router02 and its forks, constant product pairs.
+    V2,
+    /// Uniswap V3 SwapRouter/SwapRouter02, concentrated liquidity pools.
+    V3,
+}
+
+/// One deployment of a DEX: where its routers and factory live and how the
+/// factory derives pool addresses.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Dex {
+    /// Venue name every trade through this DEX is tagged with.
+    pub name: String,
+    pub kind: DexKind,
+    pub routers: Vec<H160>,
+    pub factory: H160,
+    /// keccak256 of the pair creation code, used in the CREATE2 address.
+    pub init_code_hash: H256,
+    /// Swap fee in basis points, 30 when left out. V3 pools carry their own
+    /// fee tier.
+    #[serde(default = "default_fee_bps")]
+    pub fee_bps: u32,
+}
+
+fn default_fee_bps() -> u32 {
+    DEFAULT_FEE_BPS
+}
+
+impl Dex {
+    /// CREATE2 address of the V2 pair for two sorted tokens.
+    pub fn pair_address(&self, token0: H160, token1: H160) -> H160 {
+        let mut packed = token0.as_bytes().to_vec();
+        packed.extend_from_slice(token1.as_bytes());
+        create2_address(self.factory, keccak256(&packed), self.init_code_hash)
+    }
+
+    /// CREATE2 address of the V3 pool for two sorted tokens and a fee tier.
+    pub fn pool_address(&self, token0: H160, token1: H160, fee: u32) -> H160 {
+        let salt = keccak256(&ethabi::encode(&[Token::Address(token0), Token::Address(token1),
+                                               Token::Uint(fee.into())]));
+        create2_address(self.factory, salt, self.init_code_hash)
+    }
+
+    /// Fee tier of `pool` if the factory deployed it for the two sorted tokens.
+    pub fn pool_fee(&self, pool: H160, token0: H160, token1: H160) -> Option<u32> {
+        FEE_TIERS.iter()
+            .find(|fee| self.pool_address(token0, token1, **fee) == pool)
+            .copied()
+    }
+
+    /// Whether the factory deployed `pool` for the two sorted tokens.
+    pub fn owns_pool(&self, pool: H160, token0: H160, token1: H160) -> bool {
+        match self.kind {
+            DexKind::V2 => self.pair_address(token0, token1) == pool,
+            DexKind::V3 => self.pool_fee(pool, token0, token1).is_some(),
+        }
+    }
+}
+
+/// Address a factory deploys to with CREATE2 for `salt`.
+pub fn create2_address(factory: H160, salt: [u8; 32], init_code_hash: H256) -> H160 {
+    let mut preimage = vec![0xff];
+    preimage.extend_from_slice(factory.as_bytes());
+    preimage.extend_from_slice(&salt);
+    preimage.extend_from_slice(init_code_hash.as_bytes());
+    H160::from_slice(&keccak256(&preimage)[12..])
+}
+
+fn address(hex_str: &str) -> H160 {
+    H160::from_slice(&hex::decode(hex_str).expect("valid address constant"))
+}
+
+fn hash(hex_str: &str) -> H256 {
+    H256::from_slice(&hex::decode(hex_str).expect("valid hash constant"))
+}
+
+/// The DEXes whose routers are decoded and whose pools are recognized.
+///
+/// Starts from the mainnet deployments of Uniswap V2 and V3. Forks such as
+/// SushiSwap are added from a config file, since a wrong init code hash would
+/// silently misattribute their pools.
+#[derive(Debug, Clone, Default)]
+pub struct DexRegistry {
+    dexes: Vec<Dex>,
+}
+
+impl DexRegistry {
+    pub fn new() -> DexRegistry {
+        DexRegistry { dexes: vec![] }
+    }
+
+    pub fn mainnet() -> DexRegistry {
+        let mut registry = DexRegistry::new();
+        registry.insert(Dex {
+            name: "uniswap-v2".to_string(),
+            kind: DexKind::V2,
+            routers: vec![address("7a250d5630b4cf539739df2c5dacb4c659f2488d")],
+            factory: address("5c69bee701ef814a2b6a3edd4b1652cb9cc5aa6f"),
+            init_code_hash: hash("96e8ac4277198ff8b6f785478aa9a39f403cb768dd02cbee326c3e7da348845f"),
+            fee_bps: DEFAULT_FEE_BPS,
+        });
+        registry.insert(Dex {
+            name: "uniswap-v3".to_string(),
+            kind: DexKind::V3,
+            routers: vec![address(V3_SWAP_ROUTER), address(V3_SWAP_ROUTER_02)],
+            factory: address(V3_FACTORY),
+            init_code_hash: hash(V3_POOL_INIT_CODE_HASH),
+            fee_bps: 0,
+        });
+        registry
+    }
+
+    /// Add a DEX, replacing any entry with the same name.
+    pub fn insert(&mut self, dex: Dex) {
+        match self.dexes.iter_mut().find(|known| known.name == dex.name) {
+            Some(known) => *known = dex,
+            None => self.dexes.push(dex),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Dex> {
+        self.dexes.iter()
+    }
+
+    /// The DEX a transaction was sent to, if `to` is one of its routers.
+    pub fn by_router(&self, to: &H160) -> Option<&Dex> {
+        self.dexes.iter().find(|dex| dex.routers.contains(to))
+    }
+
+    /// The DEX of `kind` whose factory deployed `pool` for the two sorted
+    /// tokens.
+    pub fn by_pool(&self, kind: DexKind, pool: H160, token0: H160, token1: H160) -> Option<&Dex> {
+        self.dexes.iter()
+            .filter(|dex| dex.kind == kind)
+            .find(|dex| dex.owns_pool(pool, token0, token1))
+    }
+
+    /// Venue name of a pool, `unknown` for pools no registered factory
+    /// deployed.
+    pub fn venue(&self, kind: DexKind, pool: H160, token0: H160, token1: H160) -> &str {
+        self.by_pool(kind, pool, token0, token1)
+            .map_or("unknown", |dex| dex.name.as_str())
+    }
+
+    /// Add the DEXes of a JSON array of `{"name", "kind", "routers",
+    /// "factory", "init_code_hash", "fee_bps"}` objects. An entry named like a
+    /// built-in DEX replaces it. A fee of 100% or more is rejected, and then
+    /// none of the file is added.
+    pub fn load_json<P: AsRef<Path>>(&mut self, path: P) -> Result<(), Box<dyn Error>> {
+        let file = File::open(path)?;
+        let reader = BufReader::new(file);
+
+        let dexes: Vec<Dex> = serde_json::from_reader(reader)?;
+        if let Some(dex) = dexes.iter().find(|dex| dex.fee_bps >= MAX_FEE_BPS) {
+            return Err(format!("dex {} has fee_bps {}, expected less than {}",
+                               dex.name, dex.fee_bps, MAX_FEE_BPS).into());
+        }
+        dexes.into_iter().for_each(|dex| self.insert(dex));
+        Ok(())
+    }
+}
+
+/// Venue of a trade through several pools, e.g. `uniswap-v2+sushiswap`,
+/// naming each venue once in path order.
+pub fn join_venues<'a>(venues: impl IntoIterator<Item = &'a str>) -> String {
+    let mut joined: Vec<&str> = vec![];
+    for venue in venues {
+        if !joined.contains(&venue) {
+            joined.push(venue);
+        }
+    }
+    joined.join("+")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(name: &str, json: &str) -> Result<DexRegistry, Box<dyn Error>> {
+        let path = std::env::temp_dir().join(format!("eth_explo_dexes_{}_{}.json", name, std::process::id()));
+        std::fs::write(&path, json).unwrap();
+        let mut registry = DexRegistry::mainnet();
+        let loaded = registry.load_json(&path);
+        std::fs::remove_file(&path).unwrap();
+        loaded.map(|_| registry)
+    }
+
+    fn fork(name: &str, fee_bps: Option<u32>) -> String {
+        format!(r#"{{"name": "{}", "kind": "v2", "routers": [], "factory": "0x{:040x}",
+                    "init_code_hash": "0x{:064x}"{}}}"#,
+                name, 0xfa, 0x11, fee_bps.map_or(String::new(), |fee| format!(r#", "fee_bps": {}"#, fee)))
+    }
+
+    #[test]
+    fn fees_default_to_uniswap() {
+        let registry = load("default", &format!("[{}, {}]", fork("pancake", Some(25)), fork("plain", None))).unwrap();
+        let fee_of = |name: &str| registry.iter().find(|dex| dex.name == name).map(|dex| dex.fee_bps);
+        assert_eq!(fee_of("pancake"), Some(25));
+        assert_eq!(fee_of("plain"), Some(DEFAULT_FEE_BPS));
+        assert_eq!(fee_of("uniswap-v2"), Some(DEFAULT_FEE_BPS));
+    }
+
+    #[test]
+    fn whole_input_fees_are_rejected() {
+        let error = load("rejected", &format!("[{}, {}]", fork("ok", Some(20)), fork("greedy", Some(10_000))))
+            .unwrap_err();
+        assert!(error.to_string().contains("greedy"));
+        assert!(load("max", &format!("[{}]", fork("steep", Some(9_999)))).is_ok());
+    }
+}
@@ -0,0 +1,100 @@
+use web3::types::{H160, H256};
+
+use std::error::Error;
+use std::fmt;
+
+/// Reasons a transaction or one of its logs could not be decoded.
+///
+/// The block loop counts these per `kind()` and moves on to the next
+/// transaction instead of aborting the whole run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    /// Calldata is shorter than the words the method needs.
+    ShortCalldata { needed: usize, len: usize },
+    /// Method id is not one of the decoded router functions.
+    UnknownMethod(String),
+    /// Receipt has no logs or a failed status.
+    Reverted,
+    /// Transaction has no `from` address.
+    MissingSender,
+    /// Log is missing an indexed topic its event signature requires.
+    MissingTopic { event: H256, index: usize },
+    /// Log data is shorter than the words its event signature requires.
+    ShortLogData { event: H256, needed: usize, len: usize },
+    /// Receipt lacks an event needed to follow a hop of the swap path.
+    MissingEvent { event: &'static str, hop: usize },
+    /// ABI encoded value is malformed, e.g. a dynamic offset out of bounds.
+    InvalidAbi(String),
+}
+
+impl DecodeError {
+    /// Short name of the variant, used to count skipped transactions.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            DecodeError::ShortCalldata { .. } => "short calldata",
+            DecodeError::UnknownMethod(_) => "unknown method",
+            DecodeError::Reverted => "reverted",
+            DecodeError::MissingSender => "missing sender",
+            DecodeError::MissingTopic { .. } => "missing topic",
+            DecodeError::ShortLogData { .. } => "short log data",
+            DecodeError::MissingEvent { .. } => "missing event",
+            DecodeError::InvalidAbi(_) => "invalid abi",
+        }
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::ShortCalldata { needed, len } =>
+                write!(f, "calldata has {} bytes, needed {}", len, needed),
+            DecodeError::UnknownMethod(method) =>
+                write!(f, "unknown method id {}", method),
+            DecodeError::Reverted =>
+                write!(f, "transaction reverted"),
+            DecodeError::MissingSender =>
+                write!(f, "transaction has no sender"),
+            DecodeError::MissingTopic { event, index } =>
+                write!(f, "log {:?} is missing topic {}", event, index),
+            DecodeError::ShortLogData { event, needed, len } =>
+                write!(f, "log {:?} has {} data words, needed {}", event, len, needed),
+            DecodeError::MissingEvent { event, hop } =>
+                write!(f, "no {} event found for hop {} of the path", event, hop),
+            DecodeError::InvalidAbi(msg) =>
+                write!(f, "invalid abi encoding: {}", msg),
+        }
+    }
+}
+
+impl Error for DecodeError {}
+
+/// Reasons a V2 pool cannot quote or make a swap, named after the
+/// `UniswapV2Library` and pair reverts they stand for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AmmError {
+    /// Token is neither token0 nor token1 of the pool.
+    UnknownToken(H160),
+    /// A reserve is empty, or an exact output would take all of one.
+    InsufficientLiquidity,
+    /// Swap would pay out nothing, which the pair reverts.
+    InsufficientOutputAmount,
+    /// Amount or reserve no longer fits in 256 bits.
+    Overflow,
+}
+
+impl fmt::Display for AmmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AmmError::UnknownToken(token) =>
+                write!(f, "token {:?} is not in the pool", token),
+            AmmError::InsufficientLiquidity =>
+                write!(f, "insufficient liquidity"),
+            AmmError::InsufficientOutputAmount =>
+                write!(f, "insufficient output amount"),
+            AmmError::Overflow =>
+                write!(f, "amount overflows 256 bits"),
+        }
+    }
+}
+
+impl Error for AmmError {}
@@ -0,0 +1,404 @@
+use serde::Serialize;
+use web3::types::H160;
+
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::dex::{DexKind, DexRegistry};
+use crate::ledger::Trade;
+use crate::v3::V3Pool;
+use crate::{Amm, Trader};
+
+/// File format of an export.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ExportFormat {
+    /// Comma separated values with a header row.
+    Csv,
+    /// One JSON object per line.
+    Jsonl,
+    /// Snappy compressed Apache Parquet.
+    #[cfg(feature = "columnar")]
+    Parquet,
+    /// Arrow IPC file.
+    #[cfg(feature = "columnar")]
+    Arrow,
+}
+
+impl ExportFormat {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Jsonl => "jsonl",
+            #[cfg(feature = "columnar")]
+            ExportFormat::Parquet => "parquet",
+            #[cfg(feature = "columnar")]
+            ExportFormat::Arrow => "arrow",
+        }
+    }
+
+    /// Whether the tables are written with typed columns by `columnar`
+    /// rather than as serde records.
+    pub fn is_columnar(&self) -> bool {
+        !matches!(self, ExportFormat::Csv | ExportFormat::Jsonl)
+    }
+
+    /// Path of the `table` file in `dir`, e.g. `dir/trades.csv`.
+    pub fn path(&self, dir: &Path, table: &str) -> PathBuf {
+        dir.join(format!("{}.{}", table, self.name()))
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<ExportFormat, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "csv" => Ok(ExportFormat::Csv),
+            "jsonl" | "ndjson" => Ok(ExportFormat::Jsonl),
+            #[cfg(feature = "columnar")]
+            "parquet" => Ok(ExportFormat::Parquet),
+            #[cfg(feature = "columnar")]
+            "arrow" | "ipc" => Ok(ExportFormat::Arrow),
+            #[cfg(not(feature = "columnar"))]
+            "parquet" | "arrow" | "ipc" => Err(format!("{} export needs a build with --features columnar", s)),
+            _ => Err(format!("unknown export format {}, expected csv, jsonl, parquet or arrow", s)),
+        }
+    }
+}
+
+impl fmt::Display for ExportFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+fn hex(address: &H160) -> String {
+    format!("{:?}", address)
+}
+
+fn join_hex(addresses: &[H160]) -> String {
+    addresses.iter().map(hex).collect::<Vec<String>>().join(";")
+}
+
+/// One row of `traders`, the settled profile of an address.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TraderRecord {
+    pub address: String,
+    pub txs: usize,
+    pub trades: usize,
+    pub gas_used: String,
+    // wei, and its burned and tipped parts
+    pub gas_fees: String,
+    pub gas_burned: String,
+    pub gas_tips: String,
+    // weth amounts in wei, signed where they can go negative
+    pub cost: String,
+    pub total_assets: String,
+    pub total_debt: String,
+    pub profit: String,
+    pub realized_pnl: String,
+    pub unrealized_pnl: String,
+    pub profit_ratio: f64,
+    pub roi: f64,
+    pub real_gain: f64,
+    // `venue:trades` pairs sorted by venue
+    pub venues: String,
+    // `token:raw amount` pairs sorted by token
+    pub holdings: String,
+    // MEV labels, sorted
+    pub labels: String,
+}
+
+impl TraderRecord {
+    pub fn new(trader: &Trader) -> TraderRecord {
+        let mut venues = trader.venues.iter()
+            .map(|(venue, count)| format!("{}:{}", venue, count))
+            .collect::<Vec<String>>();
+        venues.sort();
+        let mut holdings = trader.holdings.iter().collect::<Vec<_>>();
+        holdings.sort();
+        TraderRecord {
+            address: hex(&trader.address),
+            txs: trader.cum_txs,
+            trades: trader.trades.len(),
+            gas_used: trader.cum_gas.to_string(),
+            gas_fees: trader.gas_fees.to_string(),
+            gas_burned: trader.gas_burned.to_string(),
+            gas_tips: trader.gas_tips.to_string(),
+            cost: trader.hist_cost.to_string(),
+            total_assets: trader.total_assets.to_string(),
+            total_debt: trader.total_debt.to_string(),
+            profit: trader.profit_raw.to_string(),
+            realized_pnl: trader.realized_pnl.to_string(),
+            unrealized_pnl: trader.unrealized_pnl.to_string(),
+            profit_ratio: trader.profit_percent,
+            roi: trader.roi_percent,
+            real_gain: trader.real_gain_percent,
+            venues: venues.join(";"),
+            holdings: holdings.into_iter()
+                .map(|(token, amount)| format!("{}:{}", hex(token), amount))
+                .collect::<Vec<String>>()
+                .join(";"),
+            labels: trader.labels.iter().map(|label| label.name()).collect::<Vec<&str>>().join(";"),
+        }
+    }
+}
+
+/// One row of `trades`, a booked swap of the ledger.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TradeRecord {
+    pub tx_hash: String,
+    pub block: u64,
+    pub tx_index: u64,
+    pub log_index: usize,
+    pub trader: String,
+    pub venue: String,
+    pub token_in: String,
+    pub token_out: String,
+    // tokens from sold to bought, and the pools in between
+    pub path: String,
+    pub pools: String,
+    pub amount_in: String,
+    pub amount_out: String,
+    pub execution_price: f64,
+    // weth value of amount_in in wei, empty when it could not be priced
+    pub cost: Option<String>,
+    // zero on all but the first trade of a transaction
+    pub gas_used: String,
+    pub effective_gas_price: String,
+    pub gas_fee: String,
+    pub gas_burned: String,
+    pub gas_tip: String,
+}
+
+impl TradeRecord {
+    pub fn new(trade: &Trade) -> TradeRecord {
+        TradeRecord {
+            tx_hash: format!("{:?}", trade.tx_hash),
+            block: trade.block,
+            tx_index: trade.tx_index,
+            log_index: trade.log_index,
+            trader: hex(&trade.trader),
+            venue: trade.venue.clone(),
+            token_in: hex(&trade.token_in()),
+            token_out: hex(&trade.token_out()),
+            path: join_hex(&trade.path),
+            pools: join_hex(&trade.pools),
+            amount_in: trade.amount_in.to_string(),
+            amount_out: trade.amount_out.to_string(),
+            execution_price: trade.execution_price(),
+            cost: trade.cost.map(|cost| cost.to_string()),
+            gas_used: trade.gas.gas_used.to_string(),
+            effective_gas_price: trade.gas.effective_gas_price.to_string(),
+            gas_fee: trade.gas.total().to_string(),
+            gas_burned: trade.gas.base_fee.to_string(),
+            gas_tip: trade.gas.priority_fee.to_string(),
+        }
+    }
+}
+
+/// One row of `pools`, the final state of a V2 pair or V3 pool. Reserves are
+/// only known for V2, price, liquidity and tick only for V3.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PoolRecord {
+    pub pool: String,
+    pub kind: &'static str,
+    pub venue: String,
+    pub token0: String,
+    pub token1: String,
+    // swap fee in hundredths of a bip, e.g. 3000 for 0.3%
+    pub fee: Option<u32>,
+    pub reserve0: Option<String>,
+    pub reserve1: Option<String>,
+    pub sqrt_price_x96: Option<String>,
+    pub liquidity: Option<String>,
+    pub tick: Option<i32>,
+}
+
+impl PoolRecord {
+    pub fn v2(pool: &Amm, dexes: &DexRegistry) -> PoolRecord {
+        let (token0, token1) = pool.tokens();
+        let (reserve0, reserve1) = pool.reserves();
+        let dex = dexes.by_pool(DexKind::V2, pool.address(), token0, token1);
+        PoolRecord {
+            pool: hex(&pool.address()),
+            kind: "v2",
+            venue: dex.map_or("unknown", |dex| dex.name.as_str()).to_string(),
+            token0: hex(&token0),
+            token1: hex(&token1),
+            fee: Some(pool.fee_bps() * 100),
+            reserve0: Some(reserve0.to_string()),
+            reserve1: Some(reserve1.to_string()),
+            sqrt_price_x96: None,
+            liquidity: None,
+            tick: None,
+        }
+    }
+
+    pub fn v3(pool: &V3Pool, dexes: &DexRegistry) -> PoolRecord {
+        PoolRecord {
+            pool: hex(&pool.address),
+            kind: "v3",
+            venue: dexes.venue(DexKind::V3, pool.address, pool.token0, pool.token1).to_string(),
+            token0: hex(&pool.token0),
+            token1: hex(&pool.token1),
+            fee: pool.fee,
+            reserve0: None,
+            reserve1: None,
+            sqrt_price_x96: Some(pool.sqrt_price_x96.to_string()),
+            liquidity: Some(pool.liquidity.to_string()),
+            tick: Some(pool.tick),
+        }
+    }
+}
+
+/// Write `records` to `path` in `format`, replacing the file.
+///
+/// Both formats have one record per line with the same columns. Addresses and
+/// hashes are `0x` prefixed lowercase hex, raw amounts are decimal strings
+/// since they overflow JSON numbers, and lists are joined with `;`. Missing
+/// values are empty in CSV and `null` in JSON Lines.
+pub fn write_records<T: Serialize, P: AsRef<Path>>(path: P, format: ExportFormat, records: &[T])
+-> Result<(), Box<dyn Error>> {
+    if format.is_columnar() {
+        return Err(format!("{} tables are written by columnar::write_batch", format).into());
+    }
+    let file = File::create(path)?;
+    match format {
+        ExportFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(file);
+            for record in records {
+                writer.serialize(record)?;
+            }
+            writer.flush()?;
+        },
+        ExportFormat::Jsonl => {
+            let mut writer = BufWriter::new(file);
+            for record in records {
+                serde_json::to_writer(&mut writer, record)?;
+                writer.write_all(b"\n")?;
+            }
+            writer.flush()?;
+        },
+        #[cfg(feature = "columnar")]
+        ExportFormat::Parquet | ExportFormat::Arrow => unreachable!("checked above"),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use web3::types::{H256, U256};
+
+    use crate::ledger::GasCost;
+
+    const TRADER_COLUMNS: &str = "address,txs,trades,gas_used,gas_fees,gas_burned,gas_tips,cost,\
+        total_assets,total_debt,profit,realized_pnl,unrealized_pnl,profit_ratio,roi,real_gain,venues,\
+        holdings,labels";
+    const TRADE_COLUMNS: &str = "tx_hash,block,tx_index,log_index,trader,venue,token_in,token_out,\
+        path,pools,amount_in,amount_out,execution_price,cost,gas_used,effective_gas_price,gas_fee,\
+        gas_burned,gas_tip";
+    const POOL_COLUMNS: &str = "pool,kind,venue,token0,token1,fee,reserve0,reserve1,sqrt_price_x96,\
+        liquidity,tick";
+
+    fn address(n: u64) -> H160 {
+        H160::from_low_u64_be(n)
+    }
+
+    fn trade() -> Trade {
+        Trade {
+            tx_hash: H256::from_low_u64_be(0xabc),
+            block: 7,
+            tx_index: 2,
+            log_index: 5,
+            trader: address(0xbb),
+            venue: "uniswap-v2".to_string(),
+            path: vec![address(1), address(2)],
+            pools: vec![address(0x99)],
+            amount_in: U256::from(1000),
+            amount_out: U256::from(1990),
+            cost: None,
+            reserves_before: vec![None],
+            reserves_after: vec![None],
+            gas: GasCost::default(),
+        }
+    }
+
+    fn v3_pool() -> V3Pool {
+        let mut pool = V3Pool::new(address(0x99), address(1), address(2), Some(500));
+        pool.initialize(U256::one() << 96, 0);
+        pool
+    }
+
+    // Lines of `records` written as `format`.
+    fn written<T: Serialize>(format: ExportFormat, records: &[T]) -> Vec<String> {
+        let path = std::env::temp_dir().join(format!("eth_explo_export_{}_{:?}.{}",
+                                                     std::process::id(), std::thread::current().id(),
+                                                     format));
+        write_records(&path, format, records).unwrap();
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        written.lines().map(str::to_string).collect()
+    }
+
+    // Keys of a JSON Lines row in the order they were written: the quoted
+    // strings followed by a colon.
+    fn keys(line: &str) -> String {
+        let parts = line.split('"').collect::<Vec<&str>>();
+        parts.windows(2)
+            .skip(1)
+            .step_by(2)
+            .filter(|pair| pair[1].starts_with(':'))
+            .map(|pair| pair[0])
+            .collect::<Vec<&str>>()
+            .join(",")
+    }
+
+    #[test]
+    fn csv_headers() {
+        let lines = written(ExportFormat::Csv, &[TraderRecord::new(&Trader::new())]);
+        assert_eq!(lines[0], TRADER_COLUMNS);
+        let lines = written(ExportFormat::Csv, &[TradeRecord::new(&trade())]);
+        assert_eq!(lines[0], TRADE_COLUMNS);
+        let lines = written(ExportFormat::Csv, &[PoolRecord::v3(&v3_pool(), &DexRegistry::mainnet())]);
+        assert_eq!(lines[0], POOL_COLUMNS);
+    }
+
+    #[test]
+    fn jsonl_columns() {
+        let lines = written(ExportFormat::Jsonl, &[TraderRecord::new(&Trader::new())]);
+        assert_eq!(keys(&lines[0]), TRADER_COLUMNS);
+        let lines = written(ExportFormat::Jsonl, &[TradeRecord::new(&trade())]);
+        assert_eq!(keys(&lines[0]), TRADE_COLUMNS);
+        let lines = written(ExportFormat::Jsonl, &[PoolRecord::v3(&v3_pool(), &DexRegistry::mainnet())]);
+        assert_eq!(keys(&lines[0]), POOL_COLUMNS);
+    }
+
+    #[test]
+    fn v2_fees_are_the_pools_own() {
+        let pool = Amm::new(address(0x99), address(1), address(2), U256::from(10), U256::from(20), 100);
+        let record = PoolRecord::v2(&pool, &DexRegistry::mainnet());
+        assert_eq!(record.fee, Some(10000));
+        assert_eq!(record.reserve1.as_deref(), Some("20"));
+    }
+
+    #[test]
+    fn missing_values() {
+        let lines = written(ExportFormat::Csv, &[TradeRecord::new(&trade())]);
+        let cells = lines[1].split(',').collect::<Vec<&str>>();
+        assert_eq!(cells[10..14], ["1000", "1990", "1.99", ""]);
+        let lines = written(ExportFormat::Jsonl, &[TradeRecord::new(&trade())]);
+        assert!(lines[0].contains(r#""execution_price":1.99,"cost":null,"gas_used":"0""#));
+
+        let pool = PoolRecord::v3(&v3_pool(), &DexRegistry::mainnet());
+        let lines = written(ExportFormat::Csv, std::slice::from_ref(&pool));
+        assert!(lines[1].ends_with(",v3,unknown,0x0000000000000000000000000000000000000001,\
+            0x0000000000000000000000000000000000000002,500,,,79228162514264337593543950336,0,0"));
+        let lines = written(ExportFormat::Jsonl, &[pool]);
+        assert!(lines[0].contains(r#""fee":500,"reserve0":null,"reserve1":null,"#));
+    }
+}
@@ -0,0 +1,423 @@
+use futures::stream::{self, StreamExt};
+use serde_json::Value;
+use web3::types::{H160, H256};
+use web3::BatchTransport;
+
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Longest wait between two attempts of a batch, however often it failed.
+pub const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// Settings for downloading a block range into the local layout read by
+/// `read_blocks` and `read_receipt`.
+#[derive(Debug, Clone)]
+pub struct FetchConfig {
+    pub start_block: u64,
+    // exclusive, same as the analysis range
+    pub end_block: u64,
+    pub blocks_dir: PathBuf,
+    pub receipts_dir: PathBuf,
+    // requests per JSON-RPC batch
+    pub batch_size: usize,
+    // batches in flight at once
+    pub concurrency: usize,
+    pub max_retries: u32,
+    // doubled after every failed attempt, up to MAX_RETRY_DELAY
+    pub retry_delay: Duration,
+    // only save receipts of transactions sent to these addresses, all when empty
+    pub only_to: Vec<H160>,
+    pub print_progress: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FetchStats {
+    pub blocks_fetched: usize,
+    pub blocks_skipped: usize,
+    pub receipts_fetched: usize,
+    pub receipts_skipped: usize,
+    pub retries: usize,
+}
+
+impl FetchStats {
+    fn merge(&mut self, other: FetchStats) {
+        self.blocks_fetched += other.blocks_fetched;
+        self.blocks_skipped += other.blocks_skipped;
+        self.receipts_fetched += other.receipts_fetched;
+        self.receipts_skipped += other.receipts_skipped;
+        self.retries += other.retries;
+    }
+}
+
+pub fn block_path(blocks_dir: &Path, number: u64) -> PathBuf {
+    blocks_dir.join(format!("{}.json", number))
+}
+
+pub fn receipt_path(receipts_dir: &Path, block_number: u64, tx_hash: &H256) -> PathBuf {
+    receipts_dir.join(format!("{}_{:?}.json", block_number, tx_hash))
+}
+
+/// Download every block in the range with full transactions, plus the
+/// receipts of the selected transactions.
+///
+/// A block file is only written once all of its receipts are on disk, so a run
+/// that is stopped half way picks up where it left off: blocks with a file are
+/// skipped and receipts that already exist are not requested again.
+pub async fn fetch_range<T: BatchTransport>(transport: &T, config: &FetchConfig)
+-> Result<FetchStats, Box<dyn Error>> {
+    fs::create_dir_all(&config.blocks_dir)?;
+    fs::create_dir_all(&config.receipts_dir)?;
+
+    let numbers = (config.start_block..config.end_block).collect::<Vec<u64>>();
+    let chunks = numbers.chunks(config.batch_size.max(1))
+        .map(|chunk| chunk.to_vec())
+        .collect::<Vec<Vec<u64>>>();
+    let n_chunks = chunks.len();
+
+    let mut results = stream::iter(chunks)
+        .map(|chunk| fetch_chunk(transport, config, chunk))
+        .buffer_unordered(config.concurrency.max(1));
+
+    let mut stats = FetchStats::default();
+    let mut done = 0;
+    while let Some(chunk_stats) = results.next().await {
+        stats.merge(chunk_stats?);
+        done += 1;
+        if config.print_progress {
+            println!("fetched batch {} of {}: {:?}", done, n_chunks, stats);
+        }
+    }
+    Ok(stats)
+}
+
+async fn fetch_chunk<T: BatchTransport>(transport: &T, config: &FetchConfig, chunk: Vec<u64>)
+-> Result<FetchStats, Box<dyn Error>> {
+    let mut stats = FetchStats::default();
+
+    let missing = chunk.into_iter()
+        .filter(|number| {
+            let exists = block_path(&config.blocks_dir, *number).exists();
+            if exists { stats.blocks_skipped += 1; }
+            !exists
+        })
+        .collect::<Vec<u64>>();
+    if missing.is_empty() {
+        return Ok(stats);
+    }
+
+    let block_calls = missing.iter()
+        .map(|number| ("eth_getBlockByNumber",
+                       vec![Value::String(format!("0x{:x}", number)), Value::Bool(true)]))
+        .collect::<Vec<(&str, Vec<Value>)>>();
+    let blocks = send_with_retry(transport, config, &block_calls, &mut stats).await?;
+
+    // (block number, tx hash) of every receipt still missing on disk
+    let mut wanted: Vec<(u64, H256)> = vec![];
+    for (number, block) in missing.iter().zip(blocks.iter()) {
+        if block.is_null() {
+            return Err(format!("block {} not found, is it past the chain head?", number).into());
+        }
+        let txs = block["transactions"].as_array()
+            .ok_or_else(|| format!("block {} has no transaction list", number))?;
+        for tx in txs {
+            let to = match &tx["to"] {
+                Value::Null => None,
+                to => Some(serde_json::from_value::<H160>(to.clone())?),
+            };
+            if !config.only_to.is_empty() && !to.is_some_and(|to| config.only_to.contains(&to)) {
+                continue;
+            }
+            let hash = serde_json::from_value::<H256>(tx["hash"].clone())?;
+            match receipt_path(&config.receipts_dir, *number, &hash).exists() {
+                true => stats.receipts_skipped += 1,
+                false => wanted.push((*number, hash)),
+            }
+        }
+    }
+
+    for batch in wanted.chunks(config.batch_size.max(1)) {
+        let receipt_calls = batch.iter()
+            .map(|(_, hash)| ("eth_getTransactionReceipt",
+                              vec![Value::String(format!("{:?}", hash))]))
+            .collect::<Vec<(&str, Vec<Value>)>>();
+        let receipts = send_with_retry(transport, config, &receipt_calls, &mut stats).await?;
+        for ((number, hash), receipt) in batch.iter().zip(receipts.iter()) {
+            if receipt.is_null() {
+                return Err(format!("receipt for {:?} in block {} not found", hash, number).into());
+            }
+            write_json(&receipt_path(&config.receipts_dir, *number, hash), receipt)?;
+            stats.receipts_fetched += 1;
+        }
+    }
+
+    // Blocks go last so a block file on disk means its receipts are complete.
+    for (number, block) in missing.iter().zip(blocks.iter()) {
+        write_json(&block_path(&config.blocks_dir, *number), block)?;
+        stats.blocks_fetched += 1;
+    }
+    Ok(stats)
+}
+
+// Send the calls as one batch, re-sending only the calls that failed until
+// they all succeed or the retries run out. Results come back in call order.
+async fn send_with_retry<T: BatchTransport>(transport: &T, config: &FetchConfig,
+                                            calls: &[(&str, Vec<Value>)],
+                                            stats: &mut FetchStats)
+-> Result<Vec<Value>, Box<dyn Error>> {
+    let mut results: Vec<Option<Value>> = vec![None; calls.len()];
+    let mut delay = config.retry_delay;
+    let mut attempt = 0;
+    loop {
+        let pending = results.iter()
+            .enumerate()
+            .filter(|(_, result)| result.is_none())
+            .map(|(idx, _)| idx)
+            .collect::<Vec<usize>>();
+        let requests = pending.iter()
+            .map(|idx| transport.prepare(calls[*idx].0, calls[*idx].1.clone()))
+            .collect::<Vec<_>>();
+
+        let mut last_error = None;
+        match transport.send_batch(requests).await {
+            Ok(responses) if responses.len() == pending.len() => {
+                for (idx, response) in pending.into_iter().zip(responses) {
+                    match response {
+                        Ok(value) => results[idx] = Some(value),
+                        Err(e) => last_error = Some(e.to_string()),
+                    }
+                }
+            },
+            Ok(responses) => last_error = Some(format!(
+                "batch of {} requests got {} responses", pending.len(), responses.len())),
+            Err(e) => last_error = Some(e.to_string()),
+        }
+
+        match last_error {
+            None => return Ok(results.into_iter().flatten().collect()),
+            Some(e) if attempt >= config.max_retries => {
+                return Err(format!("giving up after {} retries: {}", attempt, e).into());
+            },
+            Some(e) => {
+                if config.print_progress {
+                    println!("retrying in {:?}: {}", delay, e);
+                }
+                stats.retries += 1;
+                attempt += 1;
+                tokio::time::sleep(delay).await;
+                delay = (delay * 2).min(MAX_RETRY_DELAY);
+            },
+        }
+    }
+}
+
+// Write through a temporary file so an interrupted run never leaves a half
+// written json file behind for `read_blocks` or `read_receipt` to trip over.
+fn write_json(path: &Path, value: &Value) -> Result<(), Box<dyn Error>> {
+    let tmp_path = path.with_extension("json.tmp");
+    {
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        serde_json::to_writer(&mut writer, value)?;
+        writer.flush()?;
+    }
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::io::{BufRead, BufReader, Read};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    // methods of every batch the stub was sent, in arrival order
+    type Batches = Arc<Mutex<Vec<Vec<String>>>>;
+
+    fn tx_hash(block: u64, index: u64) -> H256 {
+        H256::from_low_u64_be(block * 100 + index)
+    }
+
+    // Two transactions per block, the second one a contract creation in odd
+    // blocks.
+    fn block(number: u64) -> Value {
+        let txs = (0..2)
+            .map(|index| json!({
+                "hash": format!("{:?}", tx_hash(number, index)),
+                "to": match index == 1 && number % 2 == 1 {
+                    true => Value::Null,
+                    false => json!(format!("{:?}", H160::from_low_u64_be(0xabc))),
+                },
+            }))
+            .collect::<Vec<Value>>();
+        json!({ "number": format!("0x{:x}", number), "transactions": txs })
+    }
+
+    // Answer one call. The first receipt request for the flaky hash fails.
+    fn answer(call: &Value, flaky: &Mutex<Option<H256>>) -> Value {
+        let param = call["params"][0].as_str().unwrap();
+        let result = match call["method"].as_str().unwrap() {
+            "eth_getBlockByNumber" => block(u64::from_str_radix(param.trim_start_matches("0x"), 16).unwrap()),
+            "eth_getTransactionReceipt" => {
+                let hash = param.parse::<H256>().unwrap();
+                let mut flaky = flaky.lock().unwrap();
+                if *flaky == Some(hash) {
+                    *flaky = None;
+                    return json!({
+                        "jsonrpc": "2.0",
+                        "id": call["id"],
+                        "error": { "code": -32005, "message": "limit exceeded" },
+                    });
+                }
+                json!({ "transactionHash": param, "status": "0x1" })
+            },
+            method => panic!("unexpected method {}", method),
+        };
+        json!({ "jsonrpc": "2.0", "id": call["id"], "result": result })
+    }
+
+    // Minimal HTTP/1.1 JSON-RPC server for one keep-alive connection.
+    fn serve(stream: TcpStream, batches: Batches, flaky: Arc<Mutex<Option<H256>>>) {
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut stream = stream;
+        loop {
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap_or(0) == 0 {
+                    return;
+                }
+                let line = line.trim_end();
+                if line.is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = line.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        content_length = value.trim().parse().unwrap();
+                    }
+                }
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+            let calls: Vec<Value> = serde_json::from_slice(&body).unwrap();
+            batches.lock().unwrap()
+                .push(calls.iter().map(|call| call["method"].as_str().unwrap().to_string()).collect());
+            let outputs = calls.iter().map(|call| answer(call, &flaky)).collect::<Vec<Value>>();
+            let response = serde_json::to_vec(&outputs).unwrap();
+            write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n",
+                   response.len()).unwrap();
+            stream.write_all(&response).unwrap();
+        }
+    }
+
+    fn start_stub(flaky: Option<H256>) -> (String, Batches) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let batches = Batches::default();
+        let flaky = Arc::new(Mutex::new(flaky));
+        let served = batches.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let (batches, flaky) = (served.clone(), flaky.clone());
+                thread::spawn(move || serve(stream, batches, flaky));
+            }
+        });
+        (url, batches)
+    }
+
+    fn sizes(batches: &Batches) -> Vec<(String, usize)> {
+        batches.lock().unwrap().iter()
+            .map(|batch| (batch[0].clone(), batch.len()))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn fetches_in_batches_retries_and_resumes() {
+        let (url, batches) = start_stub(Some(tx_hash(11, 0)));
+        let transport = web3::transports::Http::new(&url).unwrap();
+        let dir = std::env::temp_dir().join(format!("eth_explo_fetch_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let config = FetchConfig {
+            start_block: 10,
+            end_block: 13,
+            blocks_dir: dir.join("blocks"),
+            receipts_dir: dir.join("receipts"),
+            batch_size: 2,
+            concurrency: 1,
+            max_retries: 3,
+            retry_delay: Duration::from_millis(1),
+            only_to: vec![],
+            print_progress: false,
+        };
+
+        let stats = fetch_range(&transport, &config).await.unwrap();
+        assert_eq!(stats, FetchStats {
+            blocks_fetched: 3,
+            blocks_skipped: 0,
+            receipts_fetched: 6,
+            receipts_skipped: 0,
+            retries: 1,
+        });
+        // blocks and receipts in batches of two, and only the failed call re-sent
+        let (get_block, get_receipt) = ("eth_getBlockByNumber".to_string(),
+                                        "eth_getTransactionReceipt".to_string());
+        assert_eq!(sizes(&batches), vec![
+            (get_block.clone(), 2), (get_receipt.clone(), 2), (get_receipt.clone(), 2), (get_receipt.clone(), 1),
+            (get_block.clone(), 1), (get_receipt, 2),
+        ]);
+        for number in 10..13 {
+            let saved: Value = serde_json::from_reader(
+                File::open(dir.join("blocks").join(format!("{}.json", number))).unwrap()).unwrap();
+            assert_eq!(saved, block(number));
+            for index in 0..2 {
+                let name = format!("{}_0x{:064x}.json", number, number * 100 + index);
+                assert!(dir.join("receipts").join(name).exists());
+            }
+        }
+        assert_eq!(fs::read_dir(dir.join("blocks")).unwrap().count(), 3);
+        assert_eq!(fs::read_dir(dir.join("receipts")).unwrap().count(), 6);
+
+        // a second run only fetches the block whose file is gone, not its receipts
+        fs::remove_file(block_path(&config.blocks_dir, 12)).unwrap();
+        batches.lock().unwrap().clear();
+        let stats = fetch_range(&transport, &config).await.unwrap();
+        assert_eq!(stats, FetchStats {
+            blocks_fetched: 1,
+            blocks_skipped: 2,
+            receipts_fetched: 0,
+            receipts_skipped: 2,
+            retries: 0,
+        });
+        assert_eq!(sizes(&batches), vec![(get_block, 1)]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn only_to_skips_other_receipts() {
+        let (url, _) = start_stub(None);
+        let transport = web3::transports::Http::new(&url).unwrap();
+        let dir = std::env::temp_dir().join(format!("eth_explo_fetch_only_to_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let config = FetchConfig {
+            start_block: 11,
+            end_block: 12,
+            blocks_dir: dir.join("blocks"),
+            receipts_dir: dir.join("receipts"),
+            batch_size: 10,
+            concurrency: 1,
+            max_retries: 0,
+            retry_delay: Duration::from_millis(1),
+            only_to: vec![H160::from_low_u64_be(0xabc)],
+            print_progress: false,
+        };
+        let stats = fetch_range(&transport, &config).await.unwrap();
+        assert_eq!((stats.blocks_fetched, stats.receipts_fetched), (1, 1));
+        assert!(receipt_path(&config.receipts_dir, 11, &tx_hash(11, 0)).exists());
+        assert!(!receipt_path(&config.receipts_dir, 11, &tx_hash(11, 1)).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
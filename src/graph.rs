@@ -0,0 +1,288 @@
+use web3::types::{H160, U256};
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::pools::PoolRegistry;
+use crate::v3::{V3Book, V3Pool};
+use crate::Amm;
+
+/// Longest route `PoolGraph::quote` searches, in pools.
+pub const MAX_HOPS: usize = 3;
+
+/// A pool as an edge between its two tokens.
+#[derive(Debug, Clone, Copy)]
+pub enum PoolEdge<'a> {
+    V2(&'a Amm),
+    V3(&'a V3Pool),
+}
+
+impl PoolEdge<'_> {
+    pub fn address(&self) -> H160 {
+        match self {
+            PoolEdge::V2(pool) => pool.address(),
+            PoolEdge::V3(pool) => pool.address,
+        }
+    }
+
+    pub fn tokens(&self) -> (H160, H160) {
+        match self {
+            PoolEdge::V2(pool) => pool.tokens(),
+            PoolEdge::V3(pool) => pool.tokens(),
+        }
+    }
+
+    /// The token across the pool from `token`.
+    pub fn other(&self, token: H160) -> H160 {
+        let (token0, token1) = self.tokens();
+        match token == token0 {
+            true => token1,
+            false => token0,
+        }
+    }
+
+    /// Output of selling `amount_in` of `token_in` into the pool, None for a
+    /// token it does not hold or a V3 pool that cannot be quoted yet.
+    pub fn quote(&self, token_in: H160, amount_in: U256) -> Option<U256> {
+        match self {
+            PoolEdge::V2(pool) => pool.uniswap_immut(token_in, amount_in).ok(),
+            PoolEdge::V3(pool) => pool.quote(token_in, amount_in),
+        }
+    }
+}
+
+/// A path through the pools and what it pays out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Route {
+    // tokens from sold to bought, one more than the pools
+    pub path: Vec<H160>,
+    pub pools: Vec<H160>,
+    pub amount_in: U256,
+    pub amount_out: U256,
+}
+
+impl Route {
+    fn extend(&self, edge: &PoolEdge, token: H160, amount_out: U256) -> Route {
+        let mut route = self.clone();
+        route.path.push(token);
+        route.pools.push(edge.address());
+        route.amount_out = amount_out;
+        route
+    }
+}
+
+/// Every known V2 and V3 pool as an edge between its tokens, to quote
+/// tokens that have no pool with each other through the ones in between.
+///
+/// Borrows the pools as they are, so it has to be built again after they
+/// change. The V2 pools are already indexed by token, building the graph only
+/// indexes the V3 ones.
+#[derive(Debug, Clone)]
+pub struct PoolGraph<'a> {
+    v2: &'a PoolRegistry,
+    // v3 pools of every token, by address
+    v3: HashMap<H160, Vec<&'a V3Pool>>,
+}
+
+impl<'a> PoolGraph<'a> {
+    pub fn new(v2: &'a PoolRegistry, v3_book: &'a V3Book) -> PoolGraph<'a> {
+        let mut v3: HashMap<H160, Vec<&V3Pool>> = HashMap::new();
+        for pool in v3_book.pools.values() {
+            v3.entry(pool.token0).or_default().push(pool);
+            v3.entry(pool.token1).or_default().push(pool);
+        }
+        for pools in v3.values_mut() {
+            pools.sort_by_key(|pool| pool.address);
+        }
+        PoolGraph { v2, v3 }
+    }
+
+    /// Every pool of `token`, V2 before V3.
+    pub fn edges_of(&self, token: H160) -> impl Iterator<Item = PoolEdge<'a>> + '_ {
+        self.v2.pools_of(token)
+            .map(PoolEdge::V2)
+            .chain(self.v3.get(&token).into_iter().flatten().map(|pool| PoolEdge::V3(pool)))
+    }
+
+    /// The route of at most `max_hops` pools that pays out the most
+    /// `token_out` for `amount_in` of `token_in`, never passing a token
+    /// twice. Each pool is quoted on its current state, so the route is
+    /// searched for the amount rather than the marginal price. None when no
+    /// route pays out anything.
+    pub fn best_route(&self, token_in: H160, token_out: H160, amount_in: U256, max_hops: usize)
+    -> Option<Route> {
+        let start = Route {
+            path: vec![token_in],
+            pools: vec![],
+            amount_in,
+            amount_out: amount_in,
+        };
+        if token_in == token_out {
+            return Some(start);
+        }
+        // best route found into every token so far, at most one hop longer
+        // each round; a better amount into a token can only lead to a better
+        // amount out of it
+        let mut frontier = BTreeMap::from([(token_in, start)]);
+        let mut best: Option<Route> = None;
+        for _ in 0..max_hops {
+            let mut next: BTreeMap<H160, Route> = BTreeMap::new();
+            for (token, route) in &frontier {
+                for edge in self.edges_of(*token) {
+                    let other = edge.other(*token);
+                    if route.path.contains(&other) {
+                        continue;
+                    }
+                    let amount_out = match edge.quote(*token, route.amount_out) {
+                        Some(amount_out) if !amount_out.is_zero() => amount_out,
+                        _ => continue,
+                    };
+                    let found = match other == token_out {
+                        true => best.as_ref(),
+                        false => next.get(&other),
+                    };
+                    if found.is_some_and(|found| found.amount_out >= amount_out) {
+                        continue;
+                    }
+                    let extended = route.extend(&edge, other, amount_out);
+                    match other == token_out {
+                        true => best = Some(extended),
+                        false => {
+                            next.insert(other, extended);
+                        },
+                    }
+                }
+            }
+            frontier = next;
+        }
+        best
+    }
+
+    /// `token_out` the best route of up to `MAX_HOPS` pools pays out for
+    /// `amount_in` of `token_in`.
+    pub fn quote(&self, token_in: H160, token_out: H160, amount_in: U256) -> Option<U256> {
+        Some(self.best_route(token_in, token_out, amount_in, MAX_HOPS)?.amount_out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dex::{Dex, DexKind, DexRegistry};
+    use web3::types::H256;
+
+    const WETH: u64 = 1;
+    const DAI: u64 = 2;
+    const TOKEN: u64 = 3;
+    const X: u64 = 4;
+    const Y: u64 = 5;
+
+    fn token(n: u64) -> H160 {
+        H160::from_low_u64_be(n)
+    }
+
+    fn dex() -> Dex {
+        Dex {
+            name: "test-v2".to_string(),
+            kind: DexKind::V2,
+            routers: vec![],
+            factory: token(0xfa),
+            init_code_hash: H256::repeat_byte(0x11),
+            fee_bps: 30,
+        }
+    }
+
+    // V2 pools of (token, token, reserve, reserve), admitted through the test
+    // factory's pair addresses.
+    fn registry(pools: &[(u64, u64, u64, u64)]) -> PoolRegistry {
+        let mut dexes = DexRegistry::new();
+        dexes.insert(dex());
+        let mut registry = PoolRegistry::new(dexes);
+        for &(a, b, reserve_a, reserve_b) in pools {
+            let ((token0, reserve0), (token1, reserve1)) = match a < b {
+                true => ((token(a), reserve_a), (token(b), reserve_b)),
+                false => ((token(b), reserve_b), (token(a), reserve_a)),
+            };
+            let pair = dex().pair_address(token0, token1);
+            assert!(registry.update(pair, (token0, token1), (U256::from(reserve0), U256::from(reserve1))));
+        }
+        registry
+    }
+
+    #[test]
+    fn deeper_route_beats_shallow_direct_pool() {
+        let pools = registry(&[
+            (TOKEN, WETH, 1_000, 1_000),
+            (TOKEN, DAI, 1_000_000_000, 1_000_000_000),
+            (DAI, WETH, 1_000_000_000, 1_000_000_000),
+        ]);
+        let v3 = V3Book::new();
+        let graph = PoolGraph::new(&pools, &v3);
+        let amount = U256::from(1_000);
+
+        let route = graph.best_route(token(TOKEN), token(WETH), amount, MAX_HOPS).unwrap();
+        assert_eq!(route.path, vec![token(TOKEN), token(DAI), token(WETH)]);
+        assert_eq!(route.pools.len(), 2);
+        assert_eq!(Some(route.amount_out), pools.quote_route(&route.path, amount));
+        assert!(route.amount_out > pools.quote(token(TOKEN), token(WETH), amount).unwrap());
+        assert_eq!(graph.quote(token(TOKEN), token(WETH), amount), Some(route.amount_out));
+    }
+
+    #[test]
+    fn direct_pool_wins_at_equal_depth() {
+        // one fee instead of two
+        let pools = registry(&[
+            (TOKEN, WETH, 1_000_000_000, 1_000_000_000),
+            (TOKEN, DAI, 1_000_000_000, 1_000_000_000),
+            (DAI, WETH, 1_000_000_000, 1_000_000_000),
+        ]);
+        let v3 = V3Book::new();
+        let graph = PoolGraph::new(&pools, &v3);
+        let amount = U256::from(1_000);
+
+        let route = graph.best_route(token(TOKEN), token(WETH), amount, MAX_HOPS).unwrap();
+        assert_eq!(route.path, vec![token(TOKEN), token(WETH)]);
+        assert_eq!(Some(route.amount_out), pools.quote(token(TOKEN), token(WETH), amount));
+    }
+
+    #[test]
+    fn hops_are_limited() {
+        let pools = registry(&[
+            (TOKEN, X, 1_000_000, 1_000_000),
+            (X, Y, 1_000_000, 1_000_000),
+            (Y, WETH, 1_000_000, 1_000_000),
+        ]);
+        let v3 = V3Book::new();
+        let graph = PoolGraph::new(&pools, &v3);
+        let amount = U256::from(1_000);
+
+        assert_eq!(graph.best_route(token(TOKEN), token(WETH), amount, 2), None);
+        let route = graph.best_route(token(TOKEN), token(WETH), amount, 3).unwrap();
+        assert_eq!(route.path, vec![token(TOKEN), token(X), token(Y), token(WETH)]);
+        assert_eq!(graph.quote(token(TOKEN), token(WETH), amount), Some(route.amount_out));
+
+        // nothing reaches DAI, and a token routes to itself as is
+        assert_eq!(graph.quote(token(TOKEN), token(DAI), amount), None);
+        let same = graph.best_route(token(TOKEN), token(TOKEN), amount, MAX_HOPS).unwrap();
+        assert!(same.pools.is_empty());
+        assert_eq!(same.amount_out, amount);
+    }
+
+    #[test]
+    fn routes_through_v3_pools() {
+        let pools = registry(&[(TOKEN, DAI, 1_000_000_000, 1_000_000_000)]);
+        // DAI/WETH at a price of one with full range liquidity
+        let mut v3_pool = V3Pool::new(token(0xb3), token(WETH), token(DAI), Some(3000));
+        v3_pool.initialize(U256::one() << 96, 0);
+        v3_pool.update_position(-887220, 887220, 1_000_000_000_000);
+        let mut v3 = V3Book::new();
+        v3.pools.insert(v3_pool.address, v3_pool.clone());
+        let graph = PoolGraph::new(&pools, &v3);
+        let amount = U256::from(1_000_000);
+
+        let route = graph.best_route(token(TOKEN), token(WETH), amount, MAX_HOPS).unwrap();
+        assert_eq!(route.pools, vec![pools.iter().next().unwrap().address(), token(0xb3)]);
+        let dai = pools.quote(token(TOKEN), token(DAI), amount).unwrap();
+        assert_eq!(Some(route.amount_out), v3_pool.quote(token(DAI), dai));
+        assert!(!route.amount_out.is_zero());
+    }
+}
@@ -0,0 +1,188 @@
+use web3::types::{Transaction, TransactionReceipt, H160, H256, U256};
+
+use crate::amount::u256_to_f64;
+use crate::UniswapTx;
+
+/// What a transaction paid for gas, in wei.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct GasCost {
+    // gas units used
+    pub gas_used: U256,
+    // wei paid per gas unit
+    pub effective_gas_price: U256,
+    // base fee of the block times the gas used, burned since EIP-1559
+    pub base_fee: U256,
+    // the rest of the fee, tipped to the block producer
+    pub priority_fee: U256,
+}
+
+impl GasCost {
+    /// Split `gas_used` at `effective_gas_price` into the burned base fee and
+    /// the tip. Blocks before London have no base fee, so all of it is tip.
+    pub fn new(gas_used: U256, effective_gas_price: U256, base_fee_per_gas: Option<U256>) -> GasCost {
+        let base_fee_per_gas = base_fee_per_gas.unwrap_or_default().min(effective_gas_price);
+        GasCost {
+            gas_used,
+            effective_gas_price,
+            base_fee: gas_used * base_fee_per_gas,
+            priority_fee: gas_used * (effective_gas_price - base_fee_per_gas),
+        }
+    }
+
+    /// Gas paid by `tx` in a block with `base_fee_per_gas`. The price comes
+    /// from the receipt's `effectiveGasPrice`, or for nodes that leave it out,
+    /// from the fee caps of a type 2 transaction or the legacy gas price.
+    pub fn of(tx: &Transaction, receipt: &TransactionReceipt, base_fee_per_gas: Option<U256>) -> GasCost {
+        let price = receipt.effective_gas_price
+            .or_else(|| match (tx.max_fee_per_gas, tx.max_priority_fee_per_gas, base_fee_per_gas) {
+                (Some(max_fee), Some(max_priority_fee), Some(base_fee)) =>
+                    Some(max_fee.min(base_fee + max_priority_fee)),
+                _ => tx.gas_price,
+            })
+            .unwrap_or_default();
+        GasCost::new(receipt.gas_used.unwrap_or_default(), price, base_fee_per_gas)
+    }
+
+    /// Wei paid in total, burned plus tipped.
+    pub fn total(&self) -> U256 {
+        self.base_fee + self.priority_fee
+    }
+}
+
+/// The transaction a trade was made in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TxContext {
+    pub hash: H256,
+    pub block: u64,
+    pub tx_index: u64,
+    pub from: H160,
+    pub gas: GasCost,
+}
+
+/// One decoded swap as booked on its trader.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trade {
+    pub tx_hash: H256,
+    pub block: u64,
+    pub tx_index: u64,
+    // position of the first hop's Swap event in the receipt logs
+    pub log_index: usize,
+    pub trader: H160,
+    pub venue: String,
+    // tokens from what was sold to what was bought, one more than the pools
+    pub path: Vec<H160>,
+    pub pools: Vec<H160>,
+    pub amount_in: U256,
+    pub amount_out: U256,
+    // weth value of amount_in at the pool state before the transaction, None
+    // when the sold token has no weth value
+    pub cost: Option<U256>,
+    // (reserve0, reserve1) of each pool around its hop, None for v3 pools
+    pub reserves_before: Vec<Option<(U256, U256)>>,
+    pub reserves_after: Vec<Option<(U256, U256)>>,
+    // gas of the transaction, booked on its first trade only so the ledger
+    // sums to what the trader paid
+    pub gas: GasCost,
+}
+
+impl Trade {
+    /// Book a decoded swap made in `tx`. `gas` is the gas charged to this
+    /// trade, zero for all but the first trade of a transaction.
+    pub fn new(tx: &TxContext, extracted_uniswap: &UniswapTx, cost: Option<U256>, gas: GasCost) -> Trade {
+        let (_, amount_in, _, amount_out, _, pool_ratios, venue) = extracted_uniswap;
+        let mut path = pool_ratios.iter()
+            .take(1)
+            .map(|hop| hop.token_in)
+            .collect::<Vec<H160>>();
+        path.extend(pool_ratios.iter().map(|hop| hop.token_out));
+        Trade {
+            tx_hash: tx.hash,
+            block: tx.block,
+            tx_index: tx.tx_index,
+            log_index: pool_ratios.first().map_or(0, |hop| hop.log_index),
+            trader: tx.from,
+            venue: venue.clone(),
+            path,
+            pools: pool_ratios.iter().map(|hop| hop.pool).collect(),
+            amount_in: *amount_in,
+            amount_out: *amount_out,
+            cost,
+            reserves_before: pool_ratios.iter().map(|hop| hop.reserves_before).collect(),
+            reserves_after: pool_ratios.iter().map(|hop| hop.reserves_after).collect(),
+            gas,
+        }
+    }
+
+    pub fn token_in(&self) -> H160 {
+        self.path[0]
+    }
+
+    pub fn token_out(&self) -> H160 {
+        self.path[self.path.len() - 1]
+    }
+
+    /// Raw units of the bought token received per raw unit sold.
+    pub fn execution_price(&self) -> f64 {
+        u256_to_f64(self.amount_out) / u256_to_f64(self.amount_in)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn receipt(gas_used: u64, effective_gas_price: Option<u64>) -> TransactionReceipt {
+        TransactionReceipt {
+            gas_used: Some(U256::from(gas_used)),
+            effective_gas_price: effective_gas_price.map(U256::from),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn london_receipts_burn_the_base_fee() {
+        let gas = GasCost::of(&Transaction::default(), &receipt(100_000, Some(32)), Some(U256::from(30)));
+        assert_eq!(gas, GasCost {
+            gas_used: U256::from(100_000),
+            effective_gas_price: U256::from(32),
+            base_fee: U256::from(3_000_000),
+            priority_fee: U256::from(200_000),
+        });
+        assert_eq!(gas.total(), U256::from(3_200_000));
+    }
+
+    #[test]
+    fn blocks_before_london_only_tip() {
+        let gas = GasCost::of(&Transaction::default(), &receipt(100_000, Some(20)), None);
+        assert_eq!((gas.base_fee, gas.priority_fee), (U256::zero(), U256::from(2_000_000)));
+    }
+
+    #[test]
+    fn receipts_without_a_price_fall_back_on_the_transaction() {
+        let legacy = Transaction { gas_price: Some(U256::from(25)), ..Default::default() };
+        let gas = GasCost::of(&legacy, &receipt(1_000, None), Some(U256::from(20)));
+        assert_eq!(gas.effective_gas_price, U256::from(25));
+        assert_eq!((gas.base_fee, gas.priority_fee), (U256::from(20_000), U256::from(5_000)));
+
+        // a type 2 transaction pays the base fee plus its tip, up to its cap
+        let mut dynamic = Transaction {
+            gas_price: Some(U256::from(40)),
+            max_fee_per_gas: Some(U256::from(40)),
+            max_priority_fee_per_gas: Some(U256::from(3)),
+            ..Default::default()
+        };
+        assert_eq!(GasCost::of(&dynamic, &receipt(1_000, None), Some(U256::from(30))).effective_gas_price,
+                   U256::from(33));
+        dynamic.max_fee_per_gas = Some(U256::from(31));
+        assert_eq!(GasCost::of(&dynamic, &receipt(1_000, None), Some(U256::from(30))).effective_gas_price,
+                   U256::from(31));
+        // without a base fee the caps say nothing, and the gas price is used
+        assert_eq!(GasCost::of(&dynamic, &receipt(1_000, None), None).effective_gas_price, U256::from(40));
+    }
+
+    #[test]
+    fn tips_never_go_negative() {
+        let gas = GasCost::new(U256::from(10), U256::from(5), Some(U256::from(7)));
+        assert_eq!((gas.base_fee, gas.priority_fee), (U256::from(50), U256::zero()));
+    }
+}
@@ -1,3 +1,44 @@
+pub mod amount;
+#[cfg(feature = "columnar")]
+pub mod columnar;
+pub mod cost_basis;
+pub mod dex;
+pub mod error;
+pub mod export;
+pub mod fetch;
+pub mod graph;
+pub mod ledger;
+pub mod liquidity;
+pub mod mev;
+pub mod pipeline;
+pub mod pools;
+pub mod router;
+pub mod store;
+pub mod swaps;
+#[cfg(test)]
+mod test_logs;
+pub mod tokens;
+pub mod v3;
+pub mod v3_math;
+
+pub use cost_basis::{CostBasis, CostMethod, Disposal, Lot, PositionPnl};
+pub use dex::{Dex, DexKind, DexRegistry, DEFAULT_FEE_BPS};
+pub use error::{AmmError, DecodeError};
+pub use export::{write_records, ExportFormat, PoolRecord, TradeRecord, TraderRecord};
+pub use amount::{I256, u256_to_f64, format_units};
+pub use graph::{PoolEdge, PoolGraph, Route, MAX_HOPS};
+pub use ledger::{GasCost, Trade, TxContext};
+pub use mev::{find_arbitrages, find_sandwiches, Arbitrage, Sandwich, SandwichVictim, TraderLabel};
+pub use liquidity::{read_liquidity_events, read_liquidity_tx, LiquidityEvent, LiquidityKind, LpBook, LpPosition};
+pub use pipeline::{load_blocks, LoadedBlock, TxFilter};
+pub use pools::PoolRegistry;
+pub use router::{decode_liquidity_call, decode_swap_call, LiquidityCall, LiquidityMethod, SwapCall, SwapMethod};
+pub use store::{Checkpoint, RestoredState, ScanState, Store};
+pub use swaps::{read_log_swaps, PairSwap, PairTokens};
+pub use tokens::{TokenInfo, TokenRegistry};
+pub use v3::{decode_v3_calls, is_v3_router_call, read_v3_log_swaps, read_v3_swaps, read_v3_tx, V3Book, V3Pool,
+             V3SwapCall, V3SwapEvent, V3SwapMethod};
+
 use web3::types::{
     Transaction,
     TransactionReceipt,
@@ -8,6 +49,8 @@ use web3::types::{
     Block
 };
 
+use web3::ethabi::ethereum_types::U512;
+
 use std::error::Error;
 use std::fs::File;
 use std::io::BufReader;
@@ -16,7 +59,7 @@ use std::time::{Duration, Instant};
 use std::thread;
 use std::fmt::Debug;
 
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 
 #[allow(dead_code)]
 #[allow(unused)]
@@ -24,13 +67,34 @@ use std::collections::HashMap;
 #[derive(Debug, Clone)]
 pub struct Trader {
     pub address: H160,
-    pub total_assets: f64,
-    pub total_debt: f64,
-    pub cum_gas: f64,
+    // weth value of the holdings that are up
+    pub total_assets: U256,
+    // weth value of the holdings that are down, as a positive number
+    pub total_debt: U256,
+    // gas units of the captured transactions
+    pub cum_gas: U256,
+    // wei paid for that gas, and the parts burned as base fee and tipped
+    pub gas_fees: U256,
+    pub gas_burned: U256,
+    pub gas_tips: U256,
     pub cum_txs: usize,
-    pub profit_raw: f64,
-    pub hist_cost: f64,
-    pub holdings: HashMap<H160, f64>,
+    pub profit_raw: I256,
+    // weth spent on the trades that could be priced, in wei
+    pub hist_cost: U256,
+    // raw token units, negative for tokens traded away
+    pub holdings: HashMap<H160, I256>,
+    // captured trades per venue
+    pub venues: HashMap<String, usize>,
+    // every captured swap, in chain order
+    pub trades: Vec<Trade>,
+    // weth gained on tokens sold against their bought lots
+    pub realized_pnl: I256,
+    // weth the open lots are up or down at the final pool state
+    pub unrealized_pnl: I256,
+    // cost-basis breakdown per token
+    pub positions: Vec<PositionPnl>,
+    // MEV roles seen in the ledger, e.g. sandwich attacker or victim
+    pub labels: BTreeSet<TraderLabel>,
     pub profit_percent: f64,
     pub roi_percent: f64,
     pub real_gain_percent: f64,
@@ -41,108 +105,270 @@ impl Trader {
 
         Trader {
             address: H160::from_low_u64_be(0_u64),
-            total_assets: 0_f64,
-            total_debt: 0_f64,
-            cum_gas: 0_f64,
+            total_assets: U256::zero(),
+            total_debt: U256::zero(),
+            cum_gas: U256::zero(),
+            gas_fees: U256::zero(),
+            gas_burned: U256::zero(),
+            gas_tips: U256::zero(),
             cum_txs: 0,
-            profit_raw: 0_f64,
-            hist_cost: 0_f64,
+            profit_raw: I256::zero(),
+            hist_cost: U256::zero(),
             holdings: HashMap::new(),
+            venues: HashMap::new(),
+            trades: vec![],
+            realized_pnl: I256::zero(),
+            unrealized_pnl: I256::zero(),
+            positions: vec![],
+            labels: BTreeSet::new(),
             profit_percent: 0_f64,
             roi_percent: 0_f64,
             real_gain_percent: 0_f64,
         }
     }
+
+    /// Book a trade on the ledger. Holdings, cost, gas, transaction and venue
+    /// totals only change through here, so they always sum up the ledger.
+    pub fn record(&mut self, trade: Trade) {
+        *self.holdings.entry(trade.token_in()).or_insert(I256::zero()) -= I256::from(trade.amount_in);
+        *self.holdings.entry(trade.token_out()).or_insert(I256::zero()) += I256::from(trade.amount_out);
+        self.hist_cost += trade.cost.unwrap_or_default();
+        self.pay_gas(&trade.gas);
+        // trades of one transaction are booked one after the other
+        if self.trades.last().map(|last| last.tx_hash) != Some(trade.tx_hash) {
+            self.cum_txs += 1;
+        }
+        *self.venues.entry(trade.venue.clone()).or_insert(0) += 1;
+        self.trades.push(trade);
+    }
+
+    /// Add the gas of a transaction to the totals. `record` does this for
+    /// trades, transactions without one call it directly.
+    pub fn pay_gas(&mut self, gas: &GasCost) {
+        self.cum_gas += gas.gas_used;
+        self.gas_fees += gas.total();
+        self.gas_burned += gas.base_fee;
+        self.gas_tips += gas.priority_fee;
+    }
+
+    /// Holdings in human units with their symbol, e.g. `-1.5 WETH`, sorted
+    /// by symbol.
+    pub fn holdings_in_units(&self, tokens: &TokenRegistry) -> Vec<String> {
+        let mut holdings = self.holdings.iter()
+            .map(|(coin, amt)| (tokens.symbol(coin), tokens.format_amount(coin, *amt)))
+            .collect::<Vec<(String, String)>>();
+        holdings.sort();
+        holdings.into_iter().map(|(_, amt)| amt).collect()
+    }
 }
 
+impl Default for Trader {
+    fn default() -> Self {
+        Trader::new()
+    }
+}
+
+/// One pool a trade went through, as its Swap event reported it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PoolHop {
+    pub pool: H160,
+    // position of the Swap event in the receipt logs
+    pub log_index: usize,
+    pub token_in: H160,
+    pub token_out: H160,
+    pub amount_in: U256,
+    pub amount_out: U256,
+    // (token_0, token_1) sorted the way the pool orders them
+    pub tokens: (H160, H160),
+    // (reserve0, reserve1) around the swap, None for v3 pools which have no
+    // reserves
+    pub reserves_before: Option<(U256, U256)>,
+    pub reserves_after: Option<(U256, U256)>,
+}
 
+impl PoolHop {
+    /// V2 hop from the pair's amount0In, amount1In, amount0Out, amount1Out and
+    /// the reserves its Sync reported after the swap. The reserves before are
+    /// the ones after with the swap taken back out.
+    pub fn v2(pair: H160, log_index: usize, tokens: (H160, H160), amounts: &[U256],
+              reserves_after: (U256, U256)) -> PoolHop {
+        let (token_in, token_out, amount_in, amount_out) = match amounts[3] > amounts[2] {
+            true => (tokens.0, tokens.1, amounts[0], amounts[3]),
+            false => (tokens.1, tokens.0, amounts[1], amounts[2]),
+        };
+        let reserves_before = ((reserves_after.0 + amounts[2]).saturating_sub(amounts[0]),
+                               (reserves_after.1 + amounts[3]).saturating_sub(amounts[1]));
+        PoolHop {
+            pool: pair,
+            log_index,
+            token_in,
+            token_out,
+            amount_in,
+            amount_out,
+            tokens,
+            reserves_before: Some(reserves_before),
+            reserves_after: Some(reserves_after),
+        }
+    }
+}
 
-pub fn scrape_logs(logs: &Vec<Log>, fid_vec: &Vec<H256>, final_recipient: H256,
-                   amount_out_min: U256,
+// Every pool a swap went through, in path order.
+pub type PoolRatios = Vec<PoolHop>;
+
+// start_amount, end_amount, hops of the path
+pub type ScrapedLogs = (U256, U256, PoolRatios);
+
+// start_token, start_amount, end_token, end_amount, receiving_addr, pool_ratios,
+// venue
+pub type UniswapTx = (Option<H160>, U256, Option<H160>, U256, H160, PoolRatios, String);
+
+
+// One hop of a swap path as seen in the logs: the pair's Swap event and the
+// Sync it emitted right before it.
+struct SwapHop {
+    pair: H160,
+    log_index: usize,
+    // amount0In, amount1In, amount0Out, amount1Out
+    amounts: Vec<U256>,
+    reserves: (U256, U256),
+}
+
+// Rebuild the hops of the router call from the receipt logs. The last hop is
+// the Swap of the last pair paying out to `final_recipient`, and every earlier
+// hop is the Swap of its pair paying out to the pair of the hop after it.
+// `pairs` are the CREATE2 addresses the router derives for the path, so
+// swaps that are not part of the chain, e.g. fee-on-transfer tokens selling
+// their fees mid transfer, are left out.
+fn find_swap_hops(logs: &[Log], fid_vec: &[H256], pairs: &[H160], final_recipient: H160)
+-> Result<Vec<SwapHop>, DecodeError> {
+    let mut hops: Vec<SwapHop> = vec![];
+    let mut pay_to = final_recipient;
+    let mut before = logs.len();
+    for hop in (0..pairs.len()).rev() {
+        let mut found = None;
+        for (log_index, log) in logs[..before].iter().enumerate().rev() {
+            if log.address != pairs[hop] || log.topics.first() != Some(&fid_vec[3]) { // swap_fid
+                continue;
+            }
+            if H160::from(log_topic(log, 2)?) == pay_to {
+                found = Some((log_index, log));
+                break;
+            }
+        }
+        let (log_index, log) = found.ok_or(DecodeError::MissingEvent { event: "Swap", hop })?;
+        let sync = logs[..log_index].iter()
+            .rev()
+            .find(|sync| sync.address == log.address && sync.topics.first() == Some(&fid_vec[2]))
+            .ok_or(DecodeError::MissingEvent { event: "Sync", hop })?;
+        let reserves = log_words(sync, 2)?;
+        hops.push(SwapHop {
+            pair: log.address,
+            log_index,
+            amounts: log_words(log, 4)?,
+            reserves: (reserves[0], reserves[1]),
+        });
+        pay_to = log.address;
+        before = log_index;
+    }
+    hops.reverse();
+    Ok(hops)
+}
+
+// Start and end amounts of a router call, following the rules of each method:
+//   exact input: start is `amountIn` (or msg.value), what left the trader.
+//   exact output: start is what the first pair received, the router refunds
+//   or never pulls the rest of `amountInMax`.
+//   exact output: end is the fixed `amountOut`.
+//   fee-on-transfer token output: end is the Transfer into `to`, which is
+//   lower than what the last pair sent.
+//   everything else: end is what the last pair sent, also for ETH output
+//   where the router unwraps that exact amount of WETH.
+pub fn scrape_logs(logs: &[Log], fid_vec: &[H256], call: &SwapCall,
+                   pairs: &[H160],
+                   final_recipient: H160,
                    debug_addr: Option<&H160>,
                    start_addr: &H160,
-                   method: &str,
-                   method_ids: &[&str; 6],
                    )
-// Required outputs:
-// amount traded out, 
-// pool ratios
--> (f64, f64, Vec<(f64, f64)>)
+-> Result<ScrapedLogs, DecodeError>
 {
-    let mut pool_ratios: Vec<(f64, f64)> = vec![];
-    let mut end_amount = U256::from_big_endian(&[0_u8; 32]);
-    let mut start_amount = U256::from_big_endian(&[0_u8; 32]);
-    let mut approve = false;
-    if debug_addr.is_some() && debug_addr.unwrap() == start_addr {
+    let is_debug_addr = debug_addr == Some(start_addr);
+    if is_debug_addr {
         println!("Entering scrape_logs");
     }
-    let mut swap_count = 0;
-    for log in logs {
-//        match debug_addr {
-//            Some(addr) => match addr == start_addr {
-//                true => println!("{}", serde_json::to_string_pretty(log).unwrap()),
-//                false => (),
-//            },
-//            None => ()
-//        };
-
-        let function_hash = log.topics[0];
-        if function_hash == fid_vec[5] {
-            approve = true;
-        } if function_hash == fid_vec[2] { // sync_fid
-            let data_vec = get_bytes_vec(&log.data.0)
-                .iter()
-                .map(|entry| u256_to_f64(U256::from_big_endian(entry)))
-                .collect::<Vec<f64>>();
-            if debug_addr.is_some() && debug_addr.unwrap() == start_addr { 
-                println!("SYNC:\n\tpool_ratios.push(({:?}, {:?}))", data_vec[0], data_vec[1]);
-            }
-            pool_ratios.push((data_vec[0], data_vec[1]));
-        } else if function_hash == fid_vec[3] { // swap_fid
-            let data_vec = get_bytes_vec(&log.data.0)
-                .iter()
-                .map(|entry| U256::from_big_endian(entry))
-                .collect::<Vec<U256>>();
-            let to = log.topics[2];
-            if final_recipient == to {
-                let amount_out = std::cmp::max(data_vec[2], data_vec[3]);
-                end_amount = match amount_out > amount_out_min {
-                    true => amount_out,
-                    false => end_amount,
-                };
-            }
-            if start_amount.low_u32() == 0_u32 {
-                if method == method_ids[5] {
-                    if swap_count == 1 {
-                        start_amount = std::cmp::max(data_vec[0], data_vec[1]);
-                    }
-                } else {
-                    start_amount = std::cmp::max(data_vec[0], data_vec[1]);
-                }
-            }
-            swap_count += 1;
-            if debug_addr.is_some() && debug_addr.unwrap() == start_addr {
-                println!("SWAP:\n\tend_amount: {}", end_amount);
-            }
-        } else if function_hash == fid_vec[4] { // withdrawal_fid
-            let src = log.topics[1];
-            let wad: U256 = get_bytes_vec(&log.data.0)
-                .iter()
-                .map(|entry| U256::from_big_endian(entry))
-                .last()
-                .unwrap();
-            if final_recipient == src || approve == true {
-                end_amount = wad;
-            }
-            if debug_addr.is_some() && debug_addr.unwrap() == start_addr {
-                println!("WITHDRAWAL: final_recipient == src is {}\n\twad = {}\n\tend_amount = {}", final_recipient == src, wad, end_amount);
-            }
+    let hops = find_swap_hops(logs, fid_vec, pairs, final_recipient)?;
+    if is_debug_addr {
+        for hop in &hops {
+            println!("SWAP {:?} at log {}:\n\tamounts: {:?}\n\treserves: {:?}",
+                     hop.pair, hop.log_index, hop.amounts, hop.reserves);
         }
     }
-    if debug_addr.is_some() && debug_addr.unwrap() == start_addr {
+    let first = &hops[0];
+    let last = &hops[hops.len() - 1];
+
+    let start_amount = match call.method.exact_input() {
+        true => call.amount_in,
+        false => std::cmp::max(first.amounts[0], first.amounts[1]),
+    };
+    let pair_out = std::cmp::max(last.amounts[2], last.amounts[3]);
+    let end_amount = match call.method.exact_input() {
+        false => call.amount_out_min,
+        true => match call.method.fee_on_transfer() && !call.method.eth_output() {
+            true => received_amount(logs, fid_vec, call.end_token(), last.pair, call.to,
+                                    last.log_index)?
+                .unwrap_or(pair_out),
+            false => pair_out,
+        },
+    };
+    if is_debug_addr {
+        println!("{}: start_amount {}, end_amount {}", call.method.name(), start_amount, end_amount);
         println!("Exiting scrape_logs");
     }
-    (u256_to_f64(start_amount), u256_to_f64(end_amount), pool_ratios)
+    let pool_ratios = hops.iter()
+        .zip(call.pairs())
+        .map(|(hop, tokens)| PoolHop::v2(hop.pair, hop.log_index, tokens, &hop.amounts, hop.reserves))
+        .collect::<PoolRatios>();
+    Ok((start_amount, end_amount, pool_ratios))
+}
+
+// Value of the `token` Transfer from `from` to `to`, searched before the Swap
+// that sent it since the pair transfers out before emitting Swap.
+fn received_amount(logs: &[Log], fid_vec: &[H256], token: H160, from: H160, to: H160,
+                   before: usize)
+-> Result<Option<U256>, DecodeError> {
+    for log in logs[..before].iter().rev() {
+        if log.address != token || log.topics.first() != Some(&fid_vec[1]) { // transfer_fid
+            continue;
+        }
+        if H160::from(log_topic(log, 1)?) == from && H160::from(log_topic(log, 2)?) == to {
+            return Ok(Some(log_words(log, 1)?[0]));
+        }
+    }
+    Ok(None)
+}
+
+// Indexed topic of a log, checked against the topics actually present.
+pub fn log_topic(log: &Log, index: usize) -> Result<H256, DecodeError> {
+    log.topics.get(index).copied().ok_or(DecodeError::MissingTopic {
+        event: log.topics.first().copied().unwrap_or_default(),
+        index,
+    })
+}
+
+// First `needed` data words of a log, checked against the data length. Any
+// words after them are left out.
+pub fn log_words(log: &Log, needed: usize) -> Result<Vec<U256>, DecodeError> {
+    let words = get_bytes_vec(&log.data.0);
+    if words.len() < needed {
+        return Err(DecodeError::ShortLogData {
+            event: log.topics.first().copied().unwrap_or_default(),
+            needed,
+            len: words.len(),
+        });
+    }
+    Ok(words.iter()
+        .take(needed)
+        .map(|entry| U256::from_big_endian(entry))
+        .collect::<Vec<U256>>())
 }
 
 
@@ -156,86 +382,57 @@ pub fn scrape_logs(logs: &Vec<Log>, fid_vec: &Vec<H256>, final_recipient: H256,
 //                                  address[] path, 
 //                                  address to, 
 //                                  uint256 deadline)
+// The other router methods are decoded the same way by `decode_swap_call`.
 pub fn read_uniswap_tx(tx: &Transaction, receipt: &TransactionReceipt,
-                                   fid_vec: &Vec<H256>, 
-                                   short_input_funcs: &[&str],
-                                   debug_addr: Option<&H160>,
-                                   method_ids: &[&str; 6])
--> Option<(Option<H160>,   //start_token
-    f64,           // start_amount
-    Option<H160>, // end_token
-    f64,
-    H160, //receiving_addr
-    Vec<((H160, H160), (f64, f64))>)> // pool_ratios
+                                   fid_vec: &[H256],
+                                   dex: &Dex,
+                                   debug_addr: Option<&H160>)
+-> Result<UniswapTx, DecodeError>
 {
-    if debug_addr.is_some() && debug_addr.unwrap() == &tx.from.unwrap() {
+    let from = tx.from.ok_or(DecodeError::MissingSender)?;
+    let is_debug_addr = debug_addr == Some(&from);
+    if is_debug_addr {
         println!("Entering read_uniswap_tx");
     }
     // exit early if no last log
-    if receipt.logs.last().is_none() { 
-        println!("REVERTED: Transaction has no logs, probably reverted.");
-        return None; 
-    } // exit early if no last log
-    let method = hex::encode(&tx.input.0[0..4]);
-    let is_eth_input = short_input_funcs.contains(&method.as_str());
-    let input_offset = match is_eth_input {
-        true => 0_usize,
-        false => 1_usize,
-    };
+    if receipt.logs.last().is_none() || receipt.status == Some(0_u64.into()) {
+        return Err(DecodeError::Reverted);
+    }
+    let call = decode_swap_call(&tx.input.0, tx.value)?;
 
-    let inputs_u8: Vec<&[u8]> = get_bytes_vec(&tx.input.0[4..]);
-    let amount_out_min: U256 = match method.as_str() {
-        // swapExactETHForTokens
-        "7ff36ab5" 
-        | "b6f9de95" => U256::from_big_endian(inputs_u8[0]), 
-        "791ac947" 
-        | "18cbafe5" 
-        | "38ed1739" => U256::from_big_endian(inputs_u8[1]),
-        _ => U256::from_dec_str(&"0").unwrap()
-    };
-    let end_token: Option<H160> = Some(H160::from_slice(&inputs_u8.last().unwrap()[12..]));
-    let receiving_addr = H160::from_slice(&inputs_u8[2+input_offset][12..]);
-    let start_token = match is_eth_input {
+    let end_token: Option<H160> = Some(call.end_token());
+    let receiving_addr = call.to;
+    let start_token = match call.method.eth_input() {
         true => None,
-        false => Some(H160::from_slice(&inputs_u8[5+input_offset][12..])),
+        false => Some(call.start_token()),
     };
-   
-    let raw_swap_addrs = &inputs_u8[5+input_offset..];
-    
-    // When ordering pairs, remember uniswap orders them by which by 
-    // (lesser, greater) for (token_0, token_1)
-    let swap_addrs = raw_swap_addrs.iter()
-        .zip(raw_swap_addrs.iter()
-             .skip(1))
-        .map(|(token_0, token_1)| (H160::from_slice(&token_0[12..]),
-                                   H160::from_slice(&token_1[12..])))
-        .map(|(token_0, token_1)| match token_0 < token_1 {
-            true => (token_0, token_1),
-            false => (token_1, token_0),
-            })
-        .collect::<Vec<(H160, H160)>>();
-        if debug_addr.is_some() && debug_addr.unwrap() == &tx.from.unwrap() {
-            println!("swap_addrs: {:?}", swap_addrs);
-        }
+    let swap_addrs = call.pairs();
+    let pair_addrs = swap_addrs.iter()
+        .map(|(token0, token1)| dex.pair_address(*token0, *token1))
+        .collect::<Vec<H160>>();
+    if is_debug_addr {
+        println!("{}: {:?}", call.method.name(), call);
+        println!("swap_addrs: {:?}", swap_addrs);
+        println!("pair_addrs: {:?}", pair_addrs);
+    }
 
-    let final_recipient = H256::from_slice(inputs_u8[2+input_offset]);
-    let (start_amount, end_amount, reserve_ratios) = 
-        scrape_logs(&receipt.logs, fid_vec, final_recipient, amount_out_min,
+    // ETH output swaps pay the router, which unwraps the WETH and sends ETH on
+    let final_recipient = match call.method.eth_output() {
+        true => tx.to.unwrap_or_default(),
+        false => receiving_addr,
+    };
+    let (start_amount, end_amount, pool_ratios) =
+        scrape_logs(&receipt.logs, fid_vec, &call, &pair_addrs, final_recipient,
                     debug_addr,
-                    &tx.from.unwrap(),
-                    method.as_str(),
-                    method_ids);
-    let pool_ratios = swap_addrs.into_iter()
-        .zip(reserve_ratios)
-        .collect::<Vec<((H160, H160), (f64, f64))>>();
-
-    if debug_addr.is_some() && debug_addr.unwrap() == &tx.from.unwrap() {
+                    &from)?;
+
+    if is_debug_addr {
         println!("pool ratios output: {:?}", pool_ratios);
         println!("Exiting read_uniswap_tx");
     }
 
-    return Some((start_token, start_amount, end_token, end_amount, 
-                 receiving_addr, pool_ratios));
+    Ok((start_token, start_amount, end_token, end_amount,
+          receiving_addr, pool_ratios, dex.name.clone()))
 }
 
 pub fn get_bytes_vec(inputs: &[u8]) -> Vec<&[u8]> {
@@ -249,33 +446,6 @@ pub fn get_bytes_vec(inputs: &[u8]) -> Vec<&[u8]> {
 }
 
 
-fn poor_man_log_10(mut n: U256) -> usize {
-    let mut places: usize = 0;
-    let ten = U256::from(10_u64);
-    while !n.is_zero() {
-        n = n.checked_div(ten).unwrap();
-            places += 1;
-    }
-    places
-}
-
-fn highest_five(mut n: U256) -> (U256, usize) {
-    let places = poor_man_log_10(n);
-    if places >= 16 {
-        let ten_k = U256::exp10(places - 16);
-        n = n.checked_div(ten_k).unwrap();
-    }
-    (n, places)
-}
-
-pub fn u256_to_f64(n: U256) -> f64 {
-    let (top_five, places) = highest_five(n);
-    match places >= 16 {
-        true => (top_five.as_usize() as f64) * 10_f64.powf(places as f64 - 16.0),
-        false => top_five.as_usize() as f64
-    }
-}
-
 pub fn read_blocks<P: AsRef<Path>>(path: P) 
 -> Result<Block<Transaction>, Box<dyn Error>> {
     let file = File::open(path)?;
@@ -312,13 +482,14 @@ pub fn debug_print<T: Debug>(to_print: T) -> bool {
 }
 
 pub fn update_pools(uniswap_pools: &mut HashMap<H160, f64>,
-                    pool_ratios: &Vec<((H160, H160), (f64, f64))>,
-                    weth_addr: &H160) -> () {
-    for (coins, values) in pool_ratios { // try only saving weth tuples
+                    pool_ratios: &PoolRatios,
+                    weth_addr: &H160) {
+    for (coins, values) in pool_ratios.iter() // try only saving weth tuples
+            .filter_map(|hop| Some((hop.tokens, hop.reserves_after?))) {
         let updated_pool = match coins.0 == *weth_addr {
-            true => Some((coins.1, values.0 / values.1)),
+            true => Some((coins.1, u256_to_f64(values.0) / u256_to_f64(values.1))),
             false => match coins.1 == *weth_addr {
-                true => Some((coins.0, values.1 / values.0)),
+                true => Some((coins.0, u256_to_f64(values.1) / u256_to_f64(values.0))),
                 false => None,
             }
         };
@@ -330,10 +501,11 @@ pub fn update_pools(uniswap_pools: &mut HashMap<H160, f64>,
     }
 }
 
-pub fn update_liq_pools(uniswap_liq: &mut HashMap<H160, (f64, f64)>,
-                    pool_ratios: &Vec<((H160, H160), (f64, f64))>,
-                    weth_addr: &H160) -> () {
-    for (coins, values) in pool_ratios { // try only saving weth tuples
+pub fn update_liq_pools(uniswap_liq: &mut HashMap<H160, (U256, U256)>,
+                    pool_ratios: &PoolRatios,
+                    weth_addr: &H160) {
+    for (coins, values) in pool_ratios.iter() // try only saving weth tuples
+            .filter_map(|hop| Some((hop.tokens, hop.reserves_after?))) {
         let updated_pool = match coins.0 == *weth_addr {
             true => Some((coins.1, (values.0, values.1))),
             false => match coins.1 == *weth_addr {
@@ -349,35 +521,378 @@ pub fn update_liq_pools(uniswap_liq: &mut HashMap<H160, (f64, f64)>,
     }
 }
 
-#[derive(Debug)]
+/// An exact input swap made against an `Amm`, as the pair's Swap and Sync
+/// events would report it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AmmSwap {
+    pub token_in: H160,
+    pub token_out: H160,
+    pub amount_in: U256,
+    pub amount_out: U256,
+    // (reserve0, reserve1) after the swap
+    pub reserves: (U256, U256),
+}
+
+#[derive(Debug, Clone)]
 pub struct Amm {
+    // pair contract the reserves belong to
+    address: H160,
     token0_name: H160,
     token1_name: H160,
-    token0_res: f64,
-    token1_res: f64,
+    token0_res: U256,
+    token1_res: U256,
+    // swap fee in basis points, 30 on Uniswap V2 itself
+    fee_bps: u32,
 }
 
 impl Amm {
-    pub fn new(token0_name: H160, token1_name: H160,
-               token0_res: f64, token1_res: f64) -> Amm {
+    pub fn new(address: H160, token0_name: H160, token1_name: H160,
+               token0_res: U256, token1_res: U256, fee_bps: u32) -> Amm {
         Amm {
+            address,
             token0_name,
             token1_name,
             token0_res,
             token1_res,
+            fee_bps,
+        }
+    }
+    pub fn address(&self) -> H160 {
+        self.address
+    }
+    pub fn fee_bps(&self) -> u32 {
+        self.fee_bps
+    }
+    // Part of the input left after the fee, over 10000.
+    fn fee_multiplier(&self) -> U256 {
+        U256::from(10_000_u32.saturating_sub(self.fee_bps))
+    }
+    pub fn tokens(&self) -> (H160, H160) {
+        (self.token0_name, self.token1_name)
+    }
+    pub fn reserves(&self) -> (U256, U256) {
+        (self.token0_res, self.token1_res)
+    }
+    // Reserve of one of the pair's tokens, None for any other token.
+    pub fn reserve_of(&self, token: H160) -> Option<U256> {
+        match (token == self.token0_name, token == self.token1_name) {
+            (true, _) => Some(self.token0_res),
+            (false, true) => Some(self.token1_res),
+            (false, false) => None,
         }
     }
-    pub fn uniswap_immut(&self, token_in: H160, amt_in: f64) -> f64 {
-        let (res_in, res_out): (&f64, &f64) = match token_in == self.token0_name {
-            true => (&self.token0_res, &self.token1_res),
-            false => match token_in == self.token1_name {
-                true => (&self.token1_res, &self.token0_res),
-                false => panic!("invalid token input") }
+    pub fn set_reserves(&mut self, token0_res: U256, token1_res: U256) {
+        self.token0_res = token0_res;
+        self.token1_res = token1_res;
+    }
+    // (reserve of `token`, reserve of the other token), both non-zero.
+    fn reserves_from(&self, token: H160) -> Result<(U256, U256), AmmError> {
+        let reserves = match (token == self.token0_name, token == self.token1_name) {
+            (true, _) => (self.token0_res, self.token1_res),
+            (false, true) => (self.token1_res, self.token0_res),
+            (false, false) => return Err(AmmError::UnknownToken(token)),
         };
-        let amount_in_with_fee = amt_in * 997.0;
-        let numerator = amount_in_with_fee * *res_out;
-        let denominator = (*res_in * 1000_f64) + amount_in_with_fee;
-        let amt_out = numerator / denominator;
-        amt_out
+        match reserves.0.is_zero() || reserves.1.is_zero() {
+            true => Err(AmmError::InsufficientLiquidity),
+            false => Ok(reserves),
+        }
+    }
+    fn other_token(&self, token: H160) -> H160 {
+        match token == self.token0_name {
+            true => self.token1_name,
+            false => self.token0_name,
+        }
+    }
+    /// Output of selling `amt_in` of `token_in`, with the same integer math
+    /// as UniswapV2Library.getAmountOut at the pool's fee, so quotes match the
+    /// amounts the pair contract pays out to the wei. A zero amount quotes
+    /// zero.
+    pub fn uniswap_immut(&self, token_in: H160, amt_in: U256) -> Result<U256, AmmError> {
+        let (res_in, res_out) = self.reserves_from(token_in)?;
+        if amt_in.is_zero() {
+            return Ok(U256::zero());
+        }
+        let amount_in_with_fee = amt_in.full_mul(self.fee_multiplier());
+        let numerator = amount_in_with_fee * U512::from(res_out);
+        let denominator = res_in.full_mul(U256::from(10_000_u64)) + amount_in_with_fee;
+        U256::try_from(numerator / denominator).map_err(|_| AmmError::Overflow)
+    }
+    /// Input of `token_out`'s other token needed to buy exactly `amount_out`
+    /// of `token_out`, the UniswapV2Library.getAmountIn math including its
+    /// rounding up. A zero amount costs zero.
+    pub fn get_amount_in(&self, token_out: H160, amount_out: U256) -> Result<U256, AmmError> {
+        let (res_out, res_in) = self.reserves_from(token_out)?;
+        if amount_out.is_zero() {
+            return Ok(U256::zero());
+        }
+        if amount_out >= res_out {
+            return Err(AmmError::InsufficientLiquidity);
+        }
+        let numerator = res_in.full_mul(amount_out) * U512::from(10_000_u64);
+        let denominator = (res_out - amount_out).full_mul(self.fee_multiplier());
+        if denominator.is_zero() {
+            return Err(AmmError::InsufficientLiquidity);
+        }
+        U256::try_from(numerator / denominator + U512::one()).map_err(|_| AmmError::Overflow)
+    }
+    /// Sell `amount_in` of `token_in` into the pool and move the reserves the
+    /// way the pair does. The pool is left as it was when the swap fails.
+    pub fn swap(&mut self, token_in: H160, amount_in: U256) -> Result<AmmSwap, AmmError> {
+        let amount_out = self.uniswap_immut(token_in, amount_in)?;
+        if amount_out.is_zero() {
+            return Err(AmmError::InsufficientOutputAmount);
+        }
+        let (res_in, res_out) = self.reserves_from(token_in)?;
+        let res_in = res_in.checked_add(amount_in).ok_or(AmmError::Overflow)?;
+        let res_out = res_out - amount_out;
+        match token_in == self.token0_name {
+            true => self.set_reserves(res_in, res_out),
+            false => self.set_reserves(res_out, res_in),
+        }
+        Ok(AmmSwap {
+            token_in,
+            token_out: self.other_token(token_in),
+            amount_in,
+            amount_out,
+            reserves: self.reserves(),
+        })
+    }
+    /// Marginal price of one raw unit of `token` in raw units of the other
+    /// token, before the fee.
+    pub fn spot_price(&self, token: H160) -> Result<f64, AmmError> {
+        let (res_token, res_other) = self.reserves_from(token)?;
+        Ok(u256_to_f64(res_other) / u256_to_f64(res_token))
+    }
+    /// How far selling `amount_in` of `token_in` moves its spot price down,
+    /// as a fraction, e.g. 0.02 for 2%.
+    pub fn price_impact(&self, token_in: H160, amount_in: U256) -> Result<f64, AmmError> {
+        let before = self.spot_price(token_in)?;
+        let mut after = self.clone();
+        after.swap(token_in, amount_in)?;
+        Ok(1.0 - after.spot_price(token_in)? / before)
+    }
+    /// How much less the swap pays out than `amount_in` is worth at the spot
+    /// price, as a fraction. This is what a large position loses to the curve
+    /// and the fee when sold at once.
+    pub fn slippage(&self, token_in: H160, amount_in: U256) -> Result<f64, AmmError> {
+        let at_spot = u256_to_f64(amount_in) * self.spot_price(token_in)?;
+        let amount_out = u256_to_f64(self.uniswap_immut(token_in, amount_in)?);
+        match at_spot > 0.0 {
+            true => Ok(1.0 - amount_out / at_spot),
+            false => Ok(0.0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_logs::{address, fid_vec, pair_swap, transfer};
+    use web3::ethabi::{self, Token};
+
+    fn token(n: u64) -> H160 {
+        H160::from_low_u64_be(n)
+    }
+
+    fn pool(reserve0: u64, reserve1: u64, fee_bps: u32) -> Amm {
+        Amm::new(token(0xaa), token(1), token(2), U256::from(reserve0), U256::from(reserve1), fee_bps)
+    }
+
+    #[test]
+    fn quotes_match_get_amount_out() {
+        let amm = pool(1_000_000, 2_000_000, 30);
+        // 10000 * 997 * 2000000 / (1000000 * 1000 + 10000 * 997)
+        assert_eq!(amm.uniswap_immut(token(1), U256::from(10_000)), Ok(U256::from(19_743)));
+        assert_eq!(amm.uniswap_immut(token(1), U256::zero()), Ok(U256::zero()));
+        // a 0.25% fork pays out more for the same input
+        let fork = pool(1_000_000, 2_000_000, 25);
+        assert_eq!(fork.uniswap_immut(token(1), U256::from(10_000)), Ok(U256::from(19_752)));
+        assert_eq!(fork.fee_bps(), 25);
+    }
+
+    #[test]
+    fn get_amount_in_round_trips() {
+        let amm = pool(1_000_000, 2_000_000, 30);
+        for amount_out in [1_u64, 19_743, 500_000, 1_999_999] {
+            let amount_out = U256::from(amount_out);
+            let amount_in = amm.get_amount_in(token(2), amount_out).unwrap();
+            // the input is the least that buys the output
+            assert!(amm.uniswap_immut(token(1), amount_in).unwrap() >= amount_out);
+            assert!(amm.uniswap_immut(token(1), amount_in - 1).unwrap() < amount_out);
+        }
+        assert_eq!(amm.get_amount_in(token(2), U256::from(19_743)), Ok(U256::from(10_000)));
+        assert_eq!(amm.get_amount_in(token(2), U256::zero()), Ok(U256::zero()));
+    }
+
+    #[test]
+    fn swap_moves_reserves_like_the_pair() {
+        let mut amm = pool(1_000_000, 2_000_000, 30);
+        let swap = amm.swap(token(1), U256::from(10_000)).unwrap();
+        // Swap(amount0In 10000, amount1Out 19743) then Sync(1010000, 1980257)
+        assert_eq!(swap, AmmSwap {
+            token_in: token(1),
+            token_out: token(2),
+            amount_in: U256::from(10_000),
+            amount_out: U256::from(19_743),
+            reserves: (U256::from(1_010_000), U256::from(1_980_257)),
+        });
+        assert_eq!(amm.reserves(), swap.reserves);
+        // and back the other way, reserves stay in token order
+        let back = amm.swap(token(2), U256::from(19_743)).unwrap();
+        assert_eq!(back.token_out, token(1));
+        assert_eq!(back.reserves, (U256::from(1_010_000) - back.amount_out, U256::from(2_000_000)));
+    }
+
+    #[test]
+    fn prices_and_impact() {
+        let amm = pool(1_000_000, 2_000_000, 30);
+        assert_eq!(amm.spot_price(token(1)), Ok(2.0));
+        assert_eq!(amm.spot_price(token(2)), Ok(0.5));
+        let small = amm.price_impact(token(1), U256::from(1_000)).unwrap();
+        let large = amm.price_impact(token(1), U256::from(100_000)).unwrap();
+        assert!(0.0 < small && small < large);
+        // a tiny trade loses about the fee, a large one the curve on top
+        let slippage = amm.slippage(token(1), U256::from(1_000)).unwrap();
+        assert!((slippage - 0.004).abs() < 0.001);
+        assert!(amm.slippage(token(1), U256::from(100_000)).unwrap() > 0.09);
+    }
+
+    #[test]
+    fn errors_instead_of_panics() {
+        let amm = pool(1_000_000, 2_000_000, 30);
+        assert_eq!(amm.uniswap_immut(token(3), U256::one()), Err(AmmError::UnknownToken(token(3))));
+        assert_eq!(amm.get_amount_in(token(3), U256::one()), Err(AmmError::UnknownToken(token(3))));
+        assert_eq!(amm.spot_price(token(3)), Err(AmmError::UnknownToken(token(3))));
+
+        let empty = pool(0, 2_000_000, 30);
+        assert_eq!(empty.uniswap_immut(token(1), U256::one()), Err(AmmError::InsufficientLiquidity));
+        assert_eq!(empty.spot_price(token(2)), Err(AmmError::InsufficientLiquidity));
+        assert_eq!(amm.get_amount_in(token(2), U256::from(2_000_000)), Err(AmmError::InsufficientLiquidity));
+
+        let mut deep = pool(1_000_000_000, 1_000, 30);
+        assert_eq!(deep.swap(token(1), U256::from(1_000)), Err(AmmError::InsufficientOutputAmount));
+        assert_eq!(deep.reserves(), (U256::from(1_000_000_000), U256::from(1_000)));
+
+        let huge = Amm::new(token(0xaa), token(1), token(2), U256::MAX, U256::from(1_000), 30);
+        assert_eq!(huge.get_amount_in(token(2), U256::from(999)), Err(AmmError::Overflow));
+        let mut full = Amm::new(token(0xaa), token(1), token(2), U256::MAX - 10, U256::MAX, 30);
+        assert_eq!(full.swap(token(1), U256::from(1_000)), Err(AmmError::Overflow));
+        assert_eq!(full.reserves(), (U256::MAX - 10, U256::MAX));
+    }
+
+    const A: u64 = 0x10;
+    const B: u64 = 0x20;
+    const TRADER: u64 = 0xbb;
+    const ROUTER: u64 = 0xcc;
+
+    fn uniswap_v2() -> Dex {
+        DexRegistry::mainnet().iter().find(|dex| dex.kind == DexKind::V2).cloned().unwrap()
+    }
+
+    fn sorted(x: u64, y: u64) -> (H160, H160) {
+        (address(x.min(y)), address(x.max(y)))
+    }
+
+    fn pair(x: u64, y: u64) -> H160 {
+        let (token0, token1) = sorted(x, y);
+        uniswap_v2().pair_address(token0, token1)
+    }
+
+    // A router call of `method` from the trader, with the leading amount
+    // arguments followed by the path, `to` and a deadline.
+    fn router_tx(method: SwapMethod, amounts: &[u64], path: &[u64], value: u64) -> Transaction {
+        let mut args = amounts.iter().map(|amount| Token::Uint(U256::from(*amount))).collect::<Vec<Token>>();
+        args.push(Token::Array(path.iter().map(|token| Token::Address(address(*token))).collect()));
+        args.extend([Token::Address(address(TRADER)), Token::Uint(U256::MAX)]);
+        let mut input = method.selector().to_vec();
+        input.extend(ethabi::encode(&args));
+        Transaction {
+            from: Some(address(TRADER)),
+            to: Some(address(ROUTER)),
+            input: input.into(),
+            value: U256::from(value),
+            ..Default::default()
+        }
+    }
+
+    fn read(tx: &Transaction, logs: Vec<Log>) -> Result<UniswapTx, DecodeError> {
+        let receipt = TransactionReceipt { logs, status: Some(1_u64.into()), ..Default::default() };
+        read_uniswap_tx(tx, &receipt, &fid_vec(), &uniswap_v2(), None)
+    }
+
+    #[test]
+    fn exact_input_starts_at_the_call_amount() {
+        let weth = 1;
+        let tx = router_tx(SwapMethod::SwapExactTokensForTokens, &[1000, 1], &[A, weth, B], 0);
+        let mut logs = vec![transfer(address(A), address(TRADER), pair(A, weth), 1000)];
+        logs.extend(pair_swap(pair(A, weth), sorted(A, weth), address(A), 1000, 900,
+                              address(ROUTER), pair(weth, B)));
+        // a swap of the first pair that is not part of the path
+        logs.extend(pair_swap(pair(A, weth), sorted(A, weth), address(A), 5, 4,
+                              address(ROUTER), address(0xee)));
+        logs.extend(pair_swap(pair(weth, B), sorted(weth, B), address(weth), 900, 800,
+                              address(ROUTER), address(TRADER)));
+        let (start_token, start_amount, end_token, end_amount, to, hops, venue) = read(&tx, logs).unwrap();
+        assert_eq!((start_token, start_amount), (Some(address(A)), U256::from(1000)));
+        assert_eq!((end_token, end_amount), (Some(address(B)), U256::from(800)));
+        assert_eq!((to, venue.as_str()), (address(TRADER), "uniswap-v2"));
+        assert_eq!(hops.iter().map(|hop| hop.pool).collect::<Vec<H160>>(), vec![pair(A, weth), pair(weth, B)]);
+        assert_eq!(hops[0].log_index, 2);
+        assert_eq!((hops[1].token_in, hops[1].amount_in, hops[1].amount_out),
+                   (address(weth), U256::from(900), U256::from(800)));
+    }
+
+    #[test]
+    fn eth_input_starts_at_the_value() {
+        let weth = 1;
+        let tx = router_tx(SwapMethod::SwapExactETHForTokens, &[1], &[weth, A], 5000);
+        let logs = pair_swap(pair(weth, A), sorted(weth, A), address(weth), 5000, 700,
+                             address(ROUTER), address(TRADER));
+        let (start_token, start_amount, _, end_amount, ..) = read(&tx, logs).unwrap();
+        assert_eq!((start_token, start_amount, end_amount), (None, U256::from(5000), U256::from(700)));
+    }
+
+    #[test]
+    fn exact_output_starts_at_what_the_first_pair_received() {
+        // at most 1100 A for exactly 800 B, and the pair only needed 990
+        let tx = router_tx(SwapMethod::SwapTokensForExactTokens, &[800, 1100], &[A, B], 0);
+        let mut logs = vec![transfer(address(A), address(TRADER), pair(A, B), 990)];
+        logs.extend(pair_swap(pair(A, B), sorted(A, B), address(A), 990, 800, address(ROUTER), address(TRADER)));
+        let (_, start_amount, _, end_amount, ..) = read(&tx, logs).unwrap();
+        assert_eq!((start_amount, end_amount), (U256::from(990), U256::from(800)));
+    }
+
+    #[test]
+    fn fee_on_transfer_output_ends_at_what_the_recipient_got() {
+        let tx = router_tx(SwapMethod::SwapExactTokensForTokensSupportingFeeOnTransferTokens, &[1000, 1],
+                           &[A, B], 0);
+        let mut logs = vec![transfer(address(A), address(TRADER), pair(A, B), 1000)];
+        // the pair sends 800 B, of which the token keeps 40
+        let mut hop = pair_swap(pair(A, B), sorted(A, B), address(A), 1000, 800, address(ROUTER), address(TRADER));
+        logs.push(hop.remove(0));
+        logs.push(transfer(address(B), pair(A, B), address(0xfee), 40));
+        logs.push(transfer(address(B), pair(A, B), address(TRADER), 760));
+        logs.extend(hop);
+        let (_, start_amount, _, end_amount, ..) = read(&tx, logs.clone()).unwrap();
+        assert_eq!((start_amount, end_amount), (U256::from(1000), U256::from(760)));
+
+        // without the Transfer the pair's side is all there is
+        logs.remove(3);
+        assert_eq!(read(&tx, logs).unwrap().3, U256::from(800));
+    }
+
+    #[test]
+    fn eth_output_is_paid_to_the_router() {
+        let weth = 1;
+        let tx = router_tx(SwapMethod::SwapExactTokensForETH, &[1000, 1], &[A, weth], 0);
+        let logs = pair_swap(pair(A, weth), sorted(A, weth), address(A), 1000, 600,
+                             address(ROUTER), address(ROUTER));
+        let (_, _, end_token, end_amount, to, ..) = read(&tx, logs).unwrap();
+        assert_eq!((end_token, end_amount, to), (Some(address(weth)), U256::from(600), address(TRADER)));
+
+        // a swap paying the trader directly is not the router's
+        let logs = pair_swap(pair(A, weth), sorted(A, weth), address(A), 1000, 600,
+                             address(ROUTER), address(TRADER));
+        assert_eq!(read(&tx, logs).unwrap_err(), DecodeError::MissingEvent { event: "Swap", hop: 0 });
     }
 }
@@ -0,0 +1,441 @@
+use web3::ethabi::ethereum_types::U512;
+use web3::types::{H160, H256, Log, Transaction, TransactionReceipt, U256};
+
+use std::collections::HashMap;
+
+use crate::error::DecodeError;
+use crate::router::{decode_liquidity_call, LiquidityCall};
+use crate::swaps::{pair_window, transfer_parties, PairTokens};
+use crate::{log_topic, log_words, u256_to_f64};
+
+// LP tokens the pair locks away on its first mint.
+const MINIMUM_LIQUIDITY: u64 = 1000;
+
+// Fixed point one of the sqrt(k) per LP token growth.
+const GROWTH_ONE: u64 = 1_000_000_000_000_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LiquidityKind {
+    Add,
+    Remove,
+}
+
+impl LiquidityKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            LiquidityKind::Add => "add",
+            LiquidityKind::Remove => "remove",
+        }
+    }
+}
+
+/// A pair `Mint` or `Burn` event with the LP tokens it minted or burnt and the
+/// `Sync` the pair emitted right before it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LiquidityEvent {
+    pub kind: LiquidityKind,
+    pub pair: H160,
+    // position in the receipt logs
+    pub log_index: usize,
+    // msg.sender of pair.mint or pair.burn, usually the router
+    pub sender: H160,
+    // receiver of the LP tokens when adding, of the pair tokens when removing
+    pub to: H160,
+    // (token_0, token_1) sorted the way the pair orders them
+    pub tokens: (H160, H160),
+    pub amount0: U256,
+    pub amount1: U256,
+    pub liquidity: U256,
+    // (reserve0, reserve1) reported by Sync after the event
+    pub reserves: (U256, U256),
+}
+
+impl LiquidityEvent {
+    /// LP token supply after the event, estimated from the amounts moved and
+    /// the reserves. The pair mints and burns in proportion to its reserves,
+    /// so the supply follows from any one event. Protocol fee mints are not
+    /// counted.
+    pub fn supply_after(&self) -> Option<U256> {
+        let amounts = [self.amount0, self.amount1];
+        match self.kind {
+            LiquidityKind::Add => {
+                let before = [self.reserves.0.saturating_sub(self.amount0),
+                              self.reserves.1.saturating_sub(self.amount1)];
+                if before[0].is_zero() || before[1].is_zero() {
+                    // first mint of the pair
+                    return Some(self.liquidity + U256::from(MINIMUM_LIQUIDITY));
+                }
+                // minted = min(amount * supply / reserve) over both sides
+                let supply = (0..2)
+                    .filter(|side| !amounts[*side].is_zero())
+                    .map(|side| mul_div(self.liquidity, before[side], amounts[side]))
+                    .max()?;
+                Some(supply + self.liquidity)
+            },
+            LiquidityKind::Remove => {
+                let before = [self.reserves.0 + self.amount0, self.reserves.1 + self.amount1];
+                // paid = liquidity * balance / supply on both sides
+                let supply = (0..2)
+                    .filter(|side| !amounts[*side].is_zero())
+                    .map(|side| mul_div(self.liquidity, before[side], amounts[side]))
+                    .min()?;
+                Some(supply.saturating_sub(self.liquidity))
+            },
+        }
+    }
+}
+
+fn mul_div(a: U256, b: U256, c: U256) -> U256 {
+    let value = U512::from(a) * U512::from(b) / U512::from(c);
+    U256::try_from(value).unwrap_or(U256::MAX)
+}
+
+/// Every pair `Mint` and `Burn` in the receipt, in log order.
+pub fn read_liquidity_events(logs: &[Log], fid_vec: &[H256], pair_tokens: &mut PairTokens)
+-> Result<Vec<LiquidityEvent>, DecodeError> {
+    let mut events: Vec<LiquidityEvent> = vec![];
+    for (log_index, log) in logs.iter().enumerate() {
+        let kind = match log.topics.first() {
+            Some(topic) if *topic == fid_vec[6] => LiquidityKind::Add, // mint_fid
+            Some(topic) if *topic == fid_vec[7] => LiquidityKind::Remove, // burn_fid
+            _ => continue,
+        };
+        let pair = log.address;
+        let sender = H160::from(log_topic(log, 1)?);
+        let amounts = log_words(log, 2)?;
+        let missing = |event| DecodeError::MissingEvent { event, hop: events.len() };
+
+        let sync = logs[..log_index].iter()
+            .rev()
+            .find(|sync| sync.address == pair && sync.topics.first() == Some(&fid_vec[2]))
+            .ok_or(missing("Sync"))?;
+        let reserves = log_words(sync, 2)?;
+
+        let window = pair_window(logs, fid_vec, log_index, pair);
+        // the pair is the LP token, minting from and burning to the zero address
+        let lp_transfer = window.iter()
+            .rev()
+            .find(|transfer| transfer.address == pair
+                  && transfer_parties(transfer, fid_vec).is_some_and(|(from, to)| match kind {
+                      LiquidityKind::Add => from.is_zero() && !to.is_zero(),
+                      LiquidityKind::Remove => from == pair && to.is_zero(),
+                  }))
+            .ok_or(missing("Transfer"))?;
+        let liquidity = log_words(lp_transfer, 1)?[0];
+        let to = match kind {
+            LiquidityKind::Add => transfer_parties(lp_transfer, fid_vec).map(|(_, to)| to),
+            LiquidityKind::Remove => Some(H160::from(log_topic(log, 2)?)),
+        }.unwrap_or_default();
+
+        // deposits go into the pair, withdrawals come out of it
+        let mut moved = window.iter()
+            .filter(|transfer| transfer.address != pair
+                    && transfer_parties(transfer, fid_vec).is_some_and(|(from, to)| match kind {
+                        LiquidityKind::Add => to == pair,
+                        LiquidityKind::Remove => from == pair,
+                    }))
+            .map(|transfer| transfer.address)
+            .collect::<Vec<H160>>();
+        moved.sort();
+        moved.dedup();
+        let tokens = match moved[..] {
+            [token0, token1] => (token0, token1),
+            _ => *pair_tokens.get(&pair).ok_or(missing("Transfer"))?,
+        };
+        pair_tokens.insert(pair, tokens);
+
+        events.push(LiquidityEvent {
+            kind,
+            pair,
+            log_index,
+            sender,
+            to,
+            tokens,
+            amount0: amounts[0],
+            amount1: amounts[1],
+            liquidity,
+            reserves: (reserves[0], reserves[1]),
+        });
+    }
+    Ok(events)
+}
+
+/// Decode a router liquidity call and the pair events it caused.
+pub fn read_liquidity_tx(tx: &Transaction, receipt: &TransactionReceipt, fid_vec: &[H256],
+                         pair_tokens: &mut PairTokens)
+-> Result<(LiquidityCall, Vec<LiquidityEvent>), DecodeError> {
+    if receipt.logs.is_empty() || receipt.status == Some(0_u64.into()) {
+        return Err(DecodeError::Reverted);
+    }
+    let call = decode_liquidity_call(&tx.input.0, tx.value)?;
+    let kind = match call.method.adds() {
+        true => LiquidityKind::Add,
+        false => LiquidityKind::Remove,
+    };
+    let events = read_liquidity_events(&receipt.logs, fid_vec, pair_tokens)?
+        .into_iter()
+        .filter(|event| event.kind == kind)
+        .collect::<Vec<LiquidityEvent>>();
+    if events.is_empty() {
+        let event = match kind {
+            LiquidityKind::Add => "Mint",
+            LiquidityKind::Remove => "Burn",
+        };
+        return Err(DecodeError::MissingEvent { event, hop: 0 });
+    }
+    Ok((call, events))
+}
+
+/// A pair as seen through its liquidity events and syncs. Only what values
+/// LP positions is kept, quotes go through the `PoolRegistry`.
+#[derive(Debug, Clone)]
+pub struct PairState {
+    pub tokens: (H160, H160),
+    pub reserves: (U256, U256),
+    // estimated from the last Mint or Burn, see `LiquidityEvent::supply_after`
+    pub total_supply: U256,
+}
+
+impl PairState {
+    // sqrt(k) per LP token in 1e18 parts, which only grows through swap fees
+    fn growth(&self) -> Option<U256> {
+        if self.total_supply.is_zero() {
+            return None;
+        }
+        let (reserve0, reserve1) = self.reserves;
+        let sqrt_k = (U512::from(reserve0) * U512::from(reserve1)).integer_sqrt();
+        let growth = sqrt_k * U512::from(GROWTH_ONE) / U512::from(self.total_supply);
+        Some(U256::try_from(growth).unwrap_or(U256::MAX))
+    }
+}
+
+/// LP tokens one address got from adding liquidity to one pair.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LpPosition {
+    pub owner: H160,
+    pub pair: H160,
+    pub tokens: (H160, H160),
+    // LP tokens still held
+    pub liquidity: U256,
+    pub deposited: (U256, U256),
+    pub withdrawn: (U256, U256),
+    // deposits backing the LP tokens still held, shrunk pro rata on removals
+    pub hodl: (U256, U256),
+    // liquidity weighted sqrt(k) per LP token at the deposits, in 1e18 parts
+    pub entry_growth: U256,
+}
+
+/// Per address LP positions, with the pair reserves and LP supply needed to
+/// value them.
+#[derive(Debug, Clone, Default)]
+pub struct LpBook {
+    pub pairs: HashMap<H160, PairState>,
+    pub positions: HashMap<(H160, H160), LpPosition>,
+}
+
+impl LpBook {
+    pub fn new() -> Self {
+        LpBook::default()
+    }
+
+    /// Refresh the reserves of known pairs from the Sync logs of a receipt.
+    pub fn observe_syncs(&mut self, logs: &[Log], fid_vec: &[H256]) {
+        for log in logs.iter().filter(|log| log.topics.first() == Some(&fid_vec[2])) {
+            if let (Some(state), Ok(reserves)) = (self.pairs.get_mut(&log.address),
+                                                  log_words(log, 2)) {
+                state.reserves = (reserves[0], reserves[1]);
+            }
+        }
+    }
+
+    /// Book a Mint or Burn on the position of `owner`. Removals of liquidity
+    /// added before the scanned range only count as withdrawals.
+    pub fn apply(&mut self, owner: H160, event: &LiquidityEvent) {
+        let state = self.pairs.entry(event.pair)
+            .or_insert_with(|| PairState {
+                tokens: event.tokens,
+                reserves: event.reserves,
+                total_supply: U256::zero(),
+            });
+        state.reserves = event.reserves;
+        if let Some(supply) = event.supply_after() {
+            state.total_supply = supply;
+        }
+        let growth = state.growth().unwrap_or_default();
+
+        let position = self.positions.entry((owner, event.pair))
+            .or_insert_with(|| LpPosition {
+                owner,
+                pair: event.pair,
+                tokens: event.tokens,
+                liquidity: U256::zero(),
+                deposited: (U256::zero(), U256::zero()),
+                withdrawn: (U256::zero(), U256::zero()),
+                hodl: (U256::zero(), U256::zero()),
+                entry_growth: U256::zero(),
+            });
+        match event.kind {
+            LiquidityKind::Add => {
+                let (held, added) = (U512::from(position.liquidity), U512::from(event.liquidity));
+                if !(held + added).is_zero() {
+                    let weighted = U512::from(position.entry_growth) * held + U512::from(growth) * added;
+                    position.entry_growth = U256::try_from(weighted / (held + added)).unwrap_or(U256::MAX);
+                }
+                position.liquidity += event.liquidity;
+                position.deposited.0 += event.amount0;
+                position.deposited.1 += event.amount1;
+                position.hodl.0 += event.amount0;
+                position.hodl.1 += event.amount1;
+            },
+            LiquidityKind::Remove => {
+                let removed = event.liquidity.min(position.liquidity);
+                if !position.liquidity.is_zero() {
+                    let left = position.liquidity - removed;
+                    position.hodl.0 = mul_div(position.hodl.0, left, position.liquidity);
+                    position.hodl.1 = mul_div(position.hodl.1, left, position.liquidity);
+                }
+                position.liquidity -= removed;
+                position.withdrawn.0 += event.amount0;
+                position.withdrawn.1 += event.amount1;
+            },
+        }
+    }
+
+    pub fn positions_of(&self, owner: H160) -> impl Iterator<Item = &LpPosition> {
+        self.positions.values().filter(move |position| position.owner == owner)
+    }
+
+    /// The position's share of the current reserves.
+    pub fn underlying(&self, position: &LpPosition) -> Option<(U256, U256)> {
+        let state = self.pairs.get(&position.pair)?;
+        if state.total_supply.is_zero() {
+            return None;
+        }
+        let (reserve0, reserve1) = state.reserves;
+        Some((mul_div(reserve0, position.liquidity, state.total_supply),
+              mul_div(reserve1, position.liquidity, state.total_supply)))
+    }
+
+    /// Swap fees earned since the deposits, as the part of the underlying
+    /// amounts that came from growth of sqrt(k) per LP token.
+    pub fn fee_income(&self, position: &LpPosition) -> Option<(U256, U256)> {
+        let (amount0, amount1) = self.underlying(position)?;
+        let growth = self.pairs.get(&position.pair)?.growth()?;
+        if position.entry_growth.is_zero() || growth <= position.entry_growth {
+            return Some((U256::zero(), U256::zero()));
+        }
+        // the part of the underlying amounts grown since the deposits
+        let grown = growth - position.entry_growth;
+        Some((mul_div(amount0, grown, growth), mul_div(amount1, grown, growth)))
+    }
+
+    /// Value of the position without its fee income against simply holding
+    /// the deposits, both at the current pool price. Negative, e.g. -0.05 for a
+    /// 5% loss.
+    pub fn impermanent_loss(&self, position: &LpPosition) -> Option<f64> {
+        let (amount0, amount1) = self.underlying(position)?;
+        let (fee0, fee1) = self.fee_income(position)?;
+        let (reserve0, reserve1) = self.pairs.get(&position.pair)?.reserves;
+        // token1 per token0
+        let price = u256_to_f64(reserve1) / u256_to_f64(reserve0);
+        let lp_value = u256_to_f64(amount0.saturating_sub(fee0)) * price
+            + u256_to_f64(amount1.saturating_sub(fee1));
+        let hodl_value = u256_to_f64(position.hodl.0) * price + u256_to_f64(position.hodl.1);
+        match hodl_value > 0.0 && price.is_finite() {
+            true => Some(lp_value / hodl_value - 1.0),
+            false => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(n: u64) -> H160 {
+        H160::from_low_u64_be(n)
+    }
+
+    // 100 of 1000 LP tokens of a pair, deposited as 100 of each token at
+    // `entry_growth`.
+    fn book(reserves: (u64, u64), entry_growth: u64) -> (LpBook, LpPosition) {
+        let mut book = LpBook::new();
+        let tokens = (address(1), address(2));
+        book.pairs.insert(address(0xaa), PairState {
+            tokens,
+            reserves: (U256::from(reserves.0), U256::from(reserves.1)),
+            total_supply: U256::from(1000),
+        });
+        let position = LpPosition {
+            owner: address(0xbb),
+            pair: address(0xaa),
+            tokens,
+            liquidity: U256::from(100),
+            deposited: (U256::from(100), U256::from(100)),
+            withdrawn: (U256::zero(), U256::zero()),
+            hodl: (U256::from(100), U256::from(100)),
+            entry_growth: U256::from(entry_growth) * U256::from(GROWTH_ONE),
+        };
+        (book, position)
+    }
+
+    #[test]
+    fn price_moves_without_fees() {
+        // sqrt(k) per LP token still 1, token0 at a quarter of its entry price
+        let (book, position) = book((2000, 500), 1);
+        assert_eq!(book.underlying(&position), Some((U256::from(200), U256::from(50))));
+        assert_eq!(book.fee_income(&position), Some((U256::zero(), U256::zero())));
+        let loss = book.impermanent_loss(&position).unwrap();
+        assert!((loss + 0.2).abs() < 1e-12, "{}", loss);
+    }
+
+    #[test]
+    fn fees_are_the_growth_since_entry() {
+        // sqrt(k) per LP token doubled since the deposit, at the same price
+        let (book, mut position) = book((4000, 4000), 2);
+        position.hodl = (U256::from(200), U256::from(200));
+        assert_eq!(book.fee_income(&position), Some((U256::from(200), U256::from(200))));
+        assert_eq!(book.impermanent_loss(&position), Some(0.0));
+    }
+
+    #[test]
+    fn unknown_entries_earn_no_fees() {
+        let (book, position) = book((4000, 4000), 0);
+        assert_eq!(book.fee_income(&position), Some((U256::zero(), U256::zero())));
+    }
+
+    fn add(amount: u64, liquidity: u64, reserve: u64) -> LiquidityEvent {
+        LiquidityEvent {
+            kind: LiquidityKind::Add,
+            pair: address(0xaa),
+            log_index: 0,
+            sender: address(0xcc),
+            to: address(0xbb),
+            tokens: (address(1), address(2)),
+            amount0: U256::from(amount),
+            amount1: U256::from(amount),
+            liquidity: U256::from(liquidity),
+            reserves: (U256::from(reserve), U256::from(reserve)),
+        }
+    }
+
+    #[test]
+    fn entry_growth_is_weighted_by_liquidity() {
+        let mut book = LpBook::new();
+        // first mint at sqrt(k) per LP token of exactly 1
+        book.apply(address(0xbb), &add(10_000, 9_000, 10_000));
+        let entry = |book: &LpBook| book.positions[&(address(0xbb), address(0xaa))].entry_growth;
+        assert_eq!(entry(&book), U256::from(GROWTH_ONE));
+        // fees grow the reserves by 21% before a 10% top up
+        book.pairs.get_mut(&address(0xaa)).unwrap().reserves = (U256::from(12_100), U256::from(12_100));
+        book.apply(address(0xbb), &add(1_210, 1_000, 13_310));
+        assert_eq!(book.pairs[&address(0xaa)].total_supply, U256::from(11_000));
+        // (1 * 9000 + 1.21 * 1000) / 10000
+        assert_eq!(entry(&book), U256::from(1_021) * U256::exp10(15));
+
+        let position = &book.positions[&(address(0xbb), address(0xaa))];
+        assert_eq!(book.underlying(position), Some((U256::from(12_100), U256::from(12_100))));
+        // 12100 * (1.21 - 1.021) / 1.21
+        assert_eq!(book.fee_income(position), Some((U256::from(1_890), U256::from(1_890))));
+    }
+}
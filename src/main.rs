@@ -22,50 +22,253 @@
 //          Pool addr for each token
 //          New pool ratio after each trade
 
+mod cli;
+
 use std::collections::HashMap;
+use std::error::Error;
+use std::sync::Arc;
+use std::time::Duration;
 use hex::FromHex;
+use clap::{CommandFactory, Parser};
+use clap::error::ErrorKind;
+use futures::StreamExt;
 
 use web3::types::{
     H160,
-    H256, 
+    H256,
+    U256,
+    Transaction,
 };
 
 use eth_explo::{
     read_uniswap_tx,
+    read_log_swaps,
+    PairTokens,
+    UniswapTx,
     u256_to_f64,
+    I256,
     Trader,
-    read_blocks,
-    read_receipt,
-    Amm,
+    TraderLabel,
+    Sandwich,
+    find_sandwiches,
+    Arbitrage,
+    find_arbitrages,
+    CostBasis,
+    CostMethod,
+    TokenInfo,
+    TokenRegistry,
+    load_blocks,
+    TxFilter,
+    Dex,
+    DecodeError,
+    SwapMethod,
+    LiquidityMethod,
+    LiquidityEvent,
+    LiquidityKind,
+    LpBook,
+    read_liquidity_events,
+    read_liquidity_tx,
+    read_v3_log_swaps,
+    read_v3_tx,
+    is_v3_router_call,
+    V3Book,
+    V3SwapMethod,
+    DexKind,
+    DexRegistry,
+    PoolRegistry,
+    PoolGraph,
+    Trade,
+    TxContext,
+    Checkpoint,
+    ScanState,
+    Store,
+    PoolRecord,
+    TradeRecord,
+    TraderRecord,
+    write_records,
+    GasCost,
 };
-#[allow(non_snake_case)]
+#[cfg(feature = "columnar")]
+use eth_explo::columnar;
+use eth_explo::fetch::{fetch_range, FetchConfig};
+
+use cli::{Cli, Command, ExportArgs, OutputArgs, ScanArgs, TokenArgs};
+
+// Everything the block scan produces, consumed by the output subcommands.
+struct Scan {
+    uniswap_pools: PoolRegistry,
+    trader_map: HashMap<H160, Trader>,
+    receipts_missed: usize,
+    decode_errors: HashMap<&'static str, usize>,
+    captured_trade: usize,
+    captured_by_method: HashMap<&'static str, usize>,
+    captured_by_venue: HashMap<String, usize>,
+    unpriced_trade: usize,
+    lp_book: LpBook,
+    v3_book: V3Book,
+    liquidity_events: HashMap<&'static str, usize>,
+}
 
 #[tokio::main]
-async fn main() -> web3::Result<()> {
-    let debug = false;
+async fn main() -> Result<(), Box<dyn Error>> {
     dotenv::dotenv().ok();
+    let cli = Cli::parse();
+
+    let (range_args, token_args) = match &cli.command {
+        Command::Analyze { scan, tokens, .. }
+        | Command::Pools { scan, tokens, .. }
+        | Command::Traders { scan, tokens, .. } => (&scan.range, Some(tokens)),
+        Command::Fetch { range, .. } => (range, None),
+    };
+    if let Err(msg) = range_args.validate() {
+        Cli::command().error(ErrorKind::ValueValidation, msg).exit();
+    }
+
+    // Eth does not have an address, so just use WETH address instead
+    let weth_addr = H160::from_slice(Vec::from_hex(
+        "c02aaa39b223fe8d0a0e5c4f27ead9083c756cc2").unwrap()
+            .as_slice());
+
+    let tokens = match token_args.map(|args| load_tokens(args, weth_addr)) {
+        Some(Ok(tokens)) => tokens,
+        Some(Err(e)) => Cli::command().error(ErrorKind::Io, e).exit(),
+        None => TokenRegistry::new(),
+    };
+
+    match &cli.command {
+        Command::Analyze { scan, output, export, .. } => {
+            let mut scanned = scan_blocks(scan, weth_addr).await;
+            settle_traders(&mut scanned.trader_map, &scanned.uniswap_pools, &scanned.v3_book,
+                           &scanned.lp_book, output.cost_basis, weth_addr);
+            let sandwiches = label_sandwiches(&mut scanned.trader_map, &scanned.uniswap_pools, weth_addr);
+            let arbitrages = label_arbitrages(&mut scanned.trader_map, &scanned.uniswap_pools, weth_addr);
+            if let Err(e) = export_results(export, &scanned, true, true) {
+                Cli::command().error(ErrorKind::Io, e).exit();
+            }
+            print_pools(&scanned.uniswap_pools, &scanned.v3_book, &tokens);
+            print_traders(&scanned.trader_map, &arbitrages, output, &tokens, weth_addr);
+            print_arbitrages(&arbitrages, &tokens, weth_addr);
+            print_sandwiches(&sandwiches, &tokens, weth_addr);
+            print_lp_positions(&scanned.lp_book, &tokens);
+            print_summary(&scanned);
+            println!("debug_all_addr: {}", scan.debug_all_addr);
+        },
+        Command::Pools { scan, export, .. } => {
+            let scanned = scan_blocks(scan, weth_addr).await;
+            if let Err(e) = export_results(export, &scanned, false, true) {
+                Cli::command().error(ErrorKind::Io, e).exit();
+            }
+            print_pools(&scanned.uniswap_pools, &scanned.v3_book, &tokens);
+        },
+        Command::Traders { scan, output, export, .. } => {
+            let mut scanned = scan_blocks(scan, weth_addr).await;
+            settle_traders(&mut scanned.trader_map, &scanned.uniswap_pools, &scanned.v3_book,
+                           &scanned.lp_book, output.cost_basis, weth_addr);
+            label_sandwiches(&mut scanned.trader_map, &scanned.uniswap_pools, weth_addr);
+            let arbitrages = label_arbitrages(&mut scanned.trader_map, &scanned.uniswap_pools, weth_addr);
+            if let Err(e) = export_results(export, &scanned, true, false) {
+                Cli::command().error(ErrorKind::Io, e).exit();
+            }
+            print_traders(&scanned.trader_map, &arbitrages, output, &tokens, weth_addr);
+        },
+        Command::Fetch { range, fetch } => {
+            let config = FetchConfig {
+                start_block: range.start_block,
+                end_block: range.end_block,
+                blocks_dir: range.blocks_dir.clone(),
+                receipts_dir: range.receipts_dir.clone(),
+                batch_size: fetch.batch_size,
+                concurrency: fetch.concurrency,
+                max_retries: fetch.max_retries,
+                retry_delay: Duration::from_millis(fetch.retry_delay_ms),
+                only_to: fetch.only_to.clone(),
+                print_progress: fetch.print_progress,
+            };
+            let transport = web3::transports::Http::new(&fetch.rpc_url)?;
+            let stats = fetch_range(&transport, &config).await?;
+            println!("blocks fetched:   {} (skipped {})", stats.blocks_fetched, stats.blocks_skipped);
+            println!("receipts fetched: {} (skipped {})", stats.receipts_fetched, stats.receipts_skipped);
+            println!("retries:          {}", stats.retries);
+        },
+    }
+    Ok(())
+}
+
+// Token metadata from the optional token list and decimals cache. WETH is
+// always known since every valuation is denominated in it.
+fn load_tokens(args: &TokenArgs, weth_addr: H160) -> Result<TokenRegistry, String> {
+    let mut tokens = match &args.tokens {
+        Some(path) => TokenRegistry::load(path)
+            .map_err(|e| format!("could not load token list {}: {}", path.display(), e))?,
+        None => TokenRegistry::new(),
+    };
+    if let Some(path) = &args.decimals_cache {
+        tokens.load_decimals_cache(path)
+            .map_err(|e| format!("could not load decimals cache {}: {}", path.display(), e))?;
+    }
+    if !tokens.contains(&weth_addr) {
+        tokens.insert(TokenInfo {
+            address: weth_addr,
+            symbol: "WETH".to_string(),
+            name: "Wrapped Ether".to_string(),
+            decimals: 18,
+        });
+    }
+    Ok(tokens)
+}
+
+// Uniswap V2 and V3 plus the forks from the optional DEX config.
+fn load_dexes(args: &ScanArgs) -> Result<DexRegistry, String> {
+    let mut dexes = DexRegistry::mainnet();
+    if let Some(path) = &args.dexes {
+        dexes.load_json(path)
+            .map_err(|e| format!("could not load dex config {}: {}", path.display(), e))?;
+    }
+    Ok(dexes)
+}
+
+// Checkpoint of the store to resume from, None for a new store. The requested
+// start has to fall within the blocks the store covers, so no block is skipped
+// or counted twice, and the decoding mode has to match.
+fn resume_point(store: &Store, args: &ScanArgs) -> Result<Option<Checkpoint>, String> {
+    let checkpoint = match store.checkpoint().map_err(|e| e.to_string())? {
+        Some(checkpoint) => checkpoint,
+        None => return Ok(None),
+    };
+    if checkpoint.from_logs != args.from_logs {
+        return Err(format!("the database was scanned {} --from-logs",
+                           match checkpoint.from_logs { true => "with", false => "without" }));
+    }
+    let start_block = args.range.start_block;
+    match start_block >= checkpoint.first_block && start_block <= checkpoint.next_block {
+        true => Ok(Some(checkpoint)),
+        false => Err(format!("the database holds blocks {} up to {}, start the scan within them",
+                             checkpoint.first_block, checkpoint.next_block)),
+    }
+}
+
+// Router the transaction was sent to, if it belongs to a registered DEX of
+// that kind and calls one of the decoded methods.
+fn router_dex<'a>(dexes: &'a DexRegistry, tx: &Transaction, kind: DexKind) -> Option<&'a Dex> {
+    tx.to
+        .and_then(|to| dexes.by_router(&to))
+        .filter(|dex| dex.kind == kind)
+        .filter(|_| match kind {
+            DexKind::V2 => tx.input.0.len() >= 4
+                && (SwapMethod::from_selector(&tx.input.0[0..4]).is_some()
+                    || LiquidityMethod::from_selector(&tx.input.0[0..4]).is_some()),
+            DexKind::V3 => is_v3_router_call(&tx.input.0),
+        })
+}
+
+async fn scan_blocks(args: &ScanArgs, weth_addr: H160) -> Scan {
+    let debug = args.verbose;
+    let dexes = match load_dexes(args) {
+        Ok(dexes) => dexes,
+        Err(e) => Cli::command().error(ErrorKind::Io, e).exit(),
+    };
 
-    // Set method ids for input functions, hash of first 8 hex digits in keccak hash
-    let swapExactETHForTokens = "7ff36ab5";
-    let swapETHForExactTokens = "fb3bdb41";
-
-    let eth_for_ids: [&str; 2] = [
-        swapExactETHForTokens,
-        swapETHForExactTokens
-    ];
-
-    let swapExactTokensForTokens = "38ed1739";
-    let swapExactTokensForETH = "18cbafe5";
-    let swapTokensForExactTokens = "8803dbee";
-    let swapExactTokensForETHSupportingFeeOnTransferTokens = "791ac947";
-    let method_ids: [&str; 6] = [
-        swapExactETHForTokens,
-        swapETHForExactTokens,
-        swapExactTokensForTokens,
-        swapExactTokensForETH,
-        swapTokensForExactTokens,
-        swapExactTokensForETHSupportingFeeOnTransferTokens
-    ];
+    // Router method ids are matched by SwapMethod::from_selector.
     // Set function IDs for functions called in logs.
     let deposit_fid = H256::from_slice(Vec::from_hex(
             "e1fffcc4923d04b559f4d29a8bfc6cda04eb5b0d3c460751c2402c5c5cc9109c").unwrap()
@@ -85,205 +288,675 @@ async fn main() -> web3::Result<()> {
     let approval_fid = H256::from_slice(Vec::from_hex(
             "8c5be1e5ebec7d5bd14f71427d1e84f3dd0314c0f7b2291e5b200ac8c7c3b925").unwrap()
             .as_slice());
+    let mint_fid = H256::from_slice(Vec::from_hex(
+            "4c209b5fc8ad50758f13e2e1088ba56a560dff690a1c6fef26394f4c03821c4f").unwrap()
+            .as_slice());
+    let burn_fid = H256::from_slice(Vec::from_hex(
+            "dccd412f0b1252819cb1fd330b93224ca42612892bb3f4f789976e6d81936496").unwrap()
+            .as_slice());
+    let v3_swap_fid = H256::from_slice(Vec::from_hex(
+            "c42079f94a6350d7e6235f29174924f928cc2ac818eb64fed8004e115fbcca67").unwrap()
+            .as_slice());
+    let v3_mint_fid = H256::from_slice(Vec::from_hex(
+            "7a53080ba414158be7ec69b987b5fb7d07dee101fe85488f0853ae16239d0bde").unwrap()
+            .as_slice());
+    let v3_burn_fid = H256::from_slice(Vec::from_hex(
+            "0c396cd989a39f4459b5fa1aed6a9a8dcdbc45908acfd67e028cd568da98982c").unwrap()
+            .as_slice());
+    let v3_initialize_fid = H256::from_slice(Vec::from_hex(
+            "98636036cb66a9c19a37435efc1e90142190214e8abeb821bdba3f2990dd4c95").unwrap()
+            .as_slice());
     let fid_vec = vec![deposit_fid, 
                         transfer_fid, 
                         sync_fid, 
                         swap_fid, 
                         withdrawal_fid,
-                        approval_fid];
+                        approval_fid,
+                        mint_fid,
+                        burn_fid,
+                        v3_swap_fid,
+                        v3_mint_fid,
+                        v3_burn_fid,
+                        v3_initialize_fid];
 
-    // Eth does not have an address, so just use WETH address instead
-    let weth_addr = H160::from_slice(Vec::from_hex(
-        "c02aaa39b223fe8d0a0e5c4f27ead9083c756cc2").unwrap()
-            .as_slice());
-
-    let debug_addr: Option<H160> = None;
+    let debug_addr: Option<H160> = args.debug_addr;
     let mut is_debug_addr: bool;
-//    let debug_addr: Option<H160>= Some(H160::from_slice(
-//        &hex::decode(b"1d24b133bdc88906d38d9943503a13364f4184b9").unwrap()));
-    let debug_all_addr = false;
+    let debug_all_addr = args.debug_all_addr;
 
 
-    // Uniswap Address
-    let uniswap_addr = H160::from_slice(
-        &hex::decode(b"7a250d5630B4cF539739dF2C5dAcb4c659F2488D").unwrap());    
 
     // Set up trader tracking and uniswap tracking
-    // uniswap_pools is: coin address, ratio to weth
-    // initialize weth ratio to self as 1.0
-    let mut uniswap_pools: HashMap<H160, Amm> = HashMap::new();
-    uniswap_pools.insert(weth_addr, Amm::new(weth_addr, weth_addr, 1.0_f64, 1.0_f64));
+    // uniswap_pools is: pair address, reserves of the pair
+    let mut uniswap_pools = PoolRegistry::new(dexes.clone());
     let mut trader_map: HashMap<H160, Trader> = HashMap::new();
+    let mut lp_book = LpBook::new();
+    let mut v3_book = V3Book::new();
 
     // Block range
-    // Min saved is: 14508547
-    // Max saved is: 14518566
-    let start_block = 14508547_u64;
-    let end_block = 14518566_u64;
-    let n_blocks = end_block - start_block;
+    let mut start_block = args.range.start_block;
+    let end_block = args.range.end_block;
 
-    // Track trades captured and missed by ignoring coins with no Weth pairing
+    // Track trades captured, and the ones whose sold coin has no weth value
     let mut captured_trade = 0;
-    let mut missed_trade = 0;
+    let mut captured_by_method: HashMap<&'static str, usize> = HashMap::new();
+    let mut captured_by_venue: HashMap<String, usize> = HashMap::new();
+    let mut unpriced_trade = 0;
+    let mut liquidity_events: HashMap<&'static str, usize> = HashMap::new();
 
-    let print_terminal = false;
+    let print_terminal = args.print_progress;
 
     let mut receipts_missed = 0;
-    for number in start_block..end_block {
+    // Transactions skipped because they could not be decoded, by error kind
+    let mut decode_errors: HashMap<&'static str, usize> = HashMap::new();
+    // token0/token1 of the pairs met so far
+    let mut pair_tokens: PairTokens = HashMap::new();
+
+    // A store that has seen earlier blocks hands back their state, and the
+    // scan carries on after its checkpoint
+    let mut store = args.db.as_ref().map(|path| match Store::open(path) {
+        Ok(store) => store,
+        Err(e) => Cli::command().error(ErrorKind::Io,
+                                       format!("could not open {}: {}", path.display(), e)).exit(),
+    });
+    let checkpoint = match store.as_ref().map(|store| resume_point(store, args)).transpose() {
+        Ok(checkpoint) => checkpoint.flatten(),
+        Err(e) => Cli::command().error(ErrorKind::ValueValidation, e).exit(),
+    };
+    if let (Some(store), Some(checkpoint)) = (&store, checkpoint) {
+        let restored = match store.restore(PoolRegistry::new(dexes.clone())) {
+            Ok(restored) => restored,
+            Err(e) => Cli::command().error(ErrorKind::Io, format!("could not restore the scan: {}", e)).exit(),
+        };
+        trader_map = restored.traders;
+        uniswap_pools = restored.pools;
+        pair_tokens = restored.pair_tokens;
+        lp_book = restored.lp_book;
+        v3_book = restored.v3_book;
+        start_block = checkpoint.next_block;
+        eprintln!("resuming from the checkpoint at block {}", start_block);
+    }
+    let first_block = checkpoint.map_or(start_block, |checkpoint| checkpoint.first_block);
+    // trades and snapshots from here on are not in the store yet
+    let mut unsaved_block = start_block;
+    let n_blocks = end_block.saturating_sub(start_block);
+
+    // Blocks and receipts are read and parsed ahead on several threads, and
+    // applied here one at a time in block order
+    let select_dexes = dexes.clone();
+    let from_logs = args.from_logs;
+    let select: TxFilter = Arc::new(move |tx| from_logs
+        || router_dex(&select_dexes, tx, DexKind::V2).is_some()
+        || router_dex(&select_dexes, tx, DexKind::V3).is_some());
+    let jobs = args.jobs.unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get()));
+    let mut blocks = Box::pin(load_blocks(args.range.blocks_dir.clone(), args.range.receipts_dir.clone(),
+                                          start_block..end_block, jobs, select));
+    while let Some(loaded) = blocks.next().await {
+        let loaded = match loaded {
+            Ok(loaded) => loaded,
+            Err(e) => Cli::command().error(ErrorKind::Io, e).exit(),
+        };
+        let number = loaded.number;
         if print_terminal { println!("block {} of {}", number - start_block, n_blocks); }
-//        println!("Scanning block {}     of  {}", number - start_block, n_blocks);
-        let block_path = format!("../../testy/blocks/{}.json", number);
-        let batched_blocks = read_blocks(&block_path).unwrap();
-
-        for tx in batched_blocks.transactions.iter()//.flat_map(|b| &b.transactions)
-            .filter(|tx| tx.to.is_some() && tx.to.unwrap() == uniswap_addr &&
-                    !tx.input.0.is_empty() &&
-                    method_ids.contains(&hex::encode(&tx.input.0[0..4]).as_str()))
-        {
-            if debug_addr.is_some() && debug_addr.unwrap() == tx.from.unwrap() {
+        for (tx, receipt) in &loaded.transactions {
+            if debug_addr.is_some() && debug_addr == tx.from {
                 is_debug_addr = true;
                 println!("BLOCK FOR DEBUG ADDRESS: {}", number);
             } else { is_debug_addr = false; }
             if debug { println!("OK TX: {:?}", tx.hash); }
-            let path_receipt = format!("../../testy/receipts/{}_{:?}.json", 
-                                       tx.block_number.unwrap(), tx.hash);
-            let receipt = read_receipt(path_receipt).ok();
-            if receipt.is_none() {
-                if is_debug_addr {println!("Missed for debug addr: {:?}", tx.hash);}
-                receipts_missed += 1;
-                continue
-            }
-            let receipt = receipt.unwrap();
-
-            if receipt.logs.last().is_some() {
-                if debug_all_addr { println!("debug address: {:?}", tx.from); }
-                if debug { println!("/tOK Receipt: {:?}", receipt.transaction_hash); }
-                if is_debug_addr || debug_all_addr { println!("tx_hash: {:?}", receipt.transaction_hash); }
-                let extracted_uniswap = read_uniswap_tx(&tx, 
-                                    &receipt, 
-                                    &fid_vec, 
-                                    &eth_for_ids,
-                                    debug_addr.as_ref(),
-                                    &method_ids).unwrap();
-                let (start_token, start_amount, end_token, end_amount, 
-                     _receiving_addr, pool_ratios) = &extracted_uniswap;
+            let receipt = match receipt {
+                Some(receipt) => receipt,
+                None => {
+                    if is_debug_addr {println!("Missed for debug addr: {:?}", tx.hash);}
+                    receipts_missed += 1;
+                    continue
+                },
+            };
+
+            if debug_all_addr { println!("debug address: {:?}", tx.from); }
+            if debug { println!("/tOK Receipt: {:?}", receipt.transaction_hash); }
+            if is_debug_addr || debug_all_addr { println!("tx_hash: {:?}", receipt.transaction_hash); }
+            let v2_dex = router_dex(&dexes, tx, DexKind::V2);
+            let v3_dex = router_dex(&dexes, tx, DexKind::V3);
+            let decoded = match (args.from_logs, v2_dex, v3_dex) {
+                (true, _, _) => read_log_swaps(receipt, &fid_vec, &dexes, &mut pair_tokens)
+                    .and_then(|mut extracted_swaps| {
+                        extracted_swaps.extend(read_v3_log_swaps(receipt, &fid_vec, &dexes,
+                                                                 &mut pair_tokens)?);
+                        Ok((extracted_swaps, read_liquidity_events(
+                            &receipt.logs, &fid_vec, &mut pair_tokens)?))
+                    }),
+                (false, _, Some(dex)) =>
+                    read_v3_tx(tx, receipt, &fid_vec, dex, &mut pair_tokens, &mut v3_book)
+                        .map(|extracted_swaps| (extracted_swaps, vec![])),
+                (false, Some(dex), None) => match LiquidityMethod::from_selector(&tx.input.0[0..4]) {
+                    Some(_) => read_liquidity_tx(tx, receipt, &fid_vec, &mut pair_tokens)
+                        .map(|(_call, events)| (vec![], events)),
+                    None => read_uniswap_tx(tx, receipt, &fid_vec, dex, debug_addr.as_ref())
+                        .map(|extracted_uniswap| (vec![extracted_uniswap], vec![])),
+                },
+                (false, None, None) => unreachable!("filtered to router transactions"),
+            };
+            let tx_index = receipt.transaction_index.as_u64();
+            let (extracted_swaps, extracted_liquidity) = match decoded {
+                Ok(decoded) => decoded,
+                Err(e) => {
+                    // reverted swaps are routine, only spell them out when asked
+                    if e != DecodeError::Reverted || debug || is_debug_addr || debug_all_addr {
+                        eprintln!("skipping tx {:?} in block {}: {}", tx.hash, number, e);
+                    }
+                    *decode_errors.entry(e.kind()).or_insert(0) += 1;
+                    lp_book.observe_syncs(&receipt.logs, &fid_vec);
+                    v3_book.observe_logs(&receipt.logs, &fid_vec, &dexes, &mut pair_tokens);
+                    uniswap_pools.observe_syncs(number, tx_index, &receipt.logs, &fid_vec, &pair_tokens);
+                    continue
+                },
+            };
+            // log mode counts swaps by how the tx reached the pairs
+            let method_name = match (v2_dex.is_some(), v3_dex.is_some()) {
+                (true, _) => SwapMethod::from_selector(&tx.input.0[0..4])
+                    .map_or("pair logs (other)", |method| method.name()),
+                (false, true) => V3SwapMethod::from_selector(&tx.input.0[0..4])
+                    .map_or("multicall", |(method, _)| method.name()),
+                (false, false) => "pair logs (direct)",
+            };
+
+            let tx_context = TxContext {
+                hash: tx.hash,
+                block: number,
+                tx_index,
+                from: receipt.from,
+                gas: GasCost::of(tx, receipt, loaded.base_fee_per_gas),
+            };
+            // gas is paid once per transaction however many swaps it made
+            let mut gas_booked = false;
+            // swaps are valued at the pools as the transaction found them
+            let graph = PoolGraph::new(&uniswap_pools, &v3_book);
+            for extracted_uniswap in &extracted_swaps {
+                let pool_ratios = &extracted_uniswap.5;
                 if debug_all_addr { println!("{:?}", pool_ratios); }
-                let start_token = start_token.unwrap_or(weth_addr);
-                let end_token = end_token.unwrap();
-                if is_debug_addr { 
-                    println!("start_amt: {}, end_amt: {}", start_amount, end_amount);
+                if is_debug_addr {
+                    println!("start_amt: {}, end_amt: {}", extracted_uniswap.1, extracted_uniswap.3);
                 }
-
-                let mut trader = trader_map.entry(tx.from.unwrap())
-                    .or_insert(Trader::new());
+                let trader = trader_map.entry(receipt.from)
+                    .or_default();
                 trader.address = receipt.from;
 
-                // only track coins which include a weth-coin pair
-                if uniswap_pools.contains_key(&start_token)
-                    || (uniswap_pools.contains_key(&start_token) 
-                        && uniswap_pools.contains_key(&end_token))
-                    || (start_token == weth_addr && uniswap_pools.contains_key(&end_token)) {
-                    captured_trade += 1;
-                    trader.holdings.entry(start_token)
-                        .and_modify(|cum_token_amt| *cum_token_amt =- start_amount)
-                        .or_insert(-1.0 * start_amount); 
-                    trader.holdings.entry(end_token)
-                        .and_modify(|cum_token_amt| *cum_token_amt += end_amount)
-                        .or_insert(*end_amount);
-                    trader.hist_cost += match start_token == weth_addr {
-                        true => *start_amount,
-                        false => match uniswap_pools.contains_key(&start_token) {
-                            true => uniswap_pools[&start_token].uniswap_immut(start_token, *start_amount),
-                            false => panic!("Invalid start token."), },
-                    };
-                    if is_debug_addr || debug_all_addr { 
-                        println!("trader at block {} and tx {:?} : {:?}",number, tx.hash, trader); 
-                    }
-                    trader.cum_gas += u256_to_f64(receipt.gas_used
-                        .expect("every successful transaction requires gas"));
-                    trader.cum_txs += 1_usize;
-
-                    let alt_coin = match start_token == weth_addr {
-                        true => Some(end_token),
-                        false => match end_token == weth_addr {
-                            true => Some(start_token),
-                            false => None,
-                        }
-                    };
-                    if alt_coin.is_some() {
-                        let alt_coin = alt_coin.unwrap();
-                        for ((coin0, coin1), (amt0, amt1)) in pool_ratios.iter()
-                                .filter(|((coin0, coin1), (_, _))| 
-                                    (*coin0, *coin1) == (weth_addr, alt_coin)
-                                    || (*coin1, *coin0) == (weth_addr, alt_coin)) {
-                            uniswap_pools.insert(alt_coin, Amm::new(*coin0, *coin1, *amt0, *amt1));
-                        }
-                        if debug_all_addr {println!("{:?}", uniswap_pools.get(&alt_coin)); }
-                    }
-                } else {
-                    missed_trade += 1;
+                // swaps of coins without a weth value are booked all the same, with no cost
+                let cost = trade_cost(extracted_uniswap, &uniswap_pools, &v3_book, &graph, weth_addr);
+                if cost.is_none() {
+                    unpriced_trade += 1;
+                }
+                let gas = match gas_booked {
+                    true => GasCost::default(),
+                    false => tx_context.gas,
+                };
+                gas_booked = true;
+                trader.record(Trade::new(&tx_context, extracted_uniswap, cost, gas));
+                captured_trade += 1;
+                *captured_by_method.entry(method_name).or_insert(0) += 1;
+                *captured_by_venue.entry(extracted_uniswap.6.clone()).or_insert(0) += 1;
+                if is_debug_addr || debug_all_addr {
+                    println!("trader at block {} and tx {:?} : {:?}",number, tx.hash, trader);
                 }
             }
-
+            let mut moved_liquidity = false;
+            for event in &extracted_liquidity {
+                let trader = trader_map.entry(receipt.from)
+                    .or_default();
+                trader.address = receipt.from;
+                apply_liquidity(trader, &mut lp_book, event);
+                *liquidity_events.entry(event.kind.name()).or_insert(0) += 1;
+                moved_liquidity = true;
+                if is_debug_addr || debug_all_addr {
+                    println!("liquidity at block {} and tx {:?} : {:?}", number, tx.hash, event);
+                }
+            }
+            // reserves after the whole transaction for the pairs with LP positions
+            lp_book.observe_syncs(&receipt.logs, &fid_vec);
+            v3_book.observe_logs(&receipt.logs, &fid_vec, &dexes, &mut pair_tokens);
+            // the router path tells the tokens of pairs the logs did not
+            for hop in extracted_swaps.iter().flat_map(|extracted| &extracted.5) {
+                pair_tokens.entry(hop.pool).or_insert(hop.tokens);
+            }
+            uniswap_pools.observe_syncs(number, tx_index, &receipt.logs, &fid_vec, &pair_tokens);
+            // transactions that only moved liquidity have no trade to carry the gas
+            if moved_liquidity && !gas_booked {
+                let trader = trader_map.get_mut(&receipt.from)
+                    .expect("trader was added with the liquidity event");
+                trader.pay_gas(&tx_context.gas);
+                trader.cum_txs += 1_usize;
+            }
+        }
+        let scanned_blocks = number + 1 - start_block;
+        let due = number + 1 == end_block
+            || (args.checkpoint_every > 0 && scanned_blocks.is_multiple_of(args.checkpoint_every));
+        if let (Some(store), true) = (&mut store, due) {
+            let state = ScanState {
+                traders: &trader_map,
+                pools: &uniswap_pools,
+                pair_tokens: &pair_tokens,
+                lp_book: &lp_book,
+                v3_book: &v3_book,
+            };
+            let checkpoint = Checkpoint { first_block, next_block: number + 1, from_logs: args.from_logs };
+            if let Err(e) = store.save(state, checkpoint, unsaved_block) {
+                Cli::command().error(ErrorKind::Io, format!("could not save block {}: {}", number, e)).exit();
+            }
+            unsaved_block = number + 1;
         }
     }
-    for entry in &uniswap_pools {
-        println!("{:?}", entry);
+    Scan {
+        uniswap_pools,
+        trader_map,
+        receipts_missed,
+        decode_errors,
+        captured_trade,
+        captured_by_method,
+        captured_by_venue,
+        unpriced_trade,
+        lp_book,
+        v3_book,
+        liquidity_events,
     }
+}
+
+// Weth value of what a swap sold, at the pool state before the transaction.
+// None when the sold token cannot be valued in weth; the swap is still booked,
+// without a cost, and counted as unpriced.
+fn trade_cost(extracted_uniswap: &UniswapTx, uniswap_pools: &PoolRegistry, v3_book: &V3Book,
+              graph: &PoolGraph, weth_addr: H160) -> Option<U256> {
+    let (start_token, start_amount, ..) = extracted_uniswap;
+    // only track coins which include a weth-coin pair
+    weth_value(start_token.unwrap_or(weth_addr), *start_amount, uniswap_pools, v3_book, graph,
+               weth_addr)
+}
 
-    let cloned_trader_map = trader_map.clone();
+// Move the deposited or withdrawn pair tokens in or out of the trader's
+// holdings and book the LP tokens.
+fn apply_liquidity(trader: &mut Trader, lp_book: &mut LpBook, event: &LiquidityEvent) {
+    lp_book.apply(trader.address, event);
+    for (token, amount) in [(event.tokens.0, event.amount0), (event.tokens.1, event.amount1)] {
+        let change = match event.kind {
+            LiquidityKind::Add => -I256::from(amount),
+            LiquidityKind::Remove => I256::from(amount),
+        };
+        *trader.holdings.entry(token).or_insert(I256::zero()) += change;
+    }
+}
+
+// Weth an amount of coin sells for at the current pool state: through the
+// deepest v2 weth pool, else the deepest v3 weth pool, else the best route
+// through other tokens for coins without a weth pool. `graph` indexes the same
+// pools and is built once per valuation pass.
+fn weth_value(coin: H160, amount: U256, uniswap_pools: &PoolRegistry, v3_book: &V3Book,
+              graph: &PoolGraph, weth_addr: H160) -> Option<U256> {
+    if coin == weth_addr {
+        return Some(amount);
+    }
+    match uniswap_pools.quote(coin, weth_addr, amount) {
+        Some(value) => Some(value),
+        None => match v3_book.deepest_pool(coin, weth_addr) {
+            Some(pool) => pool.quote(coin, amount),
+            None => graph.quote(coin, weth_addr, amount),
+        },
+    }
+}
+
+// Value every trader's holdings in weth at the final pool state and derive the
+// profit and return figures from it. The ledger is replayed with `cost_method`
+// to split the PnL into realized and unrealized.
+fn settle_traders(trader_map: &mut HashMap<H160, Trader>,
+                  uniswap_pools: &PoolRegistry,
+                  v3_book: &V3Book,
+                  lp_book: &LpBook,
+                  cost_method: CostMethod,
+                  weth_addr: H160) {
+    let graph = PoolGraph::new(uniswap_pools, v3_book);
+    for t in trader_map.values_mut() {
+        let basis = CostBasis::from_trades(cost_method, weth_addr, &t.trades);
+        t.positions = basis.positions(|coin, amount|
+            weth_value(coin, amount, uniswap_pools, v3_book, &graph, weth_addr));
+        t.realized_pnl = basis.realized();
+        // open lots that cannot be priced are left out
+        t.unrealized_pnl = t.positions.iter()
+            .filter_map(|position| position.unrealized)
+            .fold(I256::zero(), |acc, pnl| acc + pnl);
+    }
+
+    // LP tokens are valued as their share of the pair reserves
+    let mut cloned_trader_map = trader_map.clone();
+    for t in cloned_trader_map.values_mut() {
+        for position in lp_book.positions_of(t.address) {
+            if let Some((amount0, amount1)) = lp_book.underlying(position) {
+                *t.holdings.entry(position.tokens.0).or_insert(I256::zero()) += I256::from(amount0);
+                *t.holdings.entry(position.tokens.1).or_insert(I256::zero()) += I256::from(amount1);
+            }
+        }
+    }
     let trader_coin_totals = cloned_trader_map.iter()
         .map(|(address, t)| (address, t.holdings.iter()
-            .map(|(coin, amt)| (coin, match *coin == weth_addr { 
-                false => match uniswap_pools.get(coin) {
-                    Some(pool) => match *amt > 0.0 {
-                        true => Some(pool.uniswap_immut(*coin, amt.abs())),
-                        false => Some(-1.0 * pool.uniswap_immut(*coin, amt.abs())),
-                    },
-                    None => None },
-                true => Some(*amt)}))
+            .map(|(coin, amt)| (coin, weth_value(*coin, amt.abs(), uniswap_pools, v3_book, &graph, weth_addr)
+                .map(|value| match amt.is_negative() {
+                    true => -I256::from(value),
+                    false => I256::from(value),
+                })))
             .filter(|(_, amt)| (amt).is_some())
             .map(|(coin, amt)| (coin, amt.unwrap()))
-            .collect::<Vec<(&H160, f64)>>()));
+            .collect::<Vec<(&H160, I256)>>()));
 
     for (address, holdings) in trader_coin_totals {
-        if let Some(t) = trader_map.get_mut(&address) {
+        if let Some(t) = trader_map.get_mut(address) {
             t.total_assets = holdings.iter()
-                .filter(|(_coin, amt)| amt > &0.0)
-                .map(|(_coin, amt)| amt)
-                .fold(0_f64, |acc, x| acc + x);
+                .filter(|(_coin, amt)| amt.is_positive())
+                .map(|(_coin, amt)| amt.abs())
+                .fold(U256::zero(), |acc, x| acc + x);
             t.total_debt = holdings.iter()
-                .filter(|(_coin, amt)| amt < &0.0)
-                .map(|(_coin, amt)| amt)
-                .fold(0_f64, |acc, x| acc + x);
-            t.profit_raw = t.total_assets + t.total_debt;
-            t.profit_percent = -1.0 * t.total_assets / t.total_debt;
-            t.roi_percent = (t.profit_raw + t.hist_cost - t.cum_gas) / t.hist_cost;
-            t.real_gain_percent = (t.total_assets - t.cum_gas) / t.hist_cost;
+                .filter(|(_coin, amt)| amt.is_negative())
+                .map(|(_coin, amt)| amt.abs())
+                .fold(U256::zero(), |acc, x| acc + x);
+            t.profit_raw = I256::from(t.total_assets) - I256::from(t.total_debt);
+            // Ratios are the only place the exact amounts become floats. Gas
+            // fees are wei of eth, which prices one to one with weth.
+            let hist_cost = u256_to_f64(t.hist_cost);
+            t.profit_percent = u256_to_f64(t.total_assets) / u256_to_f64(t.total_debt);
+            t.roi_percent = (t.profit_raw + I256::from(t.hist_cost) - I256::from(t.gas_fees))
+                .to_f64() / hist_cost;
+            t.real_gain_percent = (I256::from(t.total_assets) - I256::from(t.gas_fees))
+                .to_f64() / hist_cost;
         }
     }
+}
 
-    let mut trader_profit_list: Vec<(&H160, f64, f64, usize, f64, f64, f64)> = trader_map.iter()
+// Every booked trade of every trader in chain order.
+fn chain_order(trader_map: &HashMap<H160, Trader>) -> Vec<&Trade> {
+    let mut trades = trader_map.values()
+        .flat_map(|t| &t.trades)
+        .collect::<Vec<&Trade>>();
+    trades.sort_by_key(|trade| (trade.block, trade.tx_index, trade.log_index));
+    trades
+}
+
+// Weth an amount of coin sold for in the v2 pools as the transaction at
+// `tx_index` of `block` found them.
+fn weth_value_as_of(coin: H160, amount: U256, block: u64, tx_index: u64, uniswap_pools: &PoolRegistry,
+                    weth_addr: H160) -> Option<U256> {
+    match coin == weth_addr {
+        true => Some(amount),
+        false => uniswap_pools.quote_as_of(coin, weth_addr, amount, block, tx_index),
+    }
+}
+
+// Find the sandwiches in the ledger and label their attackers and victims.
+// Profits and losses are valued in weth at the v2 pool state before the
+// front-run.
+fn label_sandwiches(trader_map: &mut HashMap<H160, Trader>, uniswap_pools: &PoolRegistry,
+                    weth_addr: H160) -> Vec<Sandwich> {
+    let sandwiches = find_sandwiches(&chain_order(trader_map), |pool| uniswap_pools.fee_bps(&pool),
+        |coin, amount, block, tx_index| weth_value_as_of(coin, amount, block, tx_index, uniswap_pools, weth_addr));
+    for sandwich in &sandwiches {
+        if let Some(t) = trader_map.get_mut(&sandwich.attacker) {
+            t.labels.insert(TraderLabel::SandwichAttacker);
+        }
+        for victim in &sandwich.victims {
+            if let Some(t) = trader_map.get_mut(&victim.trader) {
+                t.labels.insert(TraderLabel::SandwichVictim);
+            }
+        }
+    }
+    sandwiches
+}
+
+// Find the cyclic arbitrages in the ledger and label the traders who made
+// them. Profits are valued in weth at the v2 pool state before the
+// transaction.
+fn label_arbitrages(trader_map: &mut HashMap<H160, Trader>, uniswap_pools: &PoolRegistry,
+                    weth_addr: H160) -> Vec<Arbitrage> {
+    let arbitrages = find_arbitrages(&chain_order(trader_map), |coin, amount, block, tx_index|
+        weth_value_as_of(coin, amount, block, tx_index, uniswap_pools, weth_addr));
+    for arbitrage in &arbitrages {
+        if let Some(t) = trader_map.get_mut(&arbitrage.trader) {
+            t.labels.insert(TraderLabel::Arbitrageur);
+        }
+    }
+    arbitrages
+}
+
+// Write the traders and their trades, and/or the pools, in every requested
+// format: the final pool states as records, or the reserve history in the
+// columnar formats. Does nothing without an export folder.
+fn export_results(export: &ExportArgs, scanned: &Scan, with_traders: bool, with_pools: bool)
+-> Result<(), Box<dyn Error>> {
+    let dir = match &export.export_dir {
+        Some(dir) => dir,
+        None => return Ok(()),
+    };
+    std::fs::create_dir_all(dir)?;
+    let mut traders = scanned.trader_map.values().collect::<Vec<&Trader>>();
+    traders.sort_by_key(|t| t.address);
+    let trades = chain_order(&scanned.trader_map);
+    let dexes = scanned.uniswap_pools.dexes();
+    let mut pools = scanned.uniswap_pools.iter()
+        .map(|pool| PoolRecord::v2(pool, dexes))
+        .chain(scanned.v3_book.pools.values().map(|pool| PoolRecord::v3(pool, dexes)))
+        .collect::<Vec<PoolRecord>>();
+    pools.sort_by(|a, b| (a.kind, &a.pool).cmp(&(b.kind, &b.pool)));
+
+    for format in &export.export_format {
+        #[cfg(feature = "columnar")]
+        if format.is_columnar() {
+            if with_traders {
+                columnar::write_batch(format.path(dir, "traders"), *format, &columnar::traders_batch(&traders)?)?;
+                columnar::write_batch(format.path(dir, "trades"), *format, &columnar::trades_batch(&trades)?)?;
+            }
+            if with_pools {
+                columnar::write_batch(format.path(dir, "reserves"), *format,
+                                      &columnar::reserves_batch(&scanned.uniswap_pools)?)?;
+            }
+            continue;
+        }
+        if with_traders {
+            write_records(format.path(dir, "traders"), *format,
+                          &traders.iter().map(|t| TraderRecord::new(t)).collect::<Vec<_>>())?;
+            write_records(format.path(dir, "trades"), *format,
+                          &trades.iter().map(|trade| TradeRecord::new(trade)).collect::<Vec<_>>())?;
+        }
+        if with_pools {
+            write_records(format.path(dir, "pools"), *format, &pools)?;
+        }
+    }
+    Ok(())
+}
+
+fn print_pools(uniswap_pools: &PoolRegistry, v3_book: &V3Book, tokens: &TokenRegistry) {
+    for pool in uniswap_pools.iter() {
+        let (token0, token1) = pool.tokens();
+        let (res0, res1) = pool.reserves();
+        println!("{:?} {}/{}: {}, {}", pool.address(), tokens.symbol(&token0), tokens.symbol(&token1),
+                 tokens.format_amount(&token0, I256::from(res0)),
+                 tokens.format_amount(&token1, I256::from(res1)));
+    }
+    for (address, pool) in &v3_book.pools {
+        println!("{:?} v3 {}/{} fee {}: sqrtPriceX96 {}, liquidity {}, tick {}, {} ticks known",
+                 address, tokens.symbol(&pool.token0), tokens.symbol(&pool.token1),
+                 pool.fee.map_or("?".to_string(), |fee| fee.to_string()),
+                 pool.sqrt_price_x96, pool.liquidity, pool.tick, pool.ticks.len());
+    }
+}
+
+// (address, roi, profit ratio, txs, profit, real gain, cost, realized, unrealized)
+type TraderRow<'a> = (&'a H160, f64, f64, usize, I256, f64, U256, I256, I256);
+
+fn print_traders(trader_map: &HashMap<H160, Trader>, arbitrages: &[Arbitrage], output: &OutputArgs,
+                 tokens: &TokenRegistry, weth_addr: H160) {
+    // arbitrageurs are listed on their own after the other traders
+    let mut trader_profit_list: Vec<TraderRow> = trader_map.iter()
         .filter(|(_addr, t)| !t.roi_percent.is_nan())
-        .map(|(addr, t)| (addr, t.roi_percent, t.profit_percent, t.cum_txs, t.profit_raw, t.real_gain_percent, t.hist_cost))
-        .filter(|(_,_,_,cum_txs,_, _, _)| cum_txs > &0_usize)
+        .filter(|(_addr, t)| !t.labels.contains(&TraderLabel::Arbitrageur))
+        .map(|(addr, t)| (addr, t.roi_percent, t.profit_percent, t.cum_txs, t.profit_raw, t.real_gain_percent, t.hist_cost,
+                          t.realized_pnl, t.unrealized_pnl))
+        .filter(|(_,_,_,cum_txs,_, _, _, _, _)| cum_txs >= &output.min_txs)
         .collect();
     trader_profit_list.sort_by(|a, b| a.5.partial_cmp(&b.5).unwrap());
+    if let Some(top) = output.top {
+        // sorted ascending, so the top traders are at the end of the list
+        let skip = trader_profit_list.len().saturating_sub(top);
+        trader_profit_list.drain(..skip);
+    }
 
-    for entry in &trader_map {
-        println!("{:?}", entry);
+    if output.print_profiles {
+        for (addr, t) in trader_map {
+            let mut venues = t.venues.iter()
+                .map(|(venue, count)| format!("{} {}", venue, count))
+                .collect::<Vec<String>>();
+            venues.sort();
+            println!("{:?}: txs {}, gas {} for {} ({} burned, {} tips), cost {}, assets {}, debt {}, \
+                      holdings [{}], venues [{}], labels [{}]",
+                     addr, t.cum_txs, t.cum_gas, tokens.format_amount(&weth_addr, I256::from(t.gas_fees)),
+                     tokens.format_amount(&weth_addr, I256::from(t.gas_burned)),
+                     tokens.format_amount(&weth_addr, I256::from(t.gas_tips)),
+                     tokens.format_amount(&weth_addr, I256::from(t.hist_cost)),
+                     tokens.format_amount(&weth_addr, I256::from(t.total_assets)),
+                     tokens.format_amount(&weth_addr, I256::from(t.total_debt)),
+                     t.holdings_in_units(tokens).join(", "), venues.join(", "),
+                     t.labels.iter().map(|label| label.name()).collect::<Vec<_>>().join(", "));
+            for trade in &t.trades {
+                println!("    {:?} block {} tx {} log {} on {}: {} -> {} via {}, price {:.6}, cost {}, gas {}",
+                         trade.tx_hash, trade.block, trade.tx_index, trade.log_index, trade.venue,
+                         tokens.format_amount(&trade.token_in(), I256::from(trade.amount_in)),
+                         tokens.format_amount(&trade.token_out(), I256::from(trade.amount_out)),
+                         trade.path.iter().map(|coin| tokens.symbol(coin)).collect::<Vec<_>>().join(" > "),
+                         trade.execution_price(),
+                         trade.cost.map_or("?".to_string(),
+                                           |cost| tokens.format_amount(&weth_addr, I256::from(cost))),
+                         tokens.format_amount(&weth_addr, I256::from(trade.gas.total())));
+            }
+            let weth = |amount: I256| tokens.format_amount(&weth_addr, amount);
+            for position in &t.positions {
+                println!("    {} {}: realized {} over {} sales, unmatched {}, open {} at cost {}, \
+                          value {}, unrealized {}",
+                         tokens.symbol(&position.token), output.cost_basis, weth(position.realized),
+                         position.disposals, tokens.format_amount(&position.token, I256::from(position.unmatched)),
+                         tokens.format_amount(&position.token, I256::from(position.open_amount)),
+                         weth(I256::from(position.open_cost)),
+                         position.value.map_or("?".to_string(), |value| weth(I256::from(value))),
+                         position.unrealized.map_or("?".to_string(), weth));
+            }
+        }
     }
     for entry in &trader_profit_list {
-        println!("{:?}, {:.3}, {:.3}, {}, {}, {:.3}, {}", entry.0, entry.1, entry.2, entry.3, entry.4, entry.5, entry.6);
+        println!("{:?}, {:.3}, {:.3}, {}, {}, {:.3}, {}, {}, {}", entry.0, entry.1, entry.2, entry.3,
+                 tokens.format_amount(&weth_addr, entry.4), entry.5,
+                 tokens.format_amount(&weth_addr, I256::from(entry.6)),
+                 tokens.format_amount(&weth_addr, entry.7), tokens.format_amount(&weth_addr, entry.8));
+    }
+
+    // (arbitrages, unpriced, gross, gas) per arbitrageur, in weth
+    let mut arbitrageurs: HashMap<H160, (usize, usize, I256, U256)> = HashMap::new();
+    for arbitrage in arbitrages {
+        let totals = arbitrageurs.entry(arbitrage.trader)
+            .or_insert((0, 0, I256::zero(), U256::zero()));
+        totals.0 += 1;
+        totals.3 += arbitrage.gas_fees;
+        match arbitrage.gross_profit_weth {
+            Some(gross) => totals.2 += gross,
+            None => totals.1 += 1,
+        }
+    }
+    let mut arbitrageurs = arbitrageurs.into_iter()
+        .map(|(addr, (count, unpriced, gross, gas))| (addr, count, unpriced, gross, gas, gross - I256::from(gas)))
+        .collect::<Vec<_>>();
+    // sorted ascending by the profit after gas, like the traders
+    arbitrageurs.sort_by_key(|entry| (entry.5, entry.0));
+    for (addr, count, unpriced, gross, gas, net) in arbitrageurs {
+        println!("{:?} arbitrageur: {} arbitrages ({} unpriced), gross {}, gas {}, net {}", addr, count, unpriced,
+                 tokens.format_amount(&weth_addr, gross),
+                 tokens.format_amount(&weth_addr, I256::from(gas)),
+                 tokens.format_amount(&weth_addr, net));
     }
+}
 
+fn print_arbitrages(arbitrages: &[Arbitrage], tokens: &TokenRegistry, weth_addr: H160) {
+    let weth = |amount: Option<I256>| amount.map_or("?".to_string(), |amount| tokens.format_amount(&weth_addr, amount));
+    for arbitrage in arbitrages {
+        println!("arbitrage block {} tx {:?} by {:?}: {} -> {} via {}, gross {} ({}), gas {}, net {}",
+                 arbitrage.block, arbitrage.tx_hash, arbitrage.trader,
+                 tokens.format_amount(&arbitrage.token(), I256::from(arbitrage.amount_in)),
+                 tokens.format_amount(&arbitrage.token(), I256::from(arbitrage.amount_out)),
+                 arbitrage.path.iter().map(|coin| tokens.symbol(coin)).collect::<Vec<_>>().join(" > "),
+                 tokens.format_amount(&arbitrage.token(), arbitrage.gross_profit),
+                 weth(arbitrage.gross_profit_weth), weth(Some(I256::from(arbitrage.gas_fees))),
+                 weth(arbitrage.net_profit()));
+    }
+    println!("arbitrages: {}", arbitrages.len());
+}
 
-    println!("receipts_missed = {}", receipts_missed);
-    println!("trades captured: {}", captured_trade);
-    println!("missed:          {}", missed_trade);
-    println!("captured / total: {}", captured_trade as f64 / (captured_trade as f64 
-                                                              + missed_trade as f64));
-    println!("debug_all_addr: {}", debug_all_addr);
-    Ok(())
+fn print_sandwiches(sandwiches: &[Sandwich], tokens: &TokenRegistry, weth_addr: H160) {
+    let weth = |amount: Option<I256>| amount.map_or("?".to_string(), |amount| tokens.format_amount(&weth_addr, amount));
+    for sandwich in sandwiches {
+        println!("sandwich block {} pool {:?} by {:?}: front {:?}, back {:?}, {} in, {} out, profit {} ({}), \
+                  gas {}, net {}",
+                 sandwich.block, sandwich.pool, sandwich.attacker, sandwich.front_tx, sandwich.back_tx,
+                 tokens.format_amount(&sandwich.token, I256::from(sandwich.front_in)),
+                 tokens.format_amount(&sandwich.token, I256::from(sandwich.back_out)),
+                 tokens.format_amount(&sandwich.token, sandwich.profit), weth(sandwich.profit_weth),
+                 weth(Some(I256::from(sandwich.gas_fees))), weth(sandwich.net_profit()));
+        for victim in &sandwich.victims {
+            println!("    victim {:?} tx {:?}: {} -> {}, expected {}, lost {} ({})",
+                     victim.trader, victim.tx_hash,
+                     tokens.format_amount(&victim.token_in, I256::from(victim.amount_in)),
+                     tokens.format_amount(&victim.token_out, I256::from(victim.amount_out)),
+                     tokens.format_amount(&victim.token_out, I256::from(victim.expected_out)),
+                     tokens.format_amount(&victim.token_out, I256::from(victim.loss)),
+                     weth(victim.loss_weth.map(I256::from)));
+        }
+    }
+    let victims = sandwiches.iter().map(|sandwich| sandwich.victims.len()).sum::<usize>();
+    println!("sandwiches: {} with {} victims", sandwiches.len(), victims);
 }
 
+fn print_lp_positions(lp_book: &LpBook, tokens: &TokenRegistry) {
+    let mut positions = lp_book.positions.values().collect::<Vec<_>>();
+    positions.sort_by_key(|position| (position.owner, position.pair));
+    for position in positions {
+        let (token0, token1) = position.tokens;
+        let amounts = |(amount0, amount1): (U256, U256)| format!("{}, {}",
+            tokens.format_amount(&token0, I256::from(amount0)),
+            tokens.format_amount(&token1, I256::from(amount1)));
+        println!("{:?} lp {:?} {}/{}: liquidity {}, deposited [{}], withdrawn [{}], \
+                  underlying [{}], fees [{}], impermanent loss {}",
+                 position.owner, position.pair, tokens.symbol(&token0), tokens.symbol(&token1),
+                 position.liquidity, amounts(position.deposited), amounts(position.withdrawn),
+                 lp_book.underlying(position).map_or("?".to_string(), amounts),
+                 lp_book.fee_income(position).map_or("?".to_string(), amounts),
+                 lp_book.impermanent_loss(position)
+                     .map_or("?".to_string(), |loss| format!("{:.3}%", loss * 100.0)));
+    }
+}
+
+fn print_summary(scanned: &Scan) {
+    println!("receipts_missed = {}", scanned.receipts_missed);
+    let mut decode_errors = scanned.decode_errors.iter().collect::<Vec<_>>();
+    decode_errors.sort();
+    for (kind, count) in decode_errors {
+        println!("skipped ({}): {}", kind, count);
+    }
+    println!("trades captured: {}", scanned.captured_trade);
+    let mut captured_by_method = scanned.captured_by_method.iter().collect::<Vec<_>>();
+    captured_by_method.sort();
+    for (method, count) in captured_by_method {
+        println!("    {}: {}", method, count);
+    }
+    let mut captured_by_venue = scanned.captured_by_venue.iter().collect::<Vec<_>>();
+    captured_by_venue.sort();
+    for (venue, count) in captured_by_venue {
+        println!("    on {}: {}", venue, count);
+    }
+    println!("unpriced:        {}", scanned.unpriced_trade);
+    let mut liquidity_events = scanned.liquidity_events.iter().collect::<Vec<_>>();
+    liquidity_events.sort();
+    for (kind, count) in liquidity_events {
+        println!("liquidity ({}): {}", kind, count);
+    }
+    println!("priced / total:  {}", (scanned.captured_trade - scanned.unpriced_trade) as f64
+                                    / scanned.captured_trade as f64);
+}
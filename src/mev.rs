@@ -0,0 +1,505 @@
+use web3::types::{H160, H256, U256};
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::amount::I256;
+use crate::ledger::Trade;
+use crate::Amm;
+
+/// What a trader was seen doing besides plain trading.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum TraderLabel {
+    /// Front- and back-ran another swap in the same pool.
+    SandwichAttacker,
+    /// Had a swap sandwiched.
+    SandwichVictim,
+    /// Traded a token back into itself through a cycle of pools.
+    Arbitrageur,
+}
+
+impl TraderLabel {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TraderLabel::SandwichAttacker => "sandwich_attacker",
+            TraderLabel::SandwichVictim => "sandwich_victim",
+            TraderLabel::Arbitrageur => "arbitrageur",
+        }
+    }
+}
+
+impl fmt::Display for TraderLabel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// A swap caught between the two legs of a sandwich.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SandwichVictim {
+    pub trader: H160,
+    pub tx_hash: H256,
+    pub tx_index: u64,
+    pub token_in: H160,
+    pub token_out: H160,
+    pub amount_in: U256,
+    pub amount_out: U256,
+    // what the pool would have paid out without the front-run
+    pub expected_out: U256,
+    // expected_out minus amount_out in token_out, and its weth value when it
+    // can be priced
+    pub loss: U256,
+    pub loss_weth: Option<U256>,
+}
+
+/// A front-run and a back-run by the same trader around one or more swaps
+/// of others in the same pool and block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sandwich {
+    pub block: u64,
+    pub pool: H160,
+    pub attacker: H160,
+    pub front_tx: H256,
+    pub back_tx: H256,
+    // token the attacker sells in the front-run and gets back in the back-run
+    pub token: H160,
+    // amount of `token` sold in the front-run and bought in the back-run
+    pub front_in: U256,
+    pub back_out: U256,
+    // back_out minus front_in, and its weth value when it can be priced
+    pub profit: I256,
+    pub profit_weth: Option<I256>,
+    // wei both attacking transactions paid for gas
+    pub gas_fees: U256,
+    pub victims: Vec<SandwichVictim>,
+}
+
+impl Sandwich {
+    /// Weth profit after gas, None when the profit cannot be priced.
+    pub fn net_profit(&self) -> Option<I256> {
+        Some(self.profit_weth? - I256::from(self.gas_fees))
+    }
+}
+
+// One V2 pool a booked trade went through, with the amounts the pair's
+// reserves moved by. V3 hops have no reserves and are left out.
+#[derive(Debug, Clone, Copy)]
+struct Leg<'a> {
+    trade: &'a Trade,
+    pool: H160,
+    token_in: H160,
+    token_out: H160,
+    amount_in: U256,
+    amount_out: U256,
+    // (reserve_in, reserve_out) before the swap
+    reserves_before: (U256, U256),
+}
+
+fn legs(trade: &Trade) -> impl Iterator<Item = Leg<'_>> {
+    trade.pools.iter().enumerate().filter_map(move |(hop, pool)| {
+        let (before, after) = (trade.reserves_before[hop]?, trade.reserves_after[hop]?);
+        let (token_in, token_out) = (trade.path[hop], trade.path[hop + 1]);
+        let (before, after) = match token_in < token_out {
+            true => (before, after),
+            false => ((before.1, before.0), (after.1, after.0)),
+        };
+        Some(Leg {
+            trade,
+            pool: *pool,
+            token_in,
+            token_out,
+            amount_in: after.0.saturating_sub(before.0),
+            amount_out: before.1.saturating_sub(after.1),
+            reserves_before: before,
+        })
+    })
+}
+
+/// Find the sandwiches in a trade ledger.
+///
+/// The trades are grouped by block and pool and taken in transaction order.
+/// A swap is a front-run when the same trader swaps back the other way in
+/// the same pool in a later transaction of the block, and other traders
+/// swapped in the front-run's direction in between. Those swaps are the
+/// victims: each lost what the pool would have paid with the front-run taken
+/// out of its reserves, quoted with `Amm::uniswap_immut` at the pool's fee in
+/// basis points from `fee_bps`, minus what it got. `value` gives the weth an
+/// amount of a token sold for as of a transaction index of a block, and
+/// `trades` only need to hold whole blocks.
+pub fn find_sandwiches(trades: &[&Trade], fee_bps: impl Fn(H160) -> u32,
+                       value: impl Fn(H160, U256, u64, u64) -> Option<U256>) -> Vec<Sandwich> {
+    let mut by_pool: HashMap<(u64, H160), Vec<Leg>> = HashMap::new();
+    for leg in trades.iter().flat_map(|trade| legs(trade)) {
+        by_pool.entry((leg.trade.block, leg.pool)).or_default().push(leg);
+    }
+    let mut keys = by_pool.keys().copied().collect::<Vec<(u64, H160)>>();
+    keys.sort();
+    // gas is booked on the first trade of a transaction, which need not be a v2 one
+    let mut gas_fees: HashMap<H256, U256> = HashMap::new();
+    for trade in trades {
+        *gas_fees.entry(trade.tx_hash).or_default() += trade.gas.total();
+    }
+    let gas_of = |tx_hash: H256| gas_fees.get(&tx_hash).copied().unwrap_or_default();
+
+    let mut sandwiches = vec![];
+    for key in keys {
+        let legs = by_pool.get_mut(&key).expect("key was collected from the map");
+        legs.sort_by_key(|leg| (leg.trade.tx_index, leg.trade.log_index));
+        let mut used = vec![false; legs.len()];
+        for front in 0..legs.len() {
+            if used[front] {
+                continue;
+            }
+            let (attacker, token) = (legs[front].trade.trader, legs[front].token_in);
+            let back = (front + 1..legs.len()).find(|&back| !used[back]
+                && legs[back].trade.trader == attacker
+                && legs[back].token_out == token
+                && legs[back].trade.tx_index > legs[front].trade.tx_index);
+            let back = match back {
+                Some(back) => back,
+                None => continue,
+            };
+            let victims = (front + 1..back)
+                .filter(|&victim| legs[victim].trade.trader != attacker
+                    && legs[victim].token_in == token
+                    && legs[victim].trade.tx_index > legs[front].trade.tx_index
+                    && legs[victim].trade.tx_index < legs[back].trade.tx_index)
+                .collect::<Vec<usize>>();
+            if victims.is_empty() {
+                continue;
+            }
+            used[front] = true;
+            used[back] = true;
+            let (front, back) = (legs[front], legs[back]);
+            let victims = victims.into_iter()
+                .map(|victim| victim_loss(&front, &legs[victim], fee_bps(key.1), &value))
+                .collect::<Vec<SandwichVictim>>();
+            let profit = I256::from(back.amount_out) - I256::from(front.amount_in);
+            let profit_weth = value(token, profit.abs(), key.0, front.trade.tx_index)
+                .map(|value| match profit.is_negative() {
+                    true => -I256::from(value),
+                    false => I256::from(value),
+                });
+            sandwiches.push(Sandwich {
+                block: key.0,
+                pool: key.1,
+                attacker,
+                front_tx: front.trade.tx_hash,
+                back_tx: back.trade.tx_hash,
+                token,
+                front_in: front.amount_in,
+                back_out: back.amount_out,
+                profit,
+                profit_weth,
+                gas_fees: gas_of(front.trade.tx_hash) + gas_of(back.trade.tx_hash),
+                victims,
+            });
+        }
+    }
+    sandwiches
+}
+
+// Undo the front-run on the reserves the victim found and quote its swap on
+// those, as if the attacker had not traded.
+fn victim_loss(front: &Leg, victim: &Leg, fee_bps: u32,
+               value: impl Fn(H160, U256, u64, u64) -> Option<U256>) -> SandwichVictim {
+    let reserve_in = victim.reserves_before.0.saturating_sub(front.amount_in);
+    let reserve_out = victim.reserves_before.1 + front.amount_out;
+    let amm = Amm::new(victim.pool, victim.token_in, victim.token_out, reserve_in, reserve_out, fee_bps);
+    // reserves that cannot be quoted leave no loss to tell
+    let expected_out = amm.uniswap_immut(victim.token_in, victim.amount_in).unwrap_or(victim.amount_out);
+    let loss = expected_out.saturating_sub(victim.amount_out);
+    SandwichVictim {
+        trader: victim.trade.trader,
+        tx_hash: victim.trade.tx_hash,
+        tx_index: victim.trade.tx_index,
+        token_in: victim.token_in,
+        token_out: victim.token_out,
+        amount_in: victim.amount_in,
+        amount_out: victim.amount_out,
+        expected_out,
+        loss,
+        loss_weth: value(victim.token_out, loss, victim.trade.block, front.trade.tx_index),
+    }
+}
+
+/// A transaction that sold a token through a cycle of pools and got the same
+/// token back, e.g. WETH > X > Y > WETH.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Arbitrage {
+    pub tx_hash: H256,
+    pub block: u64,
+    pub tx_index: u64,
+    pub trader: H160,
+    // tokens of the cycle, starting and ending in the same one, and the pools
+    // in between
+    pub path: Vec<H160>,
+    pub pools: Vec<H160>,
+    pub amount_in: U256,
+    pub amount_out: U256,
+    // amount_out minus amount_in, and its weth value when it can be priced
+    pub gross_profit: I256,
+    pub gross_profit_weth: Option<I256>,
+    // wei the transaction paid for gas, on the first arbitrage of a
+    // transaction only
+    pub gas_fees: U256,
+}
+
+impl Arbitrage {
+    pub fn token(&self) -> H160 {
+        self.path[0]
+    }
+
+    /// Weth profit after gas, None when the profit cannot be priced.
+    pub fn net_profit(&self) -> Option<I256> {
+        Some(self.gross_profit_weth? - I256::from(self.gas_fees))
+    }
+}
+
+/// Find the cyclic arbitrages in a trade ledger.
+///
+/// The trades of each transaction are chained in log order, a trade selling
+/// what the one before bought continuing it. A router call along a cyclic
+/// path is one trade, while a bot calling the pairs itself often shows up as
+/// one trade per pool, so both end up as a chain. Every chain of two pools or
+/// more that ends in the token it started with is an arbitrage. `value` is
+/// the same as for `find_sandwiches`.
+pub fn find_arbitrages(trades: &[&Trade], value: impl Fn(H160, U256, u64, u64) -> Option<U256>)
+-> Vec<Arbitrage> {
+    let mut by_tx: HashMap<H256, Vec<&Trade>> = HashMap::new();
+    for trade in trades {
+        by_tx.entry(trade.tx_hash).or_default().push(trade);
+    }
+    let mut txs = by_tx.into_values().collect::<Vec<Vec<&Trade>>>();
+    txs.sort_by_key(|trades| (trades[0].block, trades[0].tx_index));
+
+    let mut arbitrages = vec![];
+    for mut trades in txs {
+        trades.sort_by_key(|trade| trade.log_index);
+        let mut chains: Vec<Vec<&Trade>> = vec![];
+        for trade in &trades {
+            let next_of = chains.iter_mut()
+                .find(|chain| chain.last().is_some_and(|last| last.token_out() == trade.token_in()
+                                                       && last.token_out() != chain[0].token_in()));
+            match next_of {
+                Some(chain) => chain.push(trade),
+                None => chains.push(vec![trade]),
+            }
+        }
+        let gas = trades.iter().fold(U256::zero(), |acc, trade| acc + trade.gas.total());
+        let mut gas_booked = false;
+        for chain in chains {
+            let (first, last) = (chain[0], chain[chain.len() - 1]);
+            let pools = chain.iter().flat_map(|trade| trade.pools.iter().copied()).collect::<Vec<H160>>();
+            if first.token_in() != last.token_out() || pools.len() < 2 {
+                continue;
+            }
+            let mut path = vec![first.token_in()];
+            path.extend(chain.iter().flat_map(|trade| trade.path[1..].iter().copied()));
+            let gross_profit = I256::from(last.amount_out) - I256::from(first.amount_in);
+            let gross_profit_weth = value(first.token_in(), gross_profit.abs(), first.block, first.tx_index)
+                .map(|value| match gross_profit.is_negative() {
+                    true => -I256::from(value),
+                    false => I256::from(value),
+                });
+            arbitrages.push(Arbitrage {
+                tx_hash: first.tx_hash,
+                block: first.block,
+                tx_index: first.tx_index,
+                trader: first.trader,
+                path,
+                pools,
+                amount_in: first.amount_in,
+                amount_out: last.amount_out,
+                gross_profit,
+                gross_profit_weth,
+                gas_fees: match gas_booked {
+                    true => U256::zero(),
+                    false => gas,
+                },
+            });
+            gas_booked = true;
+        }
+    }
+    arbitrages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ledger::GasCost;
+
+    const WETH: u64 = 1;
+    const USDC: u64 = 2;
+    const DAI: u64 = 3;
+
+    fn address(n: u64) -> H160 {
+        H160::from_low_u64_be(n)
+    }
+
+    fn pool() -> Amm {
+        Amm::new(address(0xaa), address(WETH), address(USDC),
+                 U256::from(1_000_000_000_u64), U256::from(2_000_000_000_u64), 30)
+    }
+
+    // A swap of `amount_in` through `amm` by `trader` in its own transaction,
+    // booked the way the scan books it.
+    fn swap(amm: &mut Amm, tx_index: u64, trader: u64, token_in: u64, amount_in: u64) -> Trade {
+        let before = amm.reserves();
+        let made = amm.swap(address(token_in), U256::from(amount_in)).unwrap();
+        Trade {
+            tx_hash: H256::from_low_u64_be(tx_index),
+            block: 7,
+            tx_index,
+            log_index: tx_index as usize,
+            trader: address(trader),
+            venue: "uniswap-v2".to_string(),
+            path: vec![made.token_in, made.token_out],
+            pools: vec![amm.address()],
+            amount_in: made.amount_in,
+            amount_out: made.amount_out,
+            cost: None,
+            reserves_before: vec![Some(before)],
+            reserves_after: vec![Some(made.reserves)],
+            gas: GasCost::new(U256::from(100), U256::from(2), None),
+        }
+    }
+
+    fn find(trades: &[Trade]) -> Vec<Sandwich> {
+        find_sandwiches(&trades.iter().collect::<Vec<&Trade>>(), |_| 30,
+                        |_, amount, _, _| Some(amount))
+    }
+
+    #[test]
+    fn front_victim_back() {
+        let mut amm = pool();
+        let untouched = pool();
+        let front = swap(&mut amm, 0, 0xa, WETH, 10_000_000);
+        let victim = swap(&mut amm, 1, 0xb, WETH, 5_000_000);
+        let back = swap(&mut amm, 2, 0xa, USDC, front.amount_out.as_u64());
+        let trades = [front, victim, back];
+
+        let sandwiches = find(&trades);
+        assert_eq!(sandwiches.len(), 1);
+        let sandwich = &sandwiches[0];
+        assert_eq!((sandwich.attacker, sandwich.pool, sandwich.token), (address(0xa), address(0xaa), address(WETH)));
+        assert_eq!((sandwich.front_in, sandwich.back_out), (trades[0].amount_in, trades[2].amount_out));
+        assert_eq!(sandwich.profit, I256::from(trades[2].amount_out) - I256::from(trades[0].amount_in));
+        assert!(!sandwich.profit.is_negative());
+        assert_eq!(sandwich.gas_fees, U256::from(400));
+        assert_eq!(sandwich.net_profit(), Some(sandwich.profit - I256::from(U256::from(400))));
+
+        assert_eq!(sandwich.victims.len(), 1);
+        let victim = &sandwich.victims[0];
+        // without the front-run the victim would have swapped on the initial reserves
+        let expected_out = untouched.uniswap_immut(address(WETH), U256::from(5_000_000)).unwrap();
+        assert_eq!(victim.trader, address(0xb));
+        assert_eq!(victim.expected_out, expected_out);
+        assert_eq!(victim.loss, expected_out - trades[1].amount_out);
+        assert!(!victim.loss.is_zero());
+        assert_eq!(victim.loss_weth, Some(victim.loss));
+    }
+
+    #[test]
+    fn same_trader_is_no_sandwich() {
+        let mut amm = pool();
+        let first = swap(&mut amm, 0, 0xa, WETH, 10_000_000);
+        let second = swap(&mut amm, 1, 0xa, WETH, 5_000_000);
+        let back = swap(&mut amm, 2, 0xa, USDC, (first.amount_out + second.amount_out).as_u64());
+        assert!(find(&[first, second, back]).is_empty());
+
+        // nor is a buy in between without the swap back
+        let mut amm = pool();
+        let front = swap(&mut amm, 0, 0xa, WETH, 10_000_000);
+        let other = swap(&mut amm, 1, 0xb, WETH, 5_000_000);
+        let more = swap(&mut amm, 2, 0xa, WETH, 1_000_000);
+        assert!(find(&[front, other, more]).is_empty());
+    }
+
+    #[test]
+    fn several_victims() {
+        let mut amm = pool();
+        let front = swap(&mut amm, 0, 0xa, WETH, 10_000_000);
+        let first = swap(&mut amm, 1, 0xb, WETH, 5_000_000);
+        // a swap the other way is not hurt by the front-run
+        let seller = swap(&mut amm, 2, 0xd, USDC, 1_000_000);
+        let second = swap(&mut amm, 3, 0xc, WETH, 2_000_000);
+        let back = swap(&mut amm, 4, 0xa, USDC, front.amount_out.as_u64());
+
+        let sandwiches = find(&[front, first, seller, second, back]);
+        assert_eq!(sandwiches.len(), 1);
+        let victims = sandwiches[0].victims.iter().map(|victim| victim.trader).collect::<Vec<H160>>();
+        assert_eq!(victims, vec![address(0xb), address(0xc)]);
+        assert!(sandwiches[0].victims.iter().all(|victim| !victim.loss.is_zero()));
+    }
+
+    // A trade along `path` in transaction `tx_index`, one pool per hop.
+    fn routed(tx_index: u64, log_index: usize, path: &[u64], amount_in: u64, amount_out: u64) -> Trade {
+        Trade {
+            tx_hash: H256::from_low_u64_be(tx_index),
+            block: 7,
+            tx_index,
+            log_index,
+            trader: address(0xe),
+            venue: "uniswap-v2".to_string(),
+            path: path.iter().map(|token| address(*token)).collect(),
+            pools: path.windows(2).map(|hop| address(0x100 + hop[0] * 0x10 + hop[1])).collect(),
+            amount_in: U256::from(amount_in),
+            amount_out: U256::from(amount_out),
+            cost: None,
+            reserves_before: vec![None; path.len() - 1],
+            reserves_after: vec![None; path.len() - 1],
+            gas: GasCost::new(U256::from(100), U256::from(2), None),
+        }
+    }
+
+    fn arbitrages(trades: &[Trade]) -> Vec<Arbitrage> {
+        find_arbitrages(&trades.iter().collect::<Vec<&Trade>>(), |_, amount, _, _| Some(amount))
+    }
+
+    #[test]
+    fn router_cycle() {
+        let found = arbitrages(&[routed(0, 0, &[WETH, USDC, DAI, WETH], 1_000, 1_097)]);
+        assert_eq!(found.len(), 1);
+        let arbitrage = &found[0];
+        assert_eq!(arbitrage.token(), address(WETH));
+        assert_eq!(arbitrage.path, vec![address(WETH), address(USDC), address(DAI), address(WETH)]);
+        assert_eq!(arbitrage.pools.len(), 3);
+        assert_eq!(arbitrage.gross_profit, I256::from(U256::from(97)));
+        assert_eq!(arbitrage.gross_profit_weth, Some(I256::from(U256::from(97))));
+        assert_eq!(arbitrage.gas_fees, U256::from(200));
+        assert_eq!(arbitrage.net_profit(), Some(I256::from(U256::from(97)) - I256::from(U256::from(200))));
+    }
+
+    #[test]
+    fn bot_calling_pairs_itself() {
+        // one trade per pool, chained in log order, with gas on the first only
+        let mut back = routed(0, 1, &[USDC, WETH], 2_000, 1_010);
+        back.gas = GasCost::default();
+        let found = arbitrages(&[back, routed(0, 0, &[WETH, USDC], 1_000, 2_000)]);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].path, vec![address(WETH), address(USDC), address(WETH)]);
+        assert_eq!((found[0].amount_in, found[0].amount_out), (U256::from(1_000), U256::from(1_010)));
+        assert_eq!(found[0].gas_fees, U256::from(200));
+    }
+
+    #[test]
+    fn open_paths_are_no_arbitrage() {
+        // a single pool, a path that does not close, and a cycle spread over two transactions
+        assert!(arbitrages(&[routed(0, 0, &[WETH, USDC], 1_000, 2_000)]).is_empty());
+        assert!(arbitrages(&[routed(0, 0, &[WETH, USDC], 1_000, 2_000),
+                             routed(0, 1, &[USDC, DAI], 2_000, 2_000)]).is_empty());
+        assert!(arbitrages(&[routed(0, 0, &[WETH, USDC], 1_000, 2_000),
+                             routed(1, 0, &[USDC, WETH], 2_000, 1_010)]).is_empty());
+    }
+
+    #[test]
+    fn gas_on_the_first_arbitrage_of_a_transaction() {
+        let found = arbitrages(&[routed(0, 0, &[WETH, USDC, WETH], 1_000, 900),
+                                 routed(0, 1, &[DAI, USDC, DAI], 1_000, 1_100)]);
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0].gross_profit, -I256::from(U256::from(100)));
+        assert_eq!(found[0].gas_fees, U256::from(400));
+        assert_eq!(found[1].gas_fees, U256::zero());
+    }
+}
@@ -0,0 +1,143 @@
+use futures::stream::{self, Stream, StreamExt};
+use web3::types::{Transaction, TransactionReceipt, U256};
+
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::fetch::{block_path, receipt_path};
+use crate::{read_blocks, read_receipt};
+
+/// A block read from disk with the receipts of its selected transactions.
+#[derive(Debug, Clone)]
+pub struct LoadedBlock {
+    pub number: u64,
+    pub base_fee_per_gas: Option<U256>,
+    // selected transactions in block order, with their receipt unless its
+    // file is missing or unreadable
+    pub transactions: Vec<(Transaction, Option<TransactionReceipt>)>,
+}
+
+/// Which transactions of a block are worth reading the receipt of.
+pub type TxFilter = Arc<dyn Fn(&Transaction) -> bool + Send + Sync>;
+
+fn load_block(blocks_dir: &Path, receipts_dir: &Path, number: u64, select: &TxFilter)
+-> Result<LoadedBlock, String> {
+    let path = block_path(blocks_dir, number);
+    let block = read_blocks(&path)
+        .map_err(|e| format!("could not read block {}: {}", path.display(), e))?;
+    let transactions = block.transactions.into_iter()
+        .filter(|tx| select(tx))
+        .map(|tx| {
+            let receipt = read_receipt(receipt_path(receipts_dir, number, &tx.hash)).ok();
+            (tx, receipt)
+        })
+        .collect();
+    Ok(LoadedBlock {
+        number,
+        base_fee_per_gas: block.base_fee_per_gas,
+        transactions,
+    })
+}
+
+/// Read and parse the blocks of `range` and the receipts of the transactions
+/// `select` keeps on up to `jobs` blocking threads, yielding them strictly in
+/// block order. Only the reading and JSON parsing run in parallel: decoding
+/// the swaps looks up pool tokens learned from earlier transactions, so it is
+/// left to the consumer with everything else that depends on earlier blocks,
+/// and applying the blocks as they come gives the same result as reading them
+/// one by one. Must be polled within a tokio runtime.
+pub fn load_blocks(blocks_dir: PathBuf, receipts_dir: PathBuf, range: Range<u64>, jobs: usize,
+                   select: TxFilter) -> impl Stream<Item = Result<LoadedBlock, String>> {
+    stream::iter(range)
+        .map(move |number| {
+            let (blocks_dir, receipts_dir, select) = (blocks_dir.clone(), receipts_dir.clone(), select.clone());
+            tokio::task::spawn_blocking(move || load_block(&blocks_dir, &receipts_dir, number, &select))
+        })
+        .buffered(jobs.max(1))
+        .map(|loaded| loaded.unwrap_or_else(|e| Err(format!("block reader failed: {}", e))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use web3::types::{Block, H256, U64};
+
+    use std::fs;
+
+    // Blocks 0..12 where earlier blocks hold more transactions, so they take
+    // longer to parse than the ones after them. Every other transaction has a
+    // receipt, and the receipts of block 5 are missing.
+    fn write_blocks(dir: &Path) {
+        fs::create_dir_all(dir.join("blocks")).unwrap();
+        fs::create_dir_all(dir.join("receipts")).unwrap();
+        for number in 0..12_u64 {
+            let transactions = (0..(12 - number) * 30)
+                .map(|index| Transaction {
+                    hash: H256::from_low_u64_be(number << 32 | index),
+                    nonce: index.into(),
+                    ..Default::default()
+                })
+                .collect::<Vec<Transaction>>();
+            for tx in transactions.iter().filter(|tx| tx.nonce.low_u64() % 2 == 0 && number != 5) {
+                let receipt = TransactionReceipt {
+                    transaction_hash: tx.hash,
+                    block_number: Some(U64::from(number)),
+                    ..Default::default()
+                };
+                fs::write(receipt_path(&dir.join("receipts"), number, &tx.hash),
+                          serde_json::to_string(&receipt).unwrap()).unwrap();
+            }
+            let block = Block {
+                number: Some(U64::from(number)),
+                base_fee_per_gas: Some(U256::from(number * 7)),
+                transactions,
+                ..Default::default()
+            };
+            fs::write(block_path(&dir.join("blocks"), number), serde_json::to_string(&block).unwrap()).unwrap();
+        }
+    }
+
+    async fn load(dir: &Path, jobs: usize) -> Vec<LoadedBlock> {
+        let select: TxFilter = Arc::new(|tx| tx.nonce.low_u64() % 3 != 0);
+        load_blocks(dir.join("blocks"), dir.join("receipts"), 0..12, jobs, select)
+            .map(|loaded| loaded.unwrap())
+            .collect()
+            .await
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn parallel_loads_match_sequential_ones() {
+        let dir = std::env::temp_dir().join(format!("eth_explo_pipeline_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        write_blocks(&dir);
+        let sequential = load(&dir, 1).await;
+        let parallel = load(&dir, 8).await;
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(sequential.iter().map(|block| block.number).collect::<Vec<u64>>(),
+                   (0..12).collect::<Vec<u64>>());
+        assert_eq!(sequential.len(), parallel.len());
+        for (one, other) in sequential.iter().zip(&parallel) {
+            assert_eq!(one.number, other.number);
+            assert_eq!(one.base_fee_per_gas, other.base_fee_per_gas);
+            assert_eq!(one.transactions, other.transactions);
+        }
+        // selected transactions in block order, with the receipts on disk
+        let first = &parallel[0].transactions;
+        assert_eq!(first.len(), 240);
+        assert!(first.windows(2).all(|pair| pair[0].0.nonce < pair[1].0.nonce));
+        assert!(first.iter().all(|(tx, receipt)| receipt.is_some() == (tx.nonce.low_u64() % 2 == 0)));
+        assert!(parallel[5].transactions.iter().all(|(_, receipt)| receipt.is_none()));
+    }
+
+    #[tokio::test]
+    async fn missing_blocks_are_errors() {
+        let dir = std::env::temp_dir().join(format!("eth_explo_pipeline_missing_{}", std::process::id()));
+        let select: TxFilter = Arc::new(|_| true);
+        let loaded = load_blocks(dir.join("blocks"), dir.join("receipts"), 3..4, 2, select)
+            .collect::<Vec<_>>()
+            .await;
+        assert!(loaded[0].as_ref().unwrap_err().contains("could not read block"));
+    }
+}
@@ -0,0 +1,383 @@
+use web3::types::{H160, H256, Log, U256};
+
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+
+use crate::dex::{DexKind, DexRegistry, DEFAULT_FEE_BPS};
+use crate::swaps::PairTokens;
+use crate::{log_words, Amm};
+
+/// Reserves one pair reported in one `Sync` event, with where in the chain
+/// the event was emitted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReserveSnapshot {
+    pub block: u64,
+    pub tx_index: u64,
+    // logIndex of the Sync within the block, its position in the receipt when
+    // the node left it out
+    pub log_index: u64,
+    pub reserve0: U256,
+    pub reserve1: U256,
+}
+
+/// Every V2 pair seen so far, keyed by its contract address.
+///
+/// A pair is only admitted once its address matches the CREATE2 address a
+/// registered factory derives for its tokens, so contracts that merely emit
+/// pair-like events cannot set prices. Several pools of the same tokens, e.g.
+/// on two forks, are kept side by side.
+#[derive(Debug, Clone, Default)]
+pub struct PoolRegistry {
+    dexes: DexRegistry,
+    pools: HashMap<H160, Amm>,
+    // pair addresses of every token
+    by_token: HashMap<H160, Vec<H160>>,
+    // addresses that failed the CREATE2 check for the tokens they came with
+    rejected: HashSet<(H160, (H160, H160))>,
+    // every Sync of every tracked pair, in chain order
+    history: HashMap<H160, Vec<ReserveSnapshot>>,
+}
+
+impl PoolRegistry {
+    pub fn new(dexes: DexRegistry) -> PoolRegistry {
+        PoolRegistry {
+            dexes,
+            pools: HashMap::new(),
+            by_token: HashMap::new(),
+            rejected: HashSet::new(),
+            history: HashMap::new(),
+        }
+    }
+
+    pub fn dexes(&self) -> &DexRegistry {
+        &self.dexes
+    }
+
+    /// Record the reserves `pair` reported for its two sorted tokens. A new
+    /// pair takes the fee of the DEX that deployed it. Returns false, and
+    /// ignores the update, for pairs no registered factory deployed.
+    pub fn update(&mut self, pair: H160, tokens: (H160, H160), reserves: (U256, U256)) -> bool {
+        if let Some(pool) = self.pools.get_mut(&pair) {
+            pool.set_reserves(reserves.0, reserves.1);
+            return true;
+        }
+        let fee_bps = match self.rejected.contains(&(pair, tokens)) {
+            true => None,
+            false => self.dexes.by_pool(DexKind::V2, pair, tokens.0, tokens.1).map(|dex| dex.fee_bps),
+        };
+        let fee_bps = match fee_bps {
+            Some(fee_bps) => fee_bps,
+            None => {
+                self.rejected.insert((pair, tokens));
+                return false;
+            },
+        };
+        self.pools.insert(pair, Amm::new(pair, tokens.0, tokens.1, reserves.0, reserves.1, fee_bps));
+        self.by_token.entry(tokens.0).or_default().push(pair);
+        self.by_token.entry(tokens.1).or_default().push(pair);
+        true
+    }
+
+    /// Record every `Sync` of a receipt, in log order, for the pairs whose
+    /// tokens are known. Syncs of pairs that fail the CREATE2 check are
+    /// ignored. Receipts have to be fed in chain order.
+    pub fn observe_syncs(&mut self, block: u64, tx_index: u64, logs: &[Log], fid_vec: &[H256],
+                         pair_tokens: &PairTokens) {
+        for (position, log) in logs.iter().enumerate() {
+            if log.topics.first() != Some(&fid_vec[2]) { // sync_fid
+                continue;
+            }
+            let tokens = match self.pools.get(&log.address) {
+                Some(pool) => pool.tokens(),
+                None => match pair_tokens.get(&log.address) {
+                    Some(tokens) => *tokens,
+                    None => continue,
+                },
+            };
+            let reserves = match log_words(log, 2) {
+                Ok(reserves) => (reserves[0], reserves[1]),
+                Err(_) => continue,
+            };
+            self.record(log.address, tokens, ReserveSnapshot {
+                block,
+                tx_index,
+                log_index: log.log_index.map_or(position as u64, |index| index.as_u64()),
+                reserve0: reserves.0,
+                reserve1: reserves.1,
+            });
+        }
+    }
+
+    /// Apply one Sync of `pair` and add it to the history, e.g. when
+    /// replaying a stored history. Snapshots have to come in chain order.
+    /// Returns false for pairs that fail the CREATE2 check.
+    pub fn record(&mut self, pair: H160, tokens: (H160, H160), snapshot: ReserveSnapshot) -> bool {
+        if !self.update(pair, tokens, (snapshot.reserve0, snapshot.reserve1)) {
+            return false;
+        }
+        self.history.entry(pair).or_default().push(snapshot);
+        true
+    }
+
+    /// Every recorded Sync of `pair`, oldest first.
+    pub fn history(&self, pair: &H160) -> &[ReserveSnapshot] {
+        self.history.get(pair).map_or(&[], |history| history.as_slice())
+    }
+
+    /// Reserves of `pair` as the transaction at `tx_index` of `block` found
+    /// them, i.e. after every Sync of the transactions before it. Pass
+    /// `u64::MAX` as `tx_index` for the state at the end of the block. None
+    /// when no Sync of the pair was seen before that point.
+    pub fn reserves_as_of(&self, pair: &H160, block: u64, tx_index: u64) -> Option<(U256, U256)> {
+        let history = self.history.get(pair)?;
+        let seen = history.partition_point(|snapshot|
+            (snapshot.block, snapshot.tx_index) < (block, tx_index));
+        let snapshot = history.get(seen.checked_sub(1)?)?;
+        Some((snapshot.reserve0, snapshot.reserve1))
+    }
+
+    /// The pool `pair` as of a point in the chain, see `reserves_as_of`.
+    pub fn amm_as_of(&self, pair: &H160, block: u64, tx_index: u64) -> Option<Amm> {
+        let pool = self.pools.get(pair)?;
+        let (token0, token1) = pool.tokens();
+        let (reserve0, reserve1) = self.reserves_as_of(pair, block, tx_index)?;
+        Some(Amm::new(*pair, token0, token1, reserve0, reserve1, pool.fee_bps()))
+    }
+
+    /// Swap fee of `pair` in basis points, the Uniswap V2 one for pairs that
+    /// are not tracked.
+    pub fn fee_bps(&self, pair: &H160) -> u32 {
+        self.pools.get(pair).map_or(DEFAULT_FEE_BPS, |pool| pool.fee_bps())
+    }
+
+    // The pools of `token` and `quote_token` as of a point in the chain,
+    // deepest first.
+    fn pools_as_of(&self, token: H160, quote_token: H160, block: u64, tx_index: u64) -> Vec<Amm> {
+        by_depth(self.pools_between(token, quote_token)
+                     .filter_map(|pool| self.amm_as_of(&pool.address(), block, tx_index))
+                     .collect(),
+                 quote_token)
+    }
+
+    /// `quote_token` an amount of `token` sold for in its deepest pool as of
+    /// a point in the chain, see `quote`.
+    pub fn quote_as_of(&self, token: H160, quote_token: H160, amount: U256, block: u64,
+                       tx_index: u64) -> Option<U256> {
+        self.pools_as_of(token, quote_token, block, tx_index).iter()
+            .find_map(|pool| pool.uniswap_immut(token, amount).ok())
+    }
+
+    /// Marginal price of one raw unit of `token` in raw units of
+    /// `quote_token`, from the deepest pool with both reserves as of a point
+    /// in the chain.
+    pub fn price_as_of(&self, token: H160, quote_token: H160, block: u64, tx_index: u64)
+    -> Option<f64> {
+        self.pools_as_of(token, quote_token, block, tx_index).iter()
+            .find_map(|pool| pool.spot_price(token).ok())
+    }
+
+    pub fn get(&self, pair: &H160) -> Option<&Amm> {
+        self.pools.get(pair)
+    }
+
+    pub fn len(&self) -> usize {
+        self.pools.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pools.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Amm> {
+        self.pools.values()
+    }
+
+    /// Every known pool of `token`.
+    pub fn pools_of(&self, token: H160) -> impl Iterator<Item = &Amm> {
+        self.by_token.get(&token)
+            .into_iter()
+            .flatten()
+            .filter_map(|pair| self.pools.get(pair))
+    }
+
+    /// Every known pool pairing `token` with `quote_token`.
+    pub fn pools_between(&self, token: H160, quote_token: H160) -> impl Iterator<Item = &Amm> {
+        self.pools_of(token)
+            .filter(move |pool| pool.reserve_of(quote_token).is_some())
+    }
+
+    /// The pool pairing `token` with `quote_token` that holds the most
+    /// `quote_token`, which moves the least for a given trade.
+    pub fn deepest_pool(&self, token: H160, quote_token: H160) -> Option<&Amm> {
+        self.pools_between(token, quote_token)
+            .max_by_key(|pool| pool.reserve_of(quote_token))
+    }
+
+    /// `quote_token` an amount of `token` sells for in its deepest pool. A
+    /// pool that cannot quote it, e.g. one whose `token` reserve was drained,
+    /// passes the quote on to the next deepest.
+    pub fn quote(&self, token: H160, quote_token: H160, amount: U256) -> Option<U256> {
+        by_depth(self.pools_between(token, quote_token).cloned().collect(), quote_token).iter()
+            .find_map(|pool| pool.uniswap_immut(token, amount).ok())
+    }
+
+    /// Output of selling `amount` of `path[0]` hop by hop along the token
+    /// path, each hop through its deepest pool. None if a hop has no pool.
+    pub fn quote_route(&self, path: &[H160], amount: U256) -> Option<U256> {
+        path.windows(2)
+            .try_fold(amount, |amount, hop| self.quote(hop[0], hop[1], amount))
+    }
+}
+
+// Pools sorted by the `quote_token` they hold, deepest first. Of equally deep
+// pools the one found last comes first, as with `max_by_key`.
+fn by_depth(mut pools: Vec<Amm>, quote_token: H160) -> Vec<Amm> {
+    pools.reverse();
+    pools.sort_by_key(|pool| Reverse(pool.reserve_of(quote_token)));
+    pools
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dex::Dex;
+
+    const A: u64 = 0x10;
+    const WETH: u64 = 0x20;
+
+    fn address(n: u64) -> H160 {
+        H160::from_low_u64_be(n)
+    }
+
+    fn tokens() -> (H160, H160) {
+        (address(A), address(WETH))
+    }
+
+    // Uniswap V2 on mainnet and a 0.25% fork with its own factory.
+    fn dexes() -> DexRegistry {
+        let mut dexes = DexRegistry::mainnet();
+        dexes.insert(Dex {
+            name: "fork".to_string(),
+            kind: DexKind::V2,
+            routers: vec![],
+            factory: address(0xfac),
+            init_code_hash: H256::from_low_u64_be(0x11),
+            fee_bps: 25,
+        });
+        dexes
+    }
+
+    fn pair(name: &str) -> H160 {
+        let dexes = dexes();
+        let dex = dexes.iter().find(|dex| dex.name == name).unwrap();
+        dex.pair_address(address(A), address(WETH))
+    }
+
+    fn reserves(reserve0: u64, reserve1: u64) -> (U256, U256) {
+        (U256::from(reserve0), U256::from(reserve1))
+    }
+
+    #[test]
+    fn pairs_no_factory_deployed_are_rejected() {
+        let mut pools = PoolRegistry::new(dexes());
+        let impostor = address(0xbad);
+        assert!(!pools.update(impostor, tokens(), reserves(1, 1_000_000)));
+        assert!(pools.get(&impostor).is_none());
+        // the pair address of other tokens does not pass either
+        assert!(!pools.update(pair("uniswap-v2"), (address(A), address(0x30)), reserves(1, 1)));
+        let snapshot = ReserveSnapshot { block: 1, tx_index: 0, log_index: 0, reserve0: 1.into(), reserve1: 1.into() };
+        assert!(!pools.record(impostor, tokens(), snapshot));
+        assert!(pools.history(&impostor).is_empty());
+        assert_eq!(pools.quote(address(A), address(WETH), U256::from(10)), None);
+
+        // which does not keep the pair out once its real tokens are known
+        assert!(pools.update(pair("uniswap-v2"), tokens(), reserves(1_000, 1_000)));
+        assert!(pools.update(pair("fork"), tokens(), reserves(1_000, 1_000)));
+        assert_eq!(pools.len(), 2);
+        assert_eq!(pools.fee_bps(&pair("uniswap-v2")), 30);
+        assert_eq!(pools.fee_bps(&pair("fork")), 25);
+    }
+
+    #[test]
+    fn quotes_fall_back_to_the_next_deepest_pool() {
+        let mut pools = PoolRegistry::new(dexes());
+        // the deepest weth pool has no A left to price against
+        pools.update(pair("uniswap-v2"), tokens(), reserves(0, 5_000_000));
+        pools.update(pair("fork"), tokens(), reserves(1_000_000, 2_000_000));
+        assert_eq!(pools.deepest_pool(address(A), address(WETH)).unwrap().address(), pair("uniswap-v2"));
+        // 10000 * 9975 * 2000000 / (1000000 * 10000 + 10000 * 9975)
+        assert_eq!(pools.quote(address(A), address(WETH), U256::from(10_000)), Some(U256::from(19_752)));
+
+        pools.update(pair("uniswap-v2"), tokens(), reserves(2_000_000, 5_000_000));
+        assert_eq!(pools.quote(address(A), address(WETH), U256::from(10_000)), Some(U256::from(24_801)));
+    }
+
+    // Syncs of the uniswap pair at block 10 tx 2 (logs 5 and 9), block 10 tx 5
+    // and block 12 tx 0.
+    fn history() -> PoolRegistry {
+        let mut pools = PoolRegistry::new(dexes());
+        for (block, tx_index, log_index, reserve0) in [(10, 2, 5, 1_000), (10, 2, 9, 1_100),
+                                                      (10, 5, 1, 1_200), (12, 0, 0, 1_300)] {
+            assert!(pools.record(pair("uniswap-v2"), tokens(), ReserveSnapshot {
+                block,
+                tx_index,
+                log_index,
+                reserve0: U256::from(reserve0),
+                reserve1: U256::from(2_000_000),
+            }));
+        }
+        pools
+    }
+
+    #[test]
+    fn reserves_as_a_transaction_found_them() {
+        let pools = history();
+        let reserve0_as_of = |block: u64, tx_index: u64| pools.reserves_as_of(&pair("uniswap-v2"), block, tx_index)
+            .map(|(reserve0, _)| reserve0.as_u64());
+        // before the first Sync, including the transaction that made it
+        assert_eq!(reserve0_as_of(9, u64::MAX), None);
+        assert_eq!(reserve0_as_of(10, 0), None);
+        assert_eq!(reserve0_as_of(10, 2), None);
+        // the last Sync of an earlier transaction in the same block
+        assert_eq!(reserve0_as_of(10, 3), Some(1_100));
+        assert_eq!(reserve0_as_of(10, 5), Some(1_100));
+        assert_eq!(reserve0_as_of(10, 6), Some(1_200));
+        // end of the block, and the latest state
+        assert_eq!(reserve0_as_of(10, u64::MAX), Some(1_200));
+        assert_eq!(reserve0_as_of(11, 0), Some(1_200));
+        assert_eq!(reserve0_as_of(12, 0), Some(1_200));
+        assert_eq!(reserve0_as_of(u64::MAX, u64::MAX), Some(1_300));
+        assert_eq!(pools.history(&pair("uniswap-v2")).len(), 4);
+    }
+
+    #[test]
+    fn pools_and_prices_as_of() {
+        let pools = history();
+        assert!(pools.amm_as_of(&pair("uniswap-v2"), 10, 2).is_none());
+        let amm = pools.amm_as_of(&pair("uniswap-v2"), 10, 3).unwrap();
+        assert_eq!((amm.tokens(), amm.reserves(), amm.fee_bps()), (tokens(), reserves(1_100, 2_000_000), 30));
+
+        assert_eq!(pools.price_as_of(address(A), address(WETH), 10, 2), None);
+        assert_eq!(pools.price_as_of(address(A), address(WETH), 10, u64::MAX), Some(2_000_000.0 / 1_200.0));
+        assert_eq!(pools.price_as_of(address(A), address(WETH), u64::MAX, u64::MAX), Some(2_000_000.0 / 1_300.0));
+        assert_eq!(pools.quote_as_of(address(A), address(WETH), U256::from(100), 10, 3),
+                   pools.amm_as_of(&pair("uniswap-v2"), 10, 3).unwrap().uniswap_immut(address(A), U256::from(100)).ok());
+    }
+
+    #[test]
+    fn prices_as_of_skip_drained_pools() {
+        let mut pools = history();
+        // a deeper fork pool with no A at block 10
+        pools.record(pair("fork"), tokens(), ReserveSnapshot {
+            block: 10,
+            tx_index: 0,
+            log_index: 0,
+            reserve0: U256::zero(),
+            reserve1: U256::from(9_000_000),
+        });
+        assert_eq!(pools.price_as_of(address(A), address(WETH), 10, 3), Some(2_000_000.0 / 1_100.0));
+        assert!(pools.quote_as_of(address(A), address(WETH), U256::from(100), 10, 3).is_some());
+        // nothing but the drained pool before the uniswap pair's first Sync
+        assert_eq!(pools.price_as_of(address(A), address(WETH), 10, 1), None);
+    }
+}
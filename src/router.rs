@@ -0,0 +1,516 @@
+use web3::ethabi::{self, ParamType, Token};
+use web3::types::{H160, U256};
+
+use crate::error::DecodeError;
+
+/// UniswapV2Router02 swap functions decoded from calldata. These are all of
+/// the router's swap entry points.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SwapMethod {
+    SwapExactETHForTokens,
+    SwapETHForExactTokens,
+    SwapExactTokensForTokens,
+    SwapExactTokensForETH,
+    SwapTokensForExactTokens,
+    SwapTokensForExactETH,
+    SwapExactTokensForTokensSupportingFeeOnTransferTokens,
+    SwapExactETHForTokensSupportingFeeOnTransferTokens,
+    SwapExactTokensForETHSupportingFeeOnTransferTokens,
+}
+
+impl SwapMethod {
+    pub const ALL: [SwapMethod; 9] = [
+        SwapMethod::SwapExactETHForTokens,
+        SwapMethod::SwapETHForExactTokens,
+        SwapMethod::SwapExactTokensForTokens,
+        SwapMethod::SwapExactTokensForETH,
+        SwapMethod::SwapTokensForExactTokens,
+        SwapMethod::SwapTokensForExactETH,
+        SwapMethod::SwapExactTokensForTokensSupportingFeeOnTransferTokens,
+        SwapMethod::SwapExactETHForTokensSupportingFeeOnTransferTokens,
+        SwapMethod::SwapExactTokensForETHSupportingFeeOnTransferTokens,
+    ];
+
+    /// First four bytes of the keccak hash of the function signature.
+    pub fn selector(&self) -> [u8; 4] {
+        match self {
+            SwapMethod::SwapExactETHForTokens => [0x7f, 0xf3, 0x6a, 0xb5],
+            SwapMethod::SwapETHForExactTokens => [0xfb, 0x3b, 0xdb, 0x41],
+            SwapMethod::SwapExactTokensForTokens => [0x38, 0xed, 0x17, 0x39],
+            SwapMethod::SwapExactTokensForETH => [0x18, 0xcb, 0xaf, 0xe5],
+            SwapMethod::SwapTokensForExactTokens => [0x88, 0x03, 0xdb, 0xee],
+            SwapMethod::SwapTokensForExactETH => [0x4a, 0x25, 0xd9, 0x4a],
+            SwapMethod::SwapExactTokensForTokensSupportingFeeOnTransferTokens => [0x5c, 0x11, 0xd7, 0x95],
+            SwapMethod::SwapExactETHForTokensSupportingFeeOnTransferTokens => [0xb6, 0xf9, 0xde, 0x95],
+            SwapMethod::SwapExactTokensForETHSupportingFeeOnTransferTokens => [0x79, 0x1a, 0xc9, 0x47],
+        }
+    }
+
+    pub fn from_selector(selector: &[u8]) -> Option<SwapMethod> {
+        SwapMethod::ALL.iter()
+            .find(|method| method.selector() == selector)
+            .copied()
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SwapMethod::SwapExactETHForTokens => "swapExactETHForTokens",
+            SwapMethod::SwapETHForExactTokens => "swapETHForExactTokens",
+            SwapMethod::SwapExactTokensForTokens => "swapExactTokensForTokens",
+            SwapMethod::SwapExactTokensForETH => "swapExactTokensForETH",
+            SwapMethod::SwapTokensForExactTokens => "swapTokensForExactTokens",
+            SwapMethod::SwapTokensForExactETH => "swapTokensForExactETH",
+            SwapMethod::SwapExactTokensForTokensSupportingFeeOnTransferTokens =>
+                "swapExactTokensForTokensSupportingFeeOnTransferTokens",
+            SwapMethod::SwapExactETHForTokensSupportingFeeOnTransferTokens =>
+                "swapExactETHForTokensSupportingFeeOnTransferTokens",
+            SwapMethod::SwapExactTokensForETHSupportingFeeOnTransferTokens =>
+                "swapExactTokensForETHSupportingFeeOnTransferTokens",
+        }
+    }
+
+    /// ETH is sent as `msg.value` instead of an amount argument.
+    pub fn eth_input(&self) -> bool {
+        matches!(self,
+                 SwapMethod::SwapExactETHForTokens
+                 | SwapMethod::SwapETHForExactTokens
+                 | SwapMethod::SwapExactETHForTokensSupportingFeeOnTransferTokens)
+    }
+
+    /// The router unwraps the WETH bought and sends plain ETH to `to`.
+    pub fn eth_output(&self) -> bool {
+        matches!(self,
+                 SwapMethod::SwapExactTokensForETH
+                 | SwapMethod::SwapTokensForExactETH
+                 | SwapMethod::SwapExactTokensForETHSupportingFeeOnTransferTokens)
+    }
+
+    /// The input amount is fixed and the output has a minimum. Otherwise the
+    /// output amount is fixed and the input has a maximum.
+    pub fn exact_input(&self) -> bool {
+        !matches!(self,
+                  SwapMethod::SwapETHForExactTokens
+                  | SwapMethod::SwapTokensForExactTokens
+                  | SwapMethod::SwapTokensForExactETH)
+    }
+
+    /// Variants for tokens that take a fee on transfer. The amounts moved by
+    /// the pairs then differ from what the caller sends and receives.
+    pub fn fee_on_transfer(&self) -> bool {
+        matches!(self,
+                 SwapMethod::SwapExactTokensForTokensSupportingFeeOnTransferTokens
+                 | SwapMethod::SwapExactETHForTokensSupportingFeeOnTransferTokens
+                 | SwapMethod::SwapExactTokensForETHSupportingFeeOnTransferTokens)
+    }
+
+    // Solidity argument list, in calldata order.
+    fn params(&self) -> Vec<ParamType> {
+        let path = ParamType::Array(Box::new(ParamType::Address));
+        match self.eth_input() {
+            // (amountOutMin | amountOut, path, to, deadline)
+            true => vec![ParamType::Uint(256), path, ParamType::Address, ParamType::Uint(256)],
+            // (amountIn | amountOut, amountOutMin | amountInMax, path, to, deadline)
+            false => vec![ParamType::Uint(256), ParamType::Uint(256), path,
+                          ParamType::Address, ParamType::Uint(256)],
+        }
+    }
+}
+
+/// Arguments of a router swap call.
+///
+/// `amount_in` is the exact input, or the most the caller will pay for exact
+/// output calls. `amount_out_min` is the least the caller accepts, or the
+/// exact output. For ETH input calls the input side is the transaction value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SwapCall {
+    pub method: SwapMethod,
+    pub amount_in: U256,
+    pub amount_out_min: U256,
+    pub path: Vec<H160>,
+    pub to: H160,
+    pub deadline: U256,
+}
+
+impl SwapCall {
+    pub fn start_token(&self) -> H160 {
+        self.path[0]
+    }
+
+    pub fn end_token(&self) -> H160 {
+        self.path[self.path.len() - 1]
+    }
+
+    /// (token_0, token_1) of every pair along the path, sorted the way the
+    /// pair contract orders its tokens.
+    pub fn pairs(&self) -> Vec<(H160, H160)> {
+        self.path.windows(2)
+            .map(|pair| match pair[0] < pair[1] {
+                true => (pair[0], pair[1]),
+                false => (pair[1], pair[0]),
+            })
+            .collect()
+    }
+}
+
+/// Decode router swap calldata, including the dynamic `address[] path`.
+///
+/// `value` is the ETH sent with the transaction, used as the input amount
+/// of the ETH input functions.
+pub fn decode_swap_call(input: &[u8], value: U256) -> Result<SwapCall, DecodeError> {
+    if input.len() < 4 {
+        return Err(DecodeError::ShortCalldata { needed: 4, len: input.len() });
+    }
+    let method = SwapMethod::from_selector(&input[0..4])
+        .ok_or_else(|| DecodeError::UnknownMethod(hex::encode(&input[0..4])))?;
+
+    let params = method.params();
+    // every argument has a head word, the path head being its offset
+    let head_len = 4 + 32 * params.len();
+    if input.len() < head_len {
+        return Err(DecodeError::ShortCalldata { needed: head_len, len: input.len() });
+    }
+    // ethabi checks the path offset and length words against the calldata
+    let tokens = ethabi::decode(&params, &input[4..])
+        .map_err(|e| DecodeError::InvalidAbi(format!("{}: {}", method.name(), e)))?;
+
+    let uint = |token: &Token| token.clone().into_uint()
+        .ok_or_else(|| DecodeError::InvalidAbi(format!("{}: expected uint", method.name())));
+    let address = |token: &Token| token.clone().into_address()
+        .ok_or_else(|| DecodeError::InvalidAbi(format!("{}: expected address", method.name())));
+
+    let (amount_in, amount_out_min, rest) = match method.eth_input() {
+        true => (value, uint(&tokens[0])?, &tokens[1..]),
+        false => match method.exact_input() {
+            true => (uint(&tokens[0])?, uint(&tokens[1])?, &tokens[2..]),
+            // (amountOut, amountInMax, ..)
+            false => (uint(&tokens[1])?, uint(&tokens[0])?, &tokens[2..]),
+        },
+    };
+    let path = rest[0].clone().into_array()
+        .ok_or_else(|| DecodeError::InvalidAbi(format!("{}: expected address[]", method.name())))?
+        .iter()
+        .map(address)
+        .collect::<Result<Vec<H160>, DecodeError>>()?;
+    if path.len() < 2 {
+        return Err(DecodeError::InvalidAbi(
+            format!("{}: path has {} tokens, needs at least 2", method.name(), path.len())));
+    }
+
+    Ok(SwapCall {
+        method,
+        amount_in,
+        amount_out_min,
+        path,
+        to: address(&rest[1])?,
+        deadline: uint(&rest[2])?,
+    })
+}
+
+/// UniswapV2Router02 functions that add or remove pair liquidity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LiquidityMethod {
+    AddLiquidity,
+    AddLiquidityETH,
+    RemoveLiquidity,
+    RemoveLiquidityETH,
+    RemoveLiquidityWithPermit,
+    RemoveLiquidityETHWithPermit,
+    RemoveLiquidityETHSupportingFeeOnTransferTokens,
+    RemoveLiquidityETHWithPermitSupportingFeeOnTransferTokens,
+}
+
+impl LiquidityMethod {
+    pub const ALL: [LiquidityMethod; 8] = [
+        LiquidityMethod::AddLiquidity,
+        LiquidityMethod::AddLiquidityETH,
+        LiquidityMethod::RemoveLiquidity,
+        LiquidityMethod::RemoveLiquidityETH,
+        LiquidityMethod::RemoveLiquidityWithPermit,
+        LiquidityMethod::RemoveLiquidityETHWithPermit,
+        LiquidityMethod::RemoveLiquidityETHSupportingFeeOnTransferTokens,
+        LiquidityMethod::RemoveLiquidityETHWithPermitSupportingFeeOnTransferTokens,
+    ];
+
+    /// First four bytes of the keccak hash of the function signature.
+    pub fn selector(&self) -> [u8; 4] {
+        match self {
+            LiquidityMethod::AddLiquidity => [0xe8, 0xe3, 0x37, 0x00],
+            LiquidityMethod::AddLiquidityETH => [0xf3, 0x05, 0xd7, 0x19],
+            LiquidityMethod::RemoveLiquidity => [0xba, 0xa2, 0xab, 0xde],
+            LiquidityMethod::RemoveLiquidityETH => [0x02, 0x75, 0x1c, 0xec],
+            LiquidityMethod::RemoveLiquidityWithPermit => [0x21, 0x95, 0x99, 0x5c],
+            LiquidityMethod::RemoveLiquidityETHWithPermit => [0xde, 0xd9, 0x38, 0x2a],
+            LiquidityMethod::RemoveLiquidityETHSupportingFeeOnTransferTokens => [0xaf, 0x29, 0x79, 0xeb],
+            LiquidityMethod::RemoveLiquidityETHWithPermitSupportingFeeOnTransferTokens => [0x5b, 0x0d, 0x59, 0x84],
+        }
+    }
+
+    pub fn from_selector(selector: &[u8]) -> Option<LiquidityMethod> {
+        LiquidityMethod::ALL.iter()
+            .find(|method| method.selector() == selector)
+            .copied()
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            LiquidityMethod::AddLiquidity => "addLiquidity",
+            LiquidityMethod::AddLiquidityETH => "addLiquidityETH",
+            LiquidityMethod::RemoveLiquidity => "removeLiquidity",
+            LiquidityMethod::RemoveLiquidityETH => "removeLiquidityETH",
+            LiquidityMethod::RemoveLiquidityWithPermit => "removeLiquidityWithPermit",
+            LiquidityMethod::RemoveLiquidityETHWithPermit => "removeLiquidityETHWithPermit",
+            LiquidityMethod::RemoveLiquidityETHSupportingFeeOnTransferTokens =>
+                "removeLiquidityETHSupportingFeeOnTransferTokens",
+            LiquidityMethod::RemoveLiquidityETHWithPermitSupportingFeeOnTransferTokens =>
+                "removeLiquidityETHWithPermitSupportingFeeOnTransferTokens",
+        }
+    }
+
+    /// Liquidity is added, otherwise LP tokens are burnt for the reserves.
+    pub fn adds(&self) -> bool {
+        matches!(self, LiquidityMethod::AddLiquidity | LiquidityMethod::AddLiquidityETH)
+    }
+
+    /// One side of the pair is WETH, sent or received as plain ETH.
+    pub fn eth(&self) -> bool {
+        !matches!(self,
+                  LiquidityMethod::AddLiquidity
+                  | LiquidityMethod::RemoveLiquidity
+                  | LiquidityMethod::RemoveLiquidityWithPermit)
+    }
+
+    /// The approval for the LP tokens is passed as a signature.
+    pub fn permit(&self) -> bool {
+        matches!(self,
+                 LiquidityMethod::RemoveLiquidityWithPermit
+                 | LiquidityMethod::RemoveLiquidityETHWithPermit
+                 | LiquidityMethod::RemoveLiquidityETHWithPermitSupportingFeeOnTransferTokens)
+    }
+
+    // Solidity argument list, in calldata order.
+    fn params(&self) -> Vec<ParamType> {
+        let uint = ParamType::Uint(256);
+        // tokenA, tokenB | token
+        let mut params = match self.eth() {
+            true => vec![ParamType::Address],
+            false => vec![ParamType::Address, ParamType::Address],
+        };
+        // amountADesired, amountBDesired | amountTokenDesired, then the minimums,
+        // or liquidity and the minimums when removing
+        let amounts = match (self.adds(), self.eth()) {
+            (true, false) => 4,
+            _ => 3,
+        };
+        params.extend(vec![uint.clone(); amounts]);
+        // to, deadline
+        params.extend(vec![ParamType::Address, uint]);
+        if self.permit() {
+            // approveMax, v, r, s
+            params.extend(vec![ParamType::Bool, ParamType::Uint(8),
+                               ParamType::FixedBytes(32), ParamType::FixedBytes(32)]);
+        }
+        params
+    }
+}
+
+/// Arguments of a router liquidity call.
+///
+/// When adding, `amount_a` and `amount_b` are the desired deposits and
+/// `liquidity` is zero. When removing, `liquidity` is the LP tokens burnt and
+/// the desired amounts are zero. The minimums apply either way.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LiquidityCall {
+    pub method: LiquidityMethod,
+    pub token_a: H160,
+    // None for the ETH variants, the other side being WETH
+    pub token_b: Option<H160>,
+    pub amount_a: U256,
+    // transaction value for addLiquidityETH
+    pub amount_b: U256,
+    pub liquidity: U256,
+    pub amount_a_min: U256,
+    pub amount_b_min: U256,
+    pub to: H160,
+    pub deadline: U256,
+}
+
+/// Decode router liquidity calldata. `value` is the ETH sent with the
+/// transaction, used as the WETH deposit of `addLiquidityETH`.
+pub fn decode_liquidity_call(input: &[u8], value: U256) -> Result<LiquidityCall, DecodeError> {
+    if input.len() < 4 {
+        return Err(DecodeError::ShortCalldata { needed: 4, len: input.len() });
+    }
+    let method = LiquidityMethod::from_selector(&input[0..4])
+        .ok_or_else(|| DecodeError::UnknownMethod(hex::encode(&input[0..4])))?;
+
+    let params = method.params();
+    let head_len = 4 + 32 * params.len();
+    if input.len() < head_len {
+        return Err(DecodeError::ShortCalldata { needed: head_len, len: input.len() });
+    }
+    let tokens = ethabi::decode(&params, &input[4..])
+        .map_err(|e| DecodeError::InvalidAbi(format!("{}: {}", method.name(), e)))?;
+
+    let uint = |token: &Token| token.clone().into_uint()
+        .ok_or_else(|| DecodeError::InvalidAbi(format!("{}: expected uint", method.name())));
+    let address = |token: &Token| token.clone().into_address()
+        .ok_or_else(|| DecodeError::InvalidAbi(format!("{}: expected address", method.name())));
+
+    let (token_a, token_b, rest) = match method.eth() {
+        true => (address(&tokens[0])?, None, &tokens[1..]),
+        false => (address(&tokens[0])?, Some(address(&tokens[1])?), &tokens[2..]),
+    };
+    let (amount_a, amount_b, liquidity, rest) = match (method.adds(), method.eth()) {
+        (true, true) => (uint(&rest[0])?, value, U256::zero(), &rest[1..]),
+        (true, false) => (uint(&rest[0])?, uint(&rest[1])?, U256::zero(), &rest[2..]),
+        (false, _) => (U256::zero(), U256::zero(), uint(&rest[0])?, &rest[1..]),
+    };
+
+    Ok(LiquidityCall {
+        method,
+        token_a,
+        token_b,
+        amount_a,
+        amount_b,
+        liquidity,
+        amount_a_min: uint(&rest[0])?,
+        amount_b_min: uint(&rest[1])?,
+        to: address(&rest[2])?,
+        deadline: uint(&rest[3])?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(n: u64) -> H160 {
+        H160::from_low_u64_be(n)
+    }
+
+    fn path(tokens: &[u64]) -> Token {
+        Token::Array(tokens.iter().map(|token| Token::Address(address(*token))).collect())
+    }
+
+    fn calldata(selector: [u8; 4], args: &[Token]) -> Vec<u8> {
+        let mut input = selector.to_vec();
+        input.extend(ethabi::encode(args));
+        input
+    }
+
+    fn uint(n: u64) -> Token {
+        Token::Uint(n.into())
+    }
+
+    #[test]
+    fn exact_input_swaps() {
+        let input = calldata(SwapMethod::SwapExactTokensForTokens.selector(),
+                             &[uint(1000), uint(990), path(&[1, 2, 3]), Token::Address(address(0xbb)), uint(77)]);
+        let call = decode_swap_call(&input, U256::zero()).unwrap();
+        assert_eq!(call, SwapCall {
+            method: SwapMethod::SwapExactTokensForTokens,
+            amount_in: 1000.into(),
+            amount_out_min: 990.into(),
+            path: vec![address(1), address(2), address(3)],
+            to: address(0xbb),
+            deadline: 77.into(),
+        });
+        assert_eq!(call.pairs(), vec![(address(1), address(2)), (address(2), address(3))]);
+    }
+
+    #[test]
+    fn eth_input_swaps_take_the_transaction_value() {
+        for method in [SwapMethod::SwapExactETHForTokens, SwapMethod::SwapETHForExactTokens,
+                       SwapMethod::SwapExactETHForTokensSupportingFeeOnTransferTokens] {
+            let input = calldata(method.selector(),
+                                 &[uint(990), path(&[1, 2]), Token::Address(address(0xbb)), uint(77)]);
+            let call = decode_swap_call(&input, U256::from(5000)).unwrap();
+            assert_eq!((call.amount_in, call.amount_out_min), (5000.into(), 990.into()), "{}", method.name());
+            assert_eq!((call.to, call.deadline), (address(0xbb), 77.into()));
+        }
+    }
+
+    #[test]
+    fn exact_output_swaps_swap_the_amounts() {
+        for method in [SwapMethod::SwapTokensForExactTokens, SwapMethod::SwapTokensForExactETH] {
+            // (amountOut, amountInMax, ..)
+            let input = calldata(method.selector(),
+                                 &[uint(500), uint(900), path(&[2, 1]), Token::Address(address(0xbb)), uint(77)]);
+            let call = decode_swap_call(&input, U256::zero()).unwrap();
+            assert_eq!((call.amount_in, call.amount_out_min), (900.into(), 500.into()), "{}", method.name());
+            assert_eq!((call.start_token(), call.end_token()), (address(2), address(1)));
+        }
+    }
+
+    #[test]
+    fn malformed_swaps_are_errors() {
+        let selector = SwapMethod::SwapExactTokensForTokens.selector();
+        let args = [uint(1000), uint(990), path(&[1]), Token::Address(address(0xbb)), uint(77)];
+        assert!(matches!(decode_swap_call(&calldata(selector, &args), U256::zero()),
+                         Err(DecodeError::InvalidAbi(message)) if message.contains("path has 1 tokens")));
+
+        // path offset far past the end of the calldata
+        let mut input = calldata(selector, &[uint(1000), uint(990), path(&[1, 2]),
+                                             Token::Address(address(0xbb)), uint(77)]);
+        input[4 + 64..4 + 96].copy_from_slice(&[0xff; 32]);
+        assert!(matches!(decode_swap_call(&input, U256::zero()), Err(DecodeError::InvalidAbi(_))));
+
+        let input = calldata(selector, &[uint(1000), uint(990)]);
+        assert_eq!(decode_swap_call(&input, U256::zero()),
+                   Err(DecodeError::ShortCalldata { needed: 164, len: 68 }));
+        assert_eq!(decode_swap_call(&selector[..3], U256::zero()),
+                   Err(DecodeError::ShortCalldata { needed: 4, len: 3 }));
+        assert_eq!(decode_swap_call(&[0xde, 0xad, 0xbe, 0xef], U256::zero()),
+                   Err(DecodeError::UnknownMethod("deadbeef".to_string())));
+    }
+
+    #[test]
+    fn liquidity_calls() {
+        let input = calldata(LiquidityMethod::AddLiquidity.selector(),
+                             &[Token::Address(address(1)), Token::Address(address(2)), uint(100), uint(200),
+                               uint(90), uint(180), Token::Address(address(0xbb)), uint(77)]);
+        assert_eq!(decode_liquidity_call(&input, U256::zero()).unwrap(), LiquidityCall {
+            method: LiquidityMethod::AddLiquidity,
+            token_a: address(1),
+            token_b: Some(address(2)),
+            amount_a: 100.into(),
+            amount_b: 200.into(),
+            liquidity: U256::zero(),
+            amount_a_min: 90.into(),
+            amount_b_min: 180.into(),
+            to: address(0xbb),
+            deadline: 77.into(),
+        });
+
+        // (token, amountTokenDesired, amountTokenMin, amountETHMin, to, deadline)
+        let input = calldata(LiquidityMethod::AddLiquidityETH.selector(),
+                             &[Token::Address(address(1)), uint(100), uint(90), uint(180),
+                               Token::Address(address(0xbb)), uint(77)]);
+        let call = decode_liquidity_call(&input, U256::from(200)).unwrap();
+        assert_eq!((call.token_b, call.amount_a, call.amount_b), (None, 100.into(), 200.into()));
+        assert_eq!((call.amount_a_min, call.amount_b_min), (90.into(), 180.into()));
+
+        // (tokenA, tokenB, liquidity, amountAMin, amountBMin, to, deadline, approveMax, v, r, s)
+        let input = calldata(LiquidityMethod::RemoveLiquidityWithPermit.selector(),
+                             &[Token::Address(address(1)), Token::Address(address(2)), uint(50), uint(90),
+                               uint(180), Token::Address(address(0xbb)), uint(77), Token::Bool(false),
+                               uint(27), Token::FixedBytes(vec![1; 32]), Token::FixedBytes(vec![2; 32])]);
+        let call = decode_liquidity_call(&input, U256::zero()).unwrap();
+        assert_eq!((call.amount_a, call.amount_b, call.liquidity), (U256::zero(), U256::zero(), 50.into()));
+        assert_eq!((call.to, call.deadline), (address(0xbb), 77.into()));
+    }
+
+    #[test]
+    fn malformed_liquidity_calls_are_errors() {
+        let selector = LiquidityMethod::RemoveLiquidityETH.selector();
+        let input = calldata(selector, &[Token::Address(address(1)), uint(50), uint(90)]);
+        assert_eq!(decode_liquidity_call(&input, U256::zero()),
+                   Err(DecodeError::ShortCalldata { needed: 196, len: 100 }));
+        assert_eq!(decode_liquidity_call(&[], U256::zero()),
+                   Err(DecodeError::ShortCalldata { needed: 4, len: 0 }));
+        // a swap is not a liquidity call
+        let swap = SwapMethod::SwapExactTokensForTokens.selector();
+        assert_eq!(decode_liquidity_call(&swap, U256::zero()),
+                   Err(DecodeError::UnknownMethod(hex::encode(swap))));
+    }
+}
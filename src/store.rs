@@ -0,0 +1,715 @@
+use rusqlite::{params, Connection, OptionalExtension};
+use web3::types::{H160, H256, U256};
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::Path;
+
+use crate::amount::I256;
+use crate::ledger::{GasCost, Trade};
+use crate::liquidity::{LpBook, LpPosition, PairState};
+use crate::pools::{PoolRegistry, ReserveSnapshot};
+use crate::swaps::PairTokens;
+use crate::v3::{V3Book, V3Pool};
+use crate::Trader;
+
+// Addresses and hashes are stored as blobs, token paths as the concatenated
+// addresses, and amounts as decimal text since they overflow INTEGER.
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS checkpoint (
+    id INTEGER PRIMARY KEY CHECK (id = 1),
+    first_block INTEGER NOT NULL,
+    next_block INTEGER NOT NULL,
+    from_logs INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS trades (
+    tx_hash BLOB NOT NULL,
+    log_index INTEGER NOT NULL,
+    block INTEGER NOT NULL,
+    tx_index INTEGER NOT NULL,
+    trader BLOB NOT NULL,
+    venue TEXT NOT NULL,
+    path BLOB NOT NULL,
+    pools BLOB NOT NULL,
+    amount_in TEXT NOT NULL,
+    amount_out TEXT NOT NULL,
+    cost TEXT,
+    reserves_before TEXT NOT NULL,
+    reserves_after TEXT NOT NULL,
+    gas_used TEXT NOT NULL,
+    effective_gas_price TEXT NOT NULL,
+    gas_burned TEXT NOT NULL,
+    gas_tip TEXT NOT NULL,
+    PRIMARY KEY (tx_hash, log_index)
+);
+CREATE INDEX IF NOT EXISTS trades_by_trader ON trades (trader, block, tx_index, log_index);
+CREATE TABLE IF NOT EXISTS reserve_snapshots (
+    pool BLOB NOT NULL,
+    block INTEGER NOT NULL,
+    tx_index INTEGER NOT NULL,
+    log_index INTEGER NOT NULL,
+    reserve0 TEXT NOT NULL,
+    reserve1 TEXT NOT NULL,
+    PRIMARY KEY (pool, block, tx_index, log_index)
+);
+CREATE TABLE IF NOT EXISTS pools (
+    pool BLOB PRIMARY KEY,
+    token0 BLOB NOT NULL,
+    token1 BLOB NOT NULL
+);
+CREATE TABLE IF NOT EXISTS pair_tokens (
+    pair BLOB PRIMARY KEY,
+    token0 BLOB NOT NULL,
+    token1 BLOB NOT NULL
+);
+CREATE TABLE IF NOT EXISTS traders (
+    address BLOB PRIMARY KEY,
+    txs INTEGER NOT NULL,
+    trades INTEGER NOT NULL,
+    gas_used TEXT NOT NULL,
+    gas_fees TEXT NOT NULL,
+    gas_burned TEXT NOT NULL,
+    gas_tips TEXT NOT NULL,
+    cost TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS holdings (
+    trader BLOB NOT NULL,
+    token BLOB NOT NULL,
+    amount TEXT NOT NULL,
+    PRIMARY KEY (trader, token)
+);
+CREATE TABLE IF NOT EXISTS lp_pairs (
+    pair BLOB PRIMARY KEY,
+    token0 BLOB NOT NULL,
+    token1 BLOB NOT NULL,
+    reserve0 TEXT NOT NULL,
+    reserve1 TEXT NOT NULL,
+    total_supply TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS lp_positions (
+    owner BLOB NOT NULL,
+    pair BLOB NOT NULL,
+    token0 BLOB NOT NULL,
+    token1 BLOB NOT NULL,
+    liquidity TEXT NOT NULL,
+    deposited0 TEXT NOT NULL,
+    deposited1 TEXT NOT NULL,
+    withdrawn0 TEXT NOT NULL,
+    withdrawn1 TEXT NOT NULL,
+    hodl0 TEXT NOT NULL,
+    hodl1 TEXT NOT NULL,
+    entry_growth TEXT NOT NULL,
+    PRIMARY KEY (owner, pair)
+);
+CREATE TABLE IF NOT EXISTS v3_pools (
+    pool BLOB PRIMARY KEY,
+    token0 BLOB NOT NULL,
+    token1 BLOB NOT NULL,
+    fee INTEGER,
+    sqrt_price_x96 TEXT NOT NULL,
+    liquidity TEXT NOT NULL,
+    tick INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS v3_ticks (
+    pool BLOB NOT NULL,
+    tick INTEGER NOT NULL,
+    liquidity_net TEXT NOT NULL,
+    PRIMARY KEY (pool, tick)
+);
+CREATE TABLE IF NOT EXISTS v3_fees (
+    pool BLOB PRIMARY KEY,
+    fee INTEGER NOT NULL
+);
+";
+
+/// How far the stored scan got.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Checkpoint {
+    // first block the store has seen
+    pub first_block: u64,
+    // first block not processed yet
+    pub next_block: u64,
+    pub from_logs: bool,
+}
+
+/// Everything a scan carries from one block to the next.
+#[derive(Debug, Clone, Copy)]
+pub struct ScanState<'a> {
+    pub traders: &'a HashMap<H160, Trader>,
+    pub pools: &'a PoolRegistry,
+    pub pair_tokens: &'a PairTokens,
+    pub lp_book: &'a LpBook,
+    pub v3_book: &'a V3Book,
+}
+
+/// The state of a scan restored from the store.
+#[derive(Debug, Clone)]
+pub struct RestoredState {
+    pub traders: HashMap<H160, Trader>,
+    pub pools: PoolRegistry,
+    pub pair_tokens: PairTokens,
+    pub lp_book: LpBook,
+    pub v3_book: V3Book,
+}
+
+fn address(bytes: &[u8]) -> Result<H160, Box<dyn Error>> {
+    match bytes.len() == 20 {
+        true => Ok(H160::from_slice(bytes)),
+        false => Err(format!("stored address has {} bytes", bytes.len()).into()),
+    }
+}
+
+fn addresses(bytes: &[u8]) -> Result<Vec<H160>, Box<dyn Error>> {
+    match bytes.len().is_multiple_of(20) {
+        true => Ok(bytes.chunks(20).map(H160::from_slice).collect()),
+        false => Err(format!("stored address list has {} bytes", bytes.len()).into()),
+    }
+}
+
+fn concat(addresses: &[H160]) -> Vec<u8> {
+    addresses.iter().flat_map(|address| address.as_bytes().iter().copied()).collect()
+}
+
+fn amount(text: &str) -> Result<U256, Box<dyn Error>> {
+    U256::from_dec_str(text).map_err(|e| format!("stored amount {}: {:?}", text, e).into())
+}
+
+fn signed_amount(text: &str) -> Result<I256, Box<dyn Error>> {
+    match text.strip_prefix('-') {
+        Some(abs) => Ok(I256::from_parts(true, amount(abs)?)),
+        None => Ok(I256::from(amount(text)?)),
+    }
+}
+
+/// SQLite file holding the trade ledger, reserve history, trader totals and
+/// pool state of a scan, plus the block it stopped at so the next run can
+/// carry on from there.
+pub struct Store {
+    conn: Connection,
+}
+
+impl Store {
+    /// Open the store at `path`, creating the file and tables if needed.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Store, Box<dyn Error>> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Store { conn })
+    }
+
+    /// None until the first save.
+    pub fn checkpoint(&self) -> Result<Option<Checkpoint>, Box<dyn Error>> {
+        let checkpoint = self.conn
+            .query_row("SELECT first_block, next_block, from_logs FROM checkpoint WHERE id = 1", [],
+                       |row| Ok(Checkpoint {
+                           first_block: row.get(0)?,
+                           next_block: row.get(1)?,
+                           from_logs: row.get(2)?,
+                       }))
+            .optional()?;
+        Ok(checkpoint)
+    }
+
+    /// Write the state after the blocks before `checkpoint.next_block`. Only
+    /// the trades and snapshots from `since_block` on are written, the ones
+    /// before are stored already; the rest of the state is replaced. All of it
+    /// lands in one transaction, so an interrupted save leaves the previous
+    /// checkpoint intact.
+    pub fn save(&mut self, state: ScanState, checkpoint: Checkpoint, since_block: u64)
+    -> Result<(), Box<dyn Error>> {
+        let tx = self.conn.transaction()?;
+        {
+            let mut insert = tx.prepare(
+                "INSERT OR REPLACE INTO trades VALUES \
+                 (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17)")?;
+            for trade in state.traders.values().flat_map(|t| &t.trades)
+                .filter(|trade| trade.block >= since_block) {
+                insert.execute(params![
+                    trade.tx_hash.as_bytes(), trade.log_index, trade.block, trade.tx_index,
+                    trade.trader.as_bytes(), trade.venue, concat(&trade.path), concat(&trade.pools),
+                    trade.amount_in.to_string(), trade.amount_out.to_string(),
+                    trade.cost.map(|cost| cost.to_string()),
+                    serde_json::to_string(&trade.reserves_before)?,
+                    serde_json::to_string(&trade.reserves_after)?,
+                    trade.gas.gas_used.to_string(), trade.gas.effective_gas_price.to_string(),
+                    trade.gas.base_fee.to_string(), trade.gas.priority_fee.to_string(),
+                ])?;
+            }
+
+            let mut insert_pool = tx.prepare("INSERT OR IGNORE INTO pools VALUES (?1, ?2, ?3)")?;
+            let mut insert_snapshot = tx.prepare(
+                "INSERT OR REPLACE INTO reserve_snapshots VALUES (?1, ?2, ?3, ?4, ?5, ?6)")?;
+            for pool in state.pools.iter() {
+                let (token0, token1) = pool.tokens();
+                insert_pool.execute(params![pool.address().as_bytes(), token0.as_bytes(), token1.as_bytes()])?;
+                for snapshot in state.pools.history(&pool.address()).iter()
+                    .filter(|snapshot| snapshot.block >= since_block) {
+                    insert_snapshot.execute(params![
+                        pool.address().as_bytes(), snapshot.block, snapshot.tx_index, snapshot.log_index,
+                        snapshot.reserve0.to_string(), snapshot.reserve1.to_string(),
+                    ])?;
+                }
+            }
+
+            let mut insert = tx.prepare("INSERT OR IGNORE INTO pair_tokens VALUES (?1, ?2, ?3)")?;
+            for (pair, (token0, token1)) in state.pair_tokens {
+                insert.execute(params![pair.as_bytes(), token0.as_bytes(), token1.as_bytes()])?;
+            }
+
+            let mut insert_trader = tx.prepare(
+                "INSERT OR REPLACE INTO traders VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)")?;
+            let mut insert_holding = tx.prepare("INSERT OR REPLACE INTO holdings VALUES (?1, ?2, ?3)")?;
+            for t in state.traders.values() {
+                insert_trader.execute(params![
+                    t.address.as_bytes(), t.cum_txs, t.trades.len(), t.cum_gas.to_string(),
+                    t.gas_fees.to_string(), t.gas_burned.to_string(), t.gas_tips.to_string(),
+                    t.hist_cost.to_string(),
+                ])?;
+                for (token, amount) in &t.holdings {
+                    insert_holding.execute(params![t.address.as_bytes(), token.as_bytes(), amount.to_string()])?;
+                }
+            }
+
+            let mut insert = tx.prepare("INSERT OR REPLACE INTO lp_pairs VALUES (?1, ?2, ?3, ?4, ?5, ?6)")?;
+            for (pair, state) in &state.lp_book.pairs {
+                let (token0, token1) = state.tokens;
+                let (reserve0, reserve1) = state.reserves;
+                insert.execute(params![pair.as_bytes(), token0.as_bytes(), token1.as_bytes(),
+                                       reserve0.to_string(), reserve1.to_string(),
+                                       state.total_supply.to_string()])?;
+            }
+            let mut insert = tx.prepare(
+                "INSERT OR REPLACE INTO lp_positions VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)")?;
+            for position in state.lp_book.positions.values() {
+                insert.execute(params![
+                    position.owner.as_bytes(), position.pair.as_bytes(),
+                    position.tokens.0.as_bytes(), position.tokens.1.as_bytes(), position.liquidity.to_string(),
+                    position.deposited.0.to_string(), position.deposited.1.to_string(),
+                    position.withdrawn.0.to_string(), position.withdrawn.1.to_string(),
+                    position.hodl.0.to_string(), position.hodl.1.to_string(), position.entry_growth.to_string(),
+                ])?;
+            }
+
+            let mut insert_pool = tx.prepare(
+                "INSERT OR REPLACE INTO v3_pools VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)")?;
+            let mut insert_tick = tx.prepare("INSERT INTO v3_ticks VALUES (?1, ?2, ?3)")?;
+            tx.execute("DELETE FROM v3_ticks", [])?;
+            for pool in state.v3_book.pools.values() {
+                insert_pool.execute(params![
+                    pool.address.as_bytes(), pool.token0.as_bytes(), pool.token1.as_bytes(), pool.fee,
+                    pool.sqrt_price_x96.to_string(), pool.liquidity.to_string(), pool.tick,
+                ])?;
+                for (tick, liquidity_net) in &pool.ticks {
+                    insert_tick.execute(params![pool.address.as_bytes(), tick, liquidity_net.to_string()])?;
+                }
+            }
+            let mut insert = tx.prepare("INSERT OR REPLACE INTO v3_fees VALUES (?1, ?2)")?;
+            for (pool, fee) in &state.v3_book.fees {
+                insert.execute(params![pool.as_bytes(), fee])?;
+            }
+
+            tx.execute("INSERT OR REPLACE INTO checkpoint VALUES (1, ?1, ?2, ?3)",
+                       params![checkpoint.first_block, checkpoint.next_block, checkpoint.from_logs])?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Rebuild the state of the last save. Pools are admitted again through
+    /// `dexes`, so a pair whose DEX is no longer registered is dropped.
+    pub fn restore(&self, pools: PoolRegistry) -> Result<RestoredState, Box<dyn Error>> {
+        Ok(RestoredState {
+            traders: self.load_traders()?,
+            pools: self.load_pools(pools)?,
+            pair_tokens: self.load_pair_tokens()?,
+            lp_book: self.load_lp_book()?,
+            v3_book: self.load_v3_book()?,
+        })
+    }
+
+    // Trader totals and holdings as saved, with the ledger in chain order.
+    // The totals include liquidity-only transactions, so they are not
+    // replayed from the ledger.
+    fn load_traders(&self) -> Result<HashMap<H160, Trader>, Box<dyn Error>> {
+        let mut traders = HashMap::new();
+        let mut select = self.conn.prepare(
+            "SELECT address, txs, gas_used, gas_fees, gas_burned, gas_tips, cost FROM traders")?;
+        let rows = select.query_map([], |row| Ok((
+            row.get::<_, Vec<u8>>(0)?, row.get::<_, usize>(1)?, row.get::<_, String>(2)?,
+            row.get::<_, String>(3)?, row.get::<_, String>(4)?, row.get::<_, String>(5)?,
+            row.get::<_, String>(6)?,
+        )))?;
+        for row in rows {
+            let (address_bytes, txs, gas_used, gas_fees, gas_burned, gas_tips, cost) = row?;
+            let mut t = Trader::new();
+            t.address = address(&address_bytes)?;
+            t.cum_txs = txs;
+            t.cum_gas = amount(&gas_used)?;
+            t.gas_fees = amount(&gas_fees)?;
+            t.gas_burned = amount(&gas_burned)?;
+            t.gas_tips = amount(&gas_tips)?;
+            t.hist_cost = amount(&cost)?;
+            traders.insert(t.address, t);
+        }
+
+        let mut select = self.conn.prepare("SELECT trader, token, amount FROM holdings")?;
+        let rows = select.query_map([], |row| Ok((
+            row.get::<_, Vec<u8>>(0)?, row.get::<_, Vec<u8>>(1)?, row.get::<_, String>(2)?,
+        )))?;
+        for row in rows {
+            let (trader, token, held) = row?;
+            if let Some(t) = traders.get_mut(&address(&trader)?) {
+                t.holdings.insert(address(&token)?, signed_amount(&held)?);
+            }
+        }
+
+        let mut select = self.conn.prepare(
+            "SELECT tx_hash, log_index, block, tx_index, trader, venue, path, pools, amount_in, amount_out, \
+             cost, reserves_before, reserves_after, gas_used, effective_gas_price, gas_burned, gas_tip \
+             FROM trades ORDER BY block, tx_index, log_index")?;
+        let rows = select.query_map([], |row| Ok((
+            (row.get::<_, Vec<u8>>(0)?, row.get::<_, usize>(1)?, row.get::<_, u64>(2)?, row.get::<_, u64>(3)?,
+             row.get::<_, Vec<u8>>(4)?, row.get::<_, String>(5)?, row.get::<_, Vec<u8>>(6)?,
+             row.get::<_, Vec<u8>>(7)?),
+            (row.get::<_, String>(8)?, row.get::<_, String>(9)?, row.get::<_, Option<String>>(10)?,
+             row.get::<_, String>(11)?, row.get::<_, String>(12)?),
+            (row.get::<_, String>(13)?, row.get::<_, String>(14)?, row.get::<_, String>(15)?,
+             row.get::<_, String>(16)?),
+        )))?;
+        for row in rows {
+            let ((tx_hash, log_index, block, tx_index, trader, venue, path, pools),
+                 (amount_in, amount_out, cost, reserves_before, reserves_after),
+                 (gas_used, effective_gas_price, gas_burned, gas_tip)) = row?;
+            if tx_hash.len() != 32 {
+                return Err(format!("stored tx hash has {} bytes", tx_hash.len()).into());
+            }
+            let trade = Trade {
+                tx_hash: H256::from_slice(&tx_hash),
+                block,
+                tx_index,
+                log_index,
+                trader: address(&trader)?,
+                venue,
+                path: addresses(&path)?,
+                pools: addresses(&pools)?,
+                amount_in: amount(&amount_in)?,
+                amount_out: amount(&amount_out)?,
+                cost: cost.as_deref().map(amount).transpose()?,
+                reserves_before: serde_json::from_str(&reserves_before)?,
+                reserves_after: serde_json::from_str(&reserves_after)?,
+                gas: GasCost {
+                    gas_used: amount(&gas_used)?,
+                    effective_gas_price: amount(&effective_gas_price)?,
+                    base_fee: amount(&gas_burned)?,
+                    priority_fee: amount(&gas_tip)?,
+                },
+            };
+            if trade.path.len() < 2 {
+                return Err(format!("stored trade {:?} has no token path", trade.tx_hash).into());
+            }
+            let t = traders.entry(trade.trader).or_insert_with(Trader::new);
+            t.address = trade.trader;
+            *t.venues.entry(trade.venue.clone()).or_insert(0) += 1;
+            t.trades.push(trade);
+        }
+        Ok(traders)
+    }
+
+    // Replay the reserve history into `pools` in chain order.
+    fn load_pools(&self, mut pools: PoolRegistry) -> Result<PoolRegistry, Box<dyn Error>> {
+        let mut select = self.conn.prepare(
+            "SELECT s.pool, p.token0, p.token1, s.block, s.tx_index, s.log_index, s.reserve0, s.reserve1 \
+             FROM reserve_snapshots s JOIN pools p ON p.pool = s.pool \
+             ORDER BY s.block, s.tx_index, s.log_index")?;
+        let rows = select.query_map([], |row| Ok((
+            row.get::<_, Vec<u8>>(0)?, row.get::<_, Vec<u8>>(1)?, row.get::<_, Vec<u8>>(2)?,
+            row.get::<_, u64>(3)?, row.get::<_, u64>(4)?, row.get::<_, u64>(5)?,
+            row.get::<_, String>(6)?, row.get::<_, String>(7)?,
+        )))?;
+        for row in rows {
+            let (pool, token0, token1, block, tx_index, log_index, reserve0, reserve1) = row?;
+            pools.record(address(&pool)?, (address(&token0)?, address(&token1)?), ReserveSnapshot {
+                block,
+                tx_index,
+                log_index,
+                reserve0: amount(&reserve0)?,
+                reserve1: amount(&reserve1)?,
+            });
+        }
+        Ok(pools)
+    }
+
+    fn load_pair_tokens(&self) -> Result<PairTokens, Box<dyn Error>> {
+        let mut pair_tokens = PairTokens::new();
+        let mut select = self.conn.prepare("SELECT pair, token0, token1 FROM pair_tokens")?;
+        let rows = select.query_map([], |row| Ok((
+            row.get::<_, Vec<u8>>(0)?, row.get::<_, Vec<u8>>(1)?, row.get::<_, Vec<u8>>(2)?,
+        )))?;
+        for row in rows {
+            let (pair, token0, token1) = row?;
+            pair_tokens.insert(address(&pair)?, (address(&token0)?, address(&token1)?));
+        }
+        Ok(pair_tokens)
+    }
+
+    fn load_lp_book(&self) -> Result<LpBook, Box<dyn Error>> {
+        let mut lp_book = LpBook::new();
+        let mut select = self.conn.prepare(
+            "SELECT pair, token0, token1, reserve0, reserve1, total_supply FROM lp_pairs")?;
+        let rows = select.query_map([], |row| Ok((
+            row.get::<_, Vec<u8>>(0)?, row.get::<_, Vec<u8>>(1)?, row.get::<_, Vec<u8>>(2)?,
+            row.get::<_, String>(3)?, row.get::<_, String>(4)?, row.get::<_, String>(5)?,
+        )))?;
+        for row in rows {
+            let (pair, token0, token1, reserve0, reserve1, total_supply) = row?;
+            let pair = address(&pair)?;
+            lp_book.pairs.insert(pair, PairState {
+                tokens: (address(&token0)?, address(&token1)?),
+                reserves: (amount(&reserve0)?, amount(&reserve1)?),
+                total_supply: amount(&total_supply)?,
+            });
+        }
+
+        let mut select = self.conn.prepare(
+            "SELECT owner, pair, token0, token1, liquidity, deposited0, deposited1, withdrawn0, withdrawn1, \
+             hodl0, hodl1, entry_growth FROM lp_positions")?;
+        let rows = select.query_map([], |row| Ok((
+            (row.get::<_, Vec<u8>>(0)?, row.get::<_, Vec<u8>>(1)?, row.get::<_, Vec<u8>>(2)?,
+             row.get::<_, Vec<u8>>(3)?),
+            (row.get::<_, String>(4)?, row.get::<_, String>(5)?, row.get::<_, String>(6)?,
+             row.get::<_, String>(7)?, row.get::<_, String>(8)?, row.get::<_, String>(9)?,
+             row.get::<_, String>(10)?),
+            row.get::<_, String>(11)?,
+        )))?;
+        for row in rows {
+            let ((owner, pair, token0, token1),
+                 (liquidity, deposited0, deposited1, withdrawn0, withdrawn1, hodl0, hodl1),
+                 entry_growth) = row?;
+            let position = LpPosition {
+                owner: address(&owner)?,
+                pair: address(&pair)?,
+                tokens: (address(&token0)?, address(&token1)?),
+                liquidity: amount(&liquidity)?,
+                deposited: (amount(&deposited0)?, amount(&deposited1)?),
+                withdrawn: (amount(&withdrawn0)?, amount(&withdrawn1)?),
+                hodl: (amount(&hodl0)?, amount(&hodl1)?),
+                entry_growth: amount(&entry_growth)?,
+            };
+            lp_book.positions.insert((position.owner, position.pair), position);
+        }
+        Ok(lp_book)
+    }
+
+    fn load_v3_book(&self) -> Result<V3Book, Box<dyn Error>> {
+        let mut v3_book = V3Book::new();
+        let mut select = self.conn.prepare(
+            "SELECT pool, token0, token1, fee, sqrt_price_x96, liquidity, tick FROM v3_pools")?;
+        let rows = select.query_map([], |row| Ok((
+            row.get::<_, Vec<u8>>(0)?, row.get::<_, Vec<u8>>(1)?, row.get::<_, Vec<u8>>(2)?,
+            row.get::<_, Option<u32>>(3)?, row.get::<_, String>(4)?, row.get::<_, String>(5)?,
+            row.get::<_, i32>(6)?,
+        )))?;
+        for row in rows {
+            let (pool, token0, token1, fee, sqrt_price_x96, liquidity, tick) = row?;
+            let mut pool = V3Pool::new(address(&pool)?, address(&token0)?, address(&token1)?, fee);
+            pool.sqrt_price_x96 = amount(&sqrt_price_x96)?;
+            pool.liquidity = liquidity.parse()?;
+            pool.tick = tick;
+            v3_book.pools.insert(pool.address, pool);
+        }
+
+        let mut select = self.conn.prepare("SELECT pool, tick, liquidity_net FROM v3_ticks")?;
+        let rows = select.query_map([], |row| Ok((
+            row.get::<_, Vec<u8>>(0)?, row.get::<_, i32>(1)?, row.get::<_, String>(2)?,
+        )))?;
+        for row in rows {
+            let (pool, tick, liquidity_net) = row?;
+            if let Some(pool) = v3_book.pools.get_mut(&address(&pool)?) {
+                pool.ticks.insert(tick, liquidity_net.parse()?);
+            }
+        }
+
+        let mut select = self.conn.prepare("SELECT pool, fee FROM v3_fees")?;
+        let rows = select.query_map([], |row| Ok((row.get::<_, Vec<u8>>(0)?, row.get::<_, u32>(1)?)))?;
+        for row in rows {
+            let (pool, fee) = row?;
+            v3_book.fees.insert(address(&pool)?, fee);
+        }
+        Ok(v3_book)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dex::{Dex, DexKind, DexRegistry};
+
+    fn address(n: u64) -> H160 {
+        H160::from_low_u64_be(n)
+    }
+
+    fn dexes() -> DexRegistry {
+        let mut dexes = DexRegistry::new();
+        dexes.insert(Dex {
+            name: "test-v2".to_string(),
+            kind: DexKind::V2,
+            routers: vec![],
+            factory: address(0xfa),
+            init_code_hash: H256::repeat_byte(0x11),
+            fee_bps: 25,
+        });
+        dexes
+    }
+
+    fn pair() -> H160 {
+        dexes().iter().next().unwrap().pair_address(address(1), address(2))
+    }
+
+    fn trade(block: u64, cost: Option<u64>) -> Trade {
+        Trade {
+            tx_hash: H256::from_low_u64_be(block),
+            block,
+            tx_index: 3,
+            log_index: 4,
+            trader: address(0xa),
+            venue: "test-v2".to_string(),
+            path: vec![address(1), address(2)],
+            pools: vec![pair()],
+            amount_in: U256::from(1_000),
+            amount_out: U256::exp10(30),
+            cost: cost.map(U256::from),
+            reserves_before: vec![Some((U256::from(10), U256::from(20)))],
+            reserves_after: vec![None],
+            gas: GasCost::new(U256::from(21_000), U256::from(7), Some(U256::from(5))),
+        }
+    }
+
+    fn snapshot(block: u64, reserve0: u64, reserve1: u64) -> ReserveSnapshot {
+        ReserveSnapshot {
+            block,
+            tx_index: 3,
+            log_index: 1,
+            reserve0: U256::from(reserve0),
+            reserve1: U256::from(reserve1),
+        }
+    }
+
+    struct Scan {
+        traders: HashMap<H160, Trader>,
+        pools: PoolRegistry,
+        pair_tokens: PairTokens,
+        lp_book: LpBook,
+        v3_book: V3Book,
+    }
+
+    impl Scan {
+        fn state(&self) -> ScanState<'_> {
+            ScanState {
+                traders: &self.traders,
+                pools: &self.pools,
+                pair_tokens: &self.pair_tokens,
+                lp_book: &self.lp_book,
+                v3_book: &self.v3_book,
+            }
+        }
+    }
+
+    fn scan() -> Scan {
+        let mut trader = Trader::new();
+        trader.address = address(0xa);
+        trader.record(trade(100, Some(500)));
+        trader.record(trade(101, None));
+        trader.holdings.insert(address(3), -I256::from(U256::from(42)));
+
+        let mut pools = PoolRegistry::new(dexes());
+        assert!(pools.record(pair(), (address(1), address(2)), snapshot(100, 10, 20)));
+        assert!(pools.record(pair(), (address(1), address(2)), snapshot(101, 11, 19)));
+
+        let mut lp_book = LpBook::new();
+        lp_book.pairs.insert(pair(), PairState {
+            tokens: (address(1), address(2)),
+            reserves: (U256::from(11), U256::from(19)),
+            total_supply: U256::from(14),
+        });
+        lp_book.positions.insert((address(0xb), pair()), LpPosition {
+            owner: address(0xb),
+            pair: pair(),
+            tokens: (address(1), address(2)),
+            liquidity: U256::from(7),
+            deposited: (U256::from(5), U256::from(10)),
+            withdrawn: (U256::zero(), U256::one()),
+            hodl: (U256::from(5), U256::from(9)),
+            entry_growth: U256::from(1_250_000_000_000_000_000_u64),
+        });
+
+        let mut v3_book = V3Book::new();
+        let mut pool = V3Pool::new(address(0xb3), address(1), address(2), Some(3000));
+        pool.initialize(U256::one() << 96, -7);
+        pool.update_position(-60, 60, 1_000_000);
+        v3_book.pools.insert(pool.address, pool);
+        v3_book.pools.insert(address(0xb4), V3Pool::new(address(0xb4), address(1), address(3), None));
+        v3_book.fees.insert(address(0xb5), 500);
+
+        Scan {
+            traders: HashMap::from([(address(0xa), trader)]),
+            pools,
+            pair_tokens: PairTokens::from([(pair(), (address(1), address(2)))]),
+            lp_book,
+            v3_book,
+        }
+    }
+
+    #[test]
+    fn save_and_restore_round_trip() {
+        let scan = scan();
+        let checkpoint = Checkpoint { first_block: 100, next_block: 102, from_logs: true };
+        let mut store = Store::open(":memory:").unwrap();
+        assert_eq!(store.checkpoint().unwrap(), None);
+        store.save(scan.state(), checkpoint, 0).unwrap();
+        assert_eq!(store.checkpoint().unwrap(), Some(checkpoint));
+
+        let restored = store.restore(PoolRegistry::new(dexes())).unwrap();
+        let (saved, t) = (&scan.traders[&address(0xa)], &restored.traders[&address(0xa)]);
+        assert_eq!(t.trades, saved.trades);
+        assert_eq!(t.trades[1].cost, None);
+        assert_eq!((t.cum_txs, t.cum_gas, t.gas_fees, t.gas_burned, t.gas_tips, t.hist_cost),
+                   (saved.cum_txs, saved.cum_gas, saved.gas_fees, saved.gas_burned, saved.gas_tips, saved.hist_cost));
+        assert_eq!(t.holdings, saved.holdings);
+        assert_eq!(t.venues, saved.venues);
+
+        assert_eq!(restored.pools.history(&pair()), scan.pools.history(&pair()));
+        let pool = restored.pools.get(&pair()).unwrap();
+        assert_eq!((pool.reserves(), pool.fee_bps()), ((U256::from(11), U256::from(19)), 25));
+        assert_eq!(restored.pair_tokens, scan.pair_tokens);
+
+        assert_eq!(restored.lp_book.positions, scan.lp_book.positions);
+        let state = &restored.lp_book.pairs[&pair()];
+        assert_eq!((state.tokens, state.reserves, state.total_supply),
+                   ((address(1), address(2)), (U256::from(11), U256::from(19)), U256::from(14)));
+
+        assert_eq!(restored.v3_book.pools, scan.v3_book.pools);
+        assert_eq!(restored.v3_book.fees, scan.v3_book.fees);
+    }
+
+    #[test]
+    fn later_saves_only_add_new_blocks() {
+        let mut scan = scan();
+        let mut store = Store::open(":memory:").unwrap();
+        store.save(scan.state(), Checkpoint { first_block: 100, next_block: 102, from_logs: false }, 0).unwrap();
+
+        scan.traders.get_mut(&address(0xa)).unwrap().record(trade(102, Some(700)));
+        assert!(scan.pools.record(pair(), (address(1), address(2)), snapshot(102, 12, 18)));
+        let checkpoint = Checkpoint { first_block: 100, next_block: 103, from_logs: false };
+        store.save(scan.state(), checkpoint, 102).unwrap();
+
+        let restored = store.restore(PoolRegistry::new(dexes())).unwrap();
+        let blocks = restored.traders[&address(0xa)].trades.iter().map(|trade| trade.block).collect::<Vec<u64>>();
+        assert_eq!(blocks, vec![100, 101, 102]);
+        assert_eq!(restored.pools.history(&pair()).len(), 3);
+        assert_eq!(restored.traders[&address(0xa)].hist_cost, U256::from(1_200));
+        assert_eq!(store.checkpoint().unwrap(), Some(checkpoint));
+
+        // a pair whose DEX is gone is not admitted again
+        let restored = store.restore(PoolRegistry::new(DexRegistry::mainnet())).unwrap();
+        assert!(restored.pools.get(&pair()).is_none());
+    }
+}
@@ -0,0 +1,285 @@
+use web3::types::{H160, H256, Log, TransactionReceipt, U256};
+
+use std::collections::HashMap;
+
+use crate::dex::{join_venues, DexKind, DexRegistry};
+use crate::error::DecodeError;
+use crate::{log_topic, log_words, PoolHop, PoolRatios, UniswapTx};
+
+/// A pair `Swap` event together with the `Sync` the pair emitted right
+/// before it and the tokens it traded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PairSwap {
+    pub pair: H160,
+    // position in the receipt logs
+    pub log_index: usize,
+    // msg.sender of pair.swap, usually a router or a bot contract
+    pub sender: H160,
+    pub to: H160,
+    pub token_in: H160,
+    pub token_out: H160,
+    pub amount_in: U256,
+    pub amount_out: U256,
+    // (token_0, token_1) sorted the way the pair orders them
+    pub tokens: (H160, H160),
+    // (reserve0, reserve1) reported by Sync after the swap
+    pub reserves: (U256, U256),
+}
+
+impl PairSwap {
+    /// The swap as one hop of a trade.
+    pub fn hop(&self) -> PoolHop {
+        let amounts = match self.token_in == self.tokens.0 {
+            true => [self.amount_in, U256::zero(), U256::zero(), self.amount_out],
+            false => [U256::zero(), self.amount_in, self.amount_out, U256::zero()],
+        };
+        PoolHop::v2(self.pair, self.log_index, self.tokens, &amounts, self.reserves)
+    }
+}
+
+/// Token0 and token1 of every pair seen so far, learned from the Transfer
+/// logs around its swaps. Kept across transactions so a swap whose input
+/// transfer is not in the receipt can still be resolved.
+pub type PairTokens = HashMap<H160, (H160, H160)>;
+
+/// Every pair swap in the receipt, in log order. Only the pair logs are used,
+/// so swaps made by bots, aggregators or any contract calling the pairs
+/// directly are found as well as router swaps.
+pub fn read_pair_swaps(logs: &[Log], fid_vec: &[H256], pair_tokens: &mut PairTokens)
+-> Result<Vec<PairSwap>, DecodeError> {
+    let mut swaps: Vec<PairSwap> = vec![];
+    for (log_index, log) in logs.iter().enumerate() {
+        if log.topics.first() != Some(&fid_vec[3]) { // swap_fid
+            continue;
+        }
+        let pair = log.address;
+        let sender = H160::from(log_topic(log, 1)?);
+        let to = H160::from(log_topic(log, 2)?);
+        // amount0In, amount1In, amount0Out, amount1Out
+        let amounts = log_words(log, 4)?;
+        let sync = logs[..log_index].iter()
+            .rev()
+            .find(|sync| sync.address == pair && sync.topics.first() == Some(&fid_vec[2]))
+            .ok_or(DecodeError::MissingEvent { event: "Sync", hop: swaps.len() })?;
+        let reserves = log_words(sync, 2)?;
+
+        let tokens = pair_tokens_at(logs, fid_vec, log_index, pair, to, &amounts, pair_tokens)
+            .ok_or(DecodeError::MissingEvent { event: "Transfer", hop: swaps.len() })?;
+        pair_tokens.insert(pair, tokens);
+
+        // the side paid out tells which token went out
+        let (token_in, token_out, amount_in, amount_out) = match amounts[3] > amounts[2] {
+            true => (tokens.0, tokens.1, amounts[0], amounts[3]),
+            false => (tokens.1, tokens.0, amounts[1], amounts[2]),
+        };
+        swaps.push(PairSwap {
+            pair,
+            log_index,
+            sender,
+            to,
+            token_in,
+            token_out,
+            amount_in,
+            amount_out,
+            tokens,
+            reserves: (reserves[0], reserves[1]),
+        });
+    }
+    Ok(swaps)
+}
+
+// (token0, token1) of the pair behind the swap at `swap_index`. The pair sends
+// the output token to `to` just before emitting Swap, and the input token was
+// sent to the pair earlier in the transaction.
+fn pair_tokens_at(logs: &[Log], fid_vec: &[H256], swap_index: usize, pair: H160, to: H160,
+                  amounts: &[U256], pair_tokens: &PairTokens)
+-> Option<(H160, H160)> {
+    let transfer = |log: &Log| transfer_parties(log, fid_vec);
+    let window = pair_window(logs, fid_vec, swap_index, pair);
+    let token_out = window.iter()
+        .rev()
+        .find(|log| transfer(log) == Some((pair, to)))
+        .map(|log| log.address);
+    let token_in = window.iter()
+        .rev()
+        .find(|log| Some(log.address) != token_out
+              && transfer(log).is_some_and(|(_, dst)| dst == pair))
+        .map(|log| log.address);
+
+    let out_is_token0 = amounts[2] > amounts[3];
+    let tokens = match (token_in, token_out) {
+        (Some(token_in), Some(token_out)) => match out_is_token0 {
+            true => (token_out, token_in),
+            false => (token_in, token_out),
+        },
+        // fall back on what earlier swaps of this pair showed
+        _ => *pair_tokens.get(&pair)?,
+    };
+    // pairs keep token0 < token1, anything else is not a v2 pair swap
+    match tokens.0 < tokens.1 {
+        true => Some(tokens),
+        false => None,
+    }
+}
+
+// (from, to) of an ERC20 Transfer log, None for any other log.
+pub(crate) fn transfer_parties(log: &Log, fid_vec: &[H256]) -> Option<(H160, H160)> {
+    match log.topics.first() == Some(&fid_vec[1]) && log.topics.len() >= 3 { // transfer_fid
+        true => Some((H160::from(log.topics[1]), H160::from(log.topics[2]))),
+        false => None,
+    }
+}
+
+// Logs between the previous Swap, Mint or Burn of the pair and the event at
+// `index`. Transfers before that belong to the earlier event.
+pub(crate) fn pair_window<'a>(logs: &'a [Log], fid_vec: &[H256], index: usize, pair: H160)
+-> &'a [Log] {
+    let pair_events = [fid_vec[3], fid_vec[6], fid_vec[7]]; // swap, mint, burn
+    let start = logs[..index].iter()
+        .rposition(|log| log.address == pair
+                   && log.topics.first().is_some_and(|topic| pair_events.contains(topic)))
+        .map_or(0, |prev_event| prev_event + 1);
+    &logs[start..index]
+}
+
+/// Group the pair swaps of one transaction into trades. A swap paying out to
+/// the pair of a later swap is a hop of the same multi-hop trade.
+pub fn chain_swaps(swaps: Vec<PairSwap>) -> Vec<Vec<PairSwap>> {
+    let mut chains: Vec<Vec<PairSwap>> = vec![];
+    for swap in swaps {
+        let next_hop_of = chains.iter_mut()
+            .find(|chain| chain.last().is_some_and(|last| last.to == swap.pair
+                                                   && last.token_out == swap.token_in));
+        match next_hop_of {
+            Some(chain) => chain.push(swap),
+            None => chains.push(vec![swap]),
+        }
+    }
+    chains
+}
+
+/// Rebuild every trade in the receipt from pair logs alone, in the same
+/// shape `read_uniswap_tx` returns for router calls. The caller attributes
+/// them to the transaction sender, and each trade is tagged with the venues
+/// whose factories deployed its pairs.
+pub fn read_log_swaps(receipt: &TransactionReceipt, fid_vec: &[H256], dexes: &DexRegistry,
+                      pair_tokens: &mut PairTokens)
+-> Result<Vec<UniswapTx>, DecodeError> {
+    if receipt.status == Some(0_u64.into()) {
+        return Err(DecodeError::Reverted);
+    }
+    let swaps = read_pair_swaps(&receipt.logs, fid_vec, pair_tokens)?;
+    Ok(chain_swaps(swaps).into_iter()
+        .map(|chain| {
+            let first = &chain[0];
+            let last = &chain[chain.len() - 1];
+            let pool_ratios = chain.iter()
+                .map(|hop| hop.hop())
+                .collect::<PoolRatios>();
+            let venue = join_venues(chain.iter()
+                .map(|hop| dexes.venue(DexKind::V2, hop.pair, hop.tokens.0, hop.tokens.1)));
+            (Some(first.token_in), first.amount_in, Some(last.token_out), last.amount_out,
+             last.to, pool_ratios, venue)
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_logs::{address, fid_vec, pair_swap, transfer};
+
+    const A: u64 = 0x10;
+    const B: u64 = 0x20;
+    const C: u64 = 0x30;
+    const TRADER: u64 = 0xbb;
+    const BOT: u64 = 0xcc;
+
+    fn sorted(x: u64, y: u64) -> (H160, H160) {
+        (address(x.min(y)), address(x.max(y)))
+    }
+
+    fn pair(x: u64, y: u64) -> H160 {
+        let (token0, token1) = sorted(x, y);
+        let dexes = DexRegistry::mainnet();
+        let uniswap_v2 = dexes.iter().find(|dex| dex.kind == DexKind::V2).unwrap();
+        uniswap_v2.pair_address(token0, token1)
+    }
+
+    // `token_in` sent to the pair of x and y, swapped and the other token paid to `to`.
+    fn swap_logs(x: u64, y: u64, token_in: u64, amount_in: u64, amount_out: u64, to: H160) -> Vec<Log> {
+        let token_out = match token_in == x {
+            true => y,
+            false => x,
+        };
+        let mut hop = pair_swap(pair(x, y), sorted(x, y), address(token_in), amount_in, amount_out,
+                                address(BOT), to);
+        let swap = hop.pop().unwrap();
+        hop.insert(0, transfer(address(token_in), address(BOT), pair(x, y), amount_in));
+        hop.push(transfer(address(token_out), pair(x, y), to, amount_out));
+        hop.push(swap);
+        hop
+    }
+
+    fn receipt(logs: Vec<Log>) -> TransactionReceipt {
+        TransactionReceipt { logs, status: Some(1_u64.into()), ..Default::default() }
+    }
+
+    #[test]
+    fn tokens_come_from_the_transfers() {
+        for (token_in, token_out) in [(A, B), (B, A)] {
+            let mut pair_tokens = PairTokens::new();
+            let logs = swap_logs(A, B, token_in, 1000, 900, address(TRADER));
+            let swaps = read_pair_swaps(&logs, &fid_vec(), &mut pair_tokens).unwrap();
+            assert_eq!(swaps.len(), 1);
+            let swap = &swaps[0];
+            assert_eq!((swap.token_in, swap.amount_in), (address(token_in), U256::from(1000)));
+            assert_eq!((swap.token_out, swap.amount_out), (address(token_out), U256::from(900)));
+            assert_eq!((swap.sender, swap.to, swap.log_index), (address(BOT), address(TRADER), 3));
+            assert_eq!(swap.reserves, (U256::from(1_000_000), U256::from(1_000_000)));
+            assert_eq!(pair_tokens[&pair(A, B)], sorted(A, B));
+        }
+    }
+
+    #[test]
+    fn known_tokens_fill_in_for_missing_transfers() {
+        // only the Sync and Swap, as when the input was sent in an earlier transaction
+        let logs = pair_swap(pair(A, B), sorted(A, B), address(B), 1000, 900, address(BOT), address(TRADER));
+        let mut pair_tokens = PairTokens::new();
+        assert_eq!(read_pair_swaps(&logs, &fid_vec(), &mut pair_tokens),
+                   Err(DecodeError::MissingEvent { event: "Transfer", hop: 0 }));
+
+        pair_tokens.insert(pair(A, B), sorted(A, B));
+        let swaps = read_pair_swaps(&logs, &fid_vec(), &mut pair_tokens).unwrap();
+        assert_eq!((swaps[0].token_in, swaps[0].token_out), (address(B), address(A)));
+    }
+
+    #[test]
+    fn multi_hop_swaps_are_one_trade() {
+        let mut logs = swap_logs(A, B, A, 1000, 900, pair(B, C));
+        logs.extend(swap_logs(B, C, B, 900, 800, address(TRADER)));
+        let trades = read_log_swaps(&receipt(logs), &fid_vec(), &DexRegistry::mainnet(), &mut PairTokens::new())
+            .unwrap();
+        assert_eq!(trades.len(), 1);
+        let (start_token, start_amount, end_token, end_amount, to, hops, venue) = &trades[0];
+        assert_eq!((*start_token, *start_amount), (Some(address(A)), U256::from(1000)));
+        assert_eq!((*end_token, *end_amount), (Some(address(C)), U256::from(800)));
+        assert_eq!((*to, venue.as_str()), (address(TRADER), "uniswap-v2"));
+        assert_eq!(hops.iter().map(|hop| hop.pool).collect::<Vec<H160>>(), vec![pair(A, B), pair(B, C)]);
+    }
+
+    #[test]
+    fn unrelated_swaps_stay_apart() {
+        // C paid to the pair of A and B, which then sells A rather than C,
+        // and a swap paid to someone else
+        let mut logs = swap_logs(A, C, A, 300, 200, pair(A, B));
+        logs.extend(swap_logs(A, B, A, 1000, 900, address(TRADER)));
+        logs.extend(swap_logs(B, C, C, 500, 400, address(0xdd)));
+        let swaps = read_pair_swaps(&logs, &fid_vec(), &mut PairTokens::new()).unwrap();
+        assert_eq!(swaps.len(), 3);
+        assert_eq!((swaps[1].token_in, swaps[1].amount_in), (address(A), U256::from(1000)));
+        let chains = chain_swaps(swaps);
+        assert_eq!(chains.iter().map(|chain| chain.len()).collect::<Vec<usize>>(), vec![1, 1, 1]);
+        assert_eq!(chains[2][0].to, address(0xdd));
+    }
+}
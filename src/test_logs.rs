@@ -0,0 +1,70 @@
+// Receipt logs for unit tests, with the event ids in the order of the scan's
+// `fid_vec`.
+
+use web3::ethabi::{self, Token};
+use web3::types::{H160, H256, Log, U256};
+
+pub fn address(n: u64) -> H160 {
+    H160::from_low_u64_be(n)
+}
+
+pub fn fid_vec() -> Vec<H256> {
+    [
+        "e1fffcc4923d04b559f4d29a8bfc6cda04eb5b0d3c460751c2402c5c5cc9109c", // deposit
+        "ddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef", // transfer
+        "1c411e9a96e071241c2f21f7726b17ae89e3cab4c78be50e062b03a9fffbbad1", // sync
+        "d78ad95fa46c994b6551d0da85fc275fe613ce37657fb8d5e3d130840159d822", // swap
+        "7fcf532c15f0a6db0bd6d0e038bea71d30d808c7d98cb3bf7268a95bf5081b65", // withdrawal
+        "8c5be1e5ebec7d5bd14f71427d1e84f3dd0314c0f7b2291e5b200ac8c7c3b925", // approval
+        "4c209b5fc8ad50758f13e2e1088ba56a560dff690a1c6fef26394f4c03821c4f", // mint
+        "dccd412f0b1252819cb1fd330b93224ca42612892bb3f4f789976e6d81936496", // burn
+        "c42079f94a6350d7e6235f29174924f928cc2ac818eb64fed8004e115fbcca67", // v3 swap
+        "7a53080ba414158be7ec69b987b5fb7d07dee101fe85488f0853ae16239d0bde", // v3 mint
+        "0c396cd989a39f4459b5fa1aed6a9a8dcdbc45908acfd67e028cd568da98982c", // v3 burn
+        "98636036cb66a9c19a37435efc1e90142190214e8abeb821bdba3f2990dd4c95", // v3 initialize
+    ]
+    .iter()
+    .map(|fid| H256::from_slice(&hex::decode(fid).unwrap()))
+    .collect()
+}
+
+pub fn log(address: H160, topics: Vec<H256>, words: &[u64]) -> Log {
+    Log {
+        address,
+        topics,
+        data: ethabi::encode(&words.iter().map(|word| Token::Uint(U256::from(*word))).collect::<Vec<Token>>())
+            .into(),
+        block_hash: None,
+        block_number: None,
+        transaction_hash: None,
+        transaction_index: None,
+        log_index: None,
+        transaction_log_index: None,
+        log_type: None,
+        removed: None,
+    }
+}
+
+pub fn transfer(token: H160, from: H160, to: H160, amount: u64) -> Log {
+    log(token, vec![fid_vec()[1], H256::from(from), H256::from(to)], &[amount])
+}
+
+pub fn sync(pair: H160, reserve0: u64, reserve1: u64) -> Log {
+    log(pair, vec![fid_vec()[2]], &[reserve0, reserve1])
+}
+
+// Swap(sender, amount0In, amount1In, amount0Out, amount1Out, to)
+pub fn swap(pair: H160, sender: H160, to: H160, amounts: [u64; 4]) -> Log {
+    log(pair, vec![fid_vec()[3], H256::from(sender), H256::from(to)], &amounts)
+}
+
+// The Sync and Swap of a pair of the sorted `tokens` selling `amount_in` of
+// `token_in` for `amount_out` paid to `to`.
+pub fn pair_swap(pair: H160, tokens: (H160, H160), token_in: H160, amount_in: u64, amount_out: u64,
+                 sender: H160, to: H160) -> Vec<Log> {
+    let amounts = match token_in == tokens.0 {
+        true => [amount_in, 0, 0, amount_out],
+        false => [0, amount_in, amount_out, 0],
+    };
+    vec![sync(pair, 1_000_000, 1_000_000), swap(pair, sender, to, amounts)]
+}
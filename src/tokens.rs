@@ -0,0 +1,224 @@
+use serde::Deserialize;
+use web3::types::{H160, U256};
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use crate::amount::{format_units, I256};
+
+/// Symbol, name and decimals of an erc20 token.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct TokenInfo {
+    pub address: H160,
+    pub symbol: String,
+    #[serde(default)]
+    pub name: String,
+    pub decimals: u8,
+}
+
+/// Token metadata used to turn raw token units into human units.
+///
+/// Tokens missing from the registry are shown in raw units next to their
+/// address, since guessing 18 decimals would silently mis-scale e.g. USDC.
+#[derive(Debug, Clone, Default)]
+pub struct TokenRegistry {
+    tokens: HashMap<H160, TokenInfo>,
+}
+
+// A cached `decimals()` result is either the plain number or the raw 32 byte
+// hex string returned by eth_call.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum CachedDecimals {
+    Number(u8),
+    Raw(String),
+}
+
+impl TokenRegistry {
+    pub fn new() -> TokenRegistry {
+        TokenRegistry { tokens: HashMap::new() }
+    }
+
+    pub fn insert(&mut self, info: TokenInfo) {
+        self.tokens.insert(info.address, info);
+    }
+
+    pub fn get(&self, address: &H160) -> Option<&TokenInfo> {
+        self.tokens.get(address)
+    }
+
+    pub fn contains(&self, address: &H160) -> bool {
+        self.tokens.contains_key(address)
+    }
+
+    pub fn len(&self) -> usize {
+        self.tokens.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tokens.is_empty()
+    }
+
+    pub fn decimals(&self, address: &H160) -> Option<u8> {
+        self.tokens.get(address).map(|info| info.decimals)
+    }
+
+    /// Symbol of the token, or its address when it is not registered.
+    pub fn symbol(&self, address: &H160) -> String {
+        match self.tokens.get(address) {
+            Some(info) if !info.symbol.is_empty() => info.symbol.clone(),
+            _ => format!("{:?}", address),
+        }
+    }
+
+    /// Exact amount in human units followed by the symbol, e.g. `1.5 USDC`.
+    /// Unknown tokens are printed in raw units.
+    pub fn format_amount(&self, address: &H160, amount: I256) -> String {
+        match self.decimals(address) {
+            Some(decimals) => format!("{} {}", format_units(amount, decimals), self.symbol(address)),
+            None => format!("{} raw {:?}", amount, address),
+        }
+    }
+
+    /// Load a token list from a `.json` or `.csv` file, picked by extension.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<TokenRegistry, Box<dyn Error>> {
+        let path = path.as_ref();
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("csv") => TokenRegistry::load_csv(path),
+            _ => TokenRegistry::load_json(path),
+        }
+    }
+
+    /// Load a JSON array of `{"address", "symbol", "name", "decimals"}` objects.
+    pub fn load_json<P: AsRef<Path>>(path: P) -> Result<TokenRegistry, Box<dyn Error>> {
+        let file = File::open(path)?;
+        let reader = BufReader::new(file);
+
+        let tokens: Vec<TokenInfo> = serde_json::from_reader(reader)?;
+
+        let mut registry = TokenRegistry::new();
+        tokens.into_iter().for_each(|info| registry.insert(info));
+        Ok(registry)
+    }
+
+    /// Load a CSV file with an `address,symbol,name,decimals` header.
+    pub fn load_csv<P: AsRef<Path>>(path: P) -> Result<TokenRegistry, Box<dyn Error>> {
+        let mut reader = csv::Reader::from_path(path)?;
+
+        let mut registry = TokenRegistry::new();
+        for row in reader.deserialize() {
+            let info: TokenInfo = row?;
+            registry.insert(info);
+        }
+        Ok(registry)
+    }
+
+    /// Fill in decimals from a JSON object of cached `decimals()` call results,
+    /// keyed by token address. Tokens already registered keep their symbol and
+    /// name, new ones are added without a symbol.
+    pub fn load_decimals_cache<P: AsRef<Path>>(&mut self, path: P) -> Result<(), Box<dyn Error>> {
+        let file = File::open(path)?;
+        let reader = BufReader::new(file);
+
+        let cached: HashMap<H160, CachedDecimals> = serde_json::from_reader(reader)?;
+        for (address, decimals) in cached {
+            let decimals = match decimals {
+                CachedDecimals::Number(decimals) => decimals,
+                CachedDecimals::Raw(raw) => {
+                    let raw = raw.trim_start_matches("0x");
+                    let value = U256::from_str_radix(raw, 16)?;
+                    if value > U256::from(u8::MAX) {
+                        return Err(format!("decimals() for {:?} out of range: {}", address, value).into());
+                    }
+                    value.low_u32() as u8
+                },
+            };
+            self.tokens.entry(address)
+                .and_modify(|info| info.decimals = decimals)
+                .or_insert(TokenInfo {
+                    address,
+                    symbol: String::new(),
+                    name: String::new(),
+                    decimals,
+                });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const USDC: &str = "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48";
+    const WETH: &str = "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2";
+    const DAI: &str = "0x6b175474e89094c44da98b954eedeac495271d0f";
+
+    fn address(hex_str: &str) -> H160 {
+        H160::from_slice(&hex::decode(&hex_str[2..]).unwrap())
+    }
+
+    // Write `contents` to a temporary file named `name`, hand its path to `f`
+    // and remove it again.
+    fn with_file<T>(name: &str, contents: &str, f: impl FnOnce(&Path) -> T) -> T {
+        let path = std::env::temp_dir().join(format!("eth_explo_tokens_{}_{}", std::process::id(), name));
+        std::fs::write(&path, contents).unwrap();
+        let result = f(&path);
+        std::fs::remove_file(&path).unwrap();
+        result
+    }
+
+    #[test]
+    fn json_and_csv_lists() {
+        let json = format!(r#"[{{"address": "{}", "symbol": "USDC", "name": "USD Coin", "decimals": 6}},
+                               {{"address": "{}", "symbol": "WETH", "decimals": 18}}]"#, USDC, WETH);
+        let from_json = with_file("list.json", &json, |path| TokenRegistry::load(path)).unwrap();
+        let csv = format!("address,symbol,name,decimals\n{},USDC,USD Coin,6\n{},WETH,,18\n", USDC, WETH);
+        let from_csv = with_file("list.csv", &csv, |path| TokenRegistry::load(path)).unwrap();
+
+        for registry in [from_json, from_csv] {
+            assert_eq!(registry.len(), 2);
+            assert_eq!(registry.get(&address(USDC)), Some(&TokenInfo {
+                address: address(USDC),
+                symbol: "USDC".to_string(),
+                name: "USD Coin".to_string(),
+                decimals: 6,
+            }));
+            assert_eq!(registry.get(&address(WETH)).unwrap().name, "");
+            assert_eq!(registry.format_amount(&address(USDC), I256::from(U256::from(1_500_000))), "1.5 USDC");
+            assert_eq!(registry.format_amount(&address(WETH), -I256::from(U256::exp10(17))), "-0.1 WETH");
+        }
+    }
+
+    #[test]
+    fn unknown_tokens_stay_raw() {
+        let registry = TokenRegistry::new();
+        assert_eq!(registry.decimals(&address(DAI)), None);
+        assert_eq!(registry.symbol(&address(DAI)), format!("{:?}", address(DAI)));
+        assert_eq!(registry.format_amount(&address(DAI), I256::from(U256::from(1_500_000))),
+                   format!("1500000 raw {:?}", address(DAI)));
+    }
+
+    #[test]
+    fn cached_decimals() {
+        let mut registry = TokenRegistry::new();
+        registry.insert(TokenInfo { address: address(WETH), symbol: "WETH".to_string(), name: String::new(), decimals: 0 });
+        let cache = format!(r#"{{"{}": 6, "{}": "0x{:064x}"}}"#, USDC, WETH, 18);
+        with_file("decimals.json", &cache, |path| registry.load_decimals_cache(path)).unwrap();
+
+        // registered tokens keep their symbol, new ones have none
+        assert_eq!(registry.get(&address(WETH)).map(|info| (info.symbol.as_str(), info.decimals)), Some(("WETH", 18)));
+        assert_eq!(registry.get(&address(USDC)).map(|info| (info.symbol.as_str(), info.decimals)), Some(("", 6)));
+        assert_eq!(registry.format_amount(&address(USDC), I256::from(U256::from(2_500_000))),
+                   format!("2.5 {:?}", address(USDC)));
+
+        let out_of_range = format!(r#"{{"{}": "0x{:064x}"}}"#, DAI, 256);
+        let error = with_file("bad_decimals.json", &out_of_range, |path| registry.load_decimals_cache(path))
+            .unwrap_err();
+        assert!(error.to_string().contains("out of range"), "{}", error);
+        assert!(!registry.contains(&address(DAI)));
+    }
+}
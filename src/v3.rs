@@ -0,0 +1,842 @@
+use web3::ethabi::{self, ParamType, Token};
+use web3::types::{H160, H256, Log, Transaction, TransactionReceipt, U256};
+
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::amount::I256;
+use crate::dex::{join_venues, Dex, DexKind, DexRegistry};
+use crate::error::DecodeError;
+use crate::swaps::{transfer_parties, PairTokens};
+use crate::v3_math::{compute_swap_step, get_sqrt_ratio_at_tick,
+                     implied_fee_pips, MAX_TICK, MIN_TICK};
+use crate::{log_topic, log_words, PoolHop, PoolRatios, UniswapTx};
+
+/// UniswapV3Factory on mainnet.
+pub const V3_FACTORY: &str = "1f98431c8ad98523631ae4a59f267346ea31f984";
+/// keccak256 of the UniswapV3Pool creation code, used in the CREATE2 address.
+pub const V3_POOL_INIT_CODE_HASH: &str =
+    "e34f199b19b2b4f47f68442619d555527d244f78a3297ea89325f843f87b8b54";
+/// SwapRouter, whose swap params carry a deadline.
+pub const V3_SWAP_ROUTER: &str = "e592427a0aece92de3edee1f18e0157c05861564";
+/// SwapRouter02, whose swap params do not.
+pub const V3_SWAP_ROUTER_02: &str = "68b3465833fb72a70ecdf485e0e4c7bd8665fc45";
+
+/// Fee tiers enabled on the factory, in hundredths of a bip.
+pub const FEE_TIERS: [u32; 4] = [100, 500, 3000, 10000];
+
+/// Tick spacing the factory gives a fee tier.
+pub fn tick_spacing(fee: u32) -> Option<i32> {
+    match fee {
+        100 => Some(1),
+        500 => Some(10),
+        3000 => Some(60),
+        10000 => Some(200),
+        _ => None,
+    }
+}
+
+/// Fee tier of `pool` if a registered V3 factory deployed it for the two
+/// sorted tokens.
+pub fn pool_fee(dexes: &DexRegistry, pool: H160, token0: H160, token1: H160) -> Option<u32> {
+    dexes.by_pool(DexKind::V3, pool, token0, token1)
+        .and_then(|dex| dex.pool_fee(pool, token0, token1))
+}
+
+/// Fee tier closest to the fee a swap paid, for pools whose tier is not
+/// known from a router path or their address. Swaps that crossed a tick or
+/// are too small to measure give None.
+pub fn infer_fee(swap: &V3SwapEvent) -> Option<u32> {
+    let zero_for_one = swap.amount0.is_positive();
+    let (amount_in, amount_out) = match zero_for_one {
+        true => (swap.amount0.abs(), swap.amount1.abs()),
+        false => (swap.amount1.abs(), swap.amount0.abs()),
+    };
+    // rounding swamps the fee of tiny swaps
+    if amount_in < U256::exp10(6) {
+        return None;
+    }
+    let pips = implied_fee_pips(swap.sqrt_price_x96, swap.liquidity, amount_in, amount_out,
+                                zero_for_one)?;
+    FEE_TIERS.iter()
+        .copied()
+        .find(|tier| pips.abs_diff(u64::from(*tier)) <= u64::from(*tier) / 20 + 1)
+}
+
+/// SwapRouter and SwapRouter02 swap functions. Each has one selector per
+/// router since the param structs differ by the deadline field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum V3SwapMethod {
+    ExactInputSingle,
+    ExactInput,
+    ExactOutputSingle,
+    ExactOutput,
+}
+
+// multicall(bytes[]), multicall(uint256,bytes[]) and multicall(bytes32,bytes[])
+const MULTICALL: [u8; 4] = [0xac, 0x96, 0x50, 0xd8];
+const MULTICALL_DEADLINE: [u8; 4] = [0x5a, 0xe4, 0x01, 0xdc];
+const MULTICALL_BLOCKHASH: [u8; 4] = [0x1f, 0x04, 0x64, 0xd1];
+
+impl V3SwapMethod {
+    pub const ALL: [V3SwapMethod; 4] = [
+        V3SwapMethod::ExactInputSingle,
+        V3SwapMethod::ExactInput,
+        V3SwapMethod::ExactOutputSingle,
+        V3SwapMethod::ExactOutput,
+    ];
+
+    /// Selector on SwapRouter (`deadline` true) or SwapRouter02.
+    pub fn selector(&self, deadline: bool) -> [u8; 4] {
+        match (self, deadline) {
+            (V3SwapMethod::ExactInputSingle, true) => [0x41, 0x4b, 0xf3, 0x89],
+            (V3SwapMethod::ExactInput, true) => [0xc0, 0x4b, 0x8d, 0x59],
+            (V3SwapMethod::ExactOutputSingle, true) => [0xdb, 0x3e, 0x21, 0x98],
+            (V3SwapMethod::ExactOutput, true) => [0xf2, 0x8c, 0x04, 0x98],
+            (V3SwapMethod::ExactInputSingle, false) => [0x04, 0xe4, 0x5a, 0xaf],
+            (V3SwapMethod::ExactInput, false) => [0xb8, 0x58, 0x18, 0x3f],
+            (V3SwapMethod::ExactOutputSingle, false) => [0x50, 0x23, 0xb4, 0xdf],
+            (V3SwapMethod::ExactOutput, false) => [0x09, 0xb8, 0x13, 0x46],
+        }
+    }
+
+    /// The method and whether its params carry a deadline.
+    pub fn from_selector(selector: &[u8]) -> Option<(V3SwapMethod, bool)> {
+        V3SwapMethod::ALL.iter()
+            .flat_map(|method| [(*method, true), (*method, false)])
+            .find(|(method, deadline)| method.selector(*deadline) == selector)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            V3SwapMethod::ExactInputSingle => "exactInputSingle",
+            V3SwapMethod::ExactInput => "exactInput",
+            V3SwapMethod::ExactOutputSingle => "exactOutputSingle",
+            V3SwapMethod::ExactOutput => "exactOutput",
+        }
+    }
+
+    pub fn exact_input(&self) -> bool {
+        matches!(self, V3SwapMethod::ExactInputSingle | V3SwapMethod::ExactInput)
+    }
+
+    pub fn single(&self) -> bool {
+        matches!(self, V3SwapMethod::ExactInputSingle | V3SwapMethod::ExactOutputSingle)
+    }
+
+    // The single params struct every swap function takes.
+    fn params(&self, deadline: bool) -> Vec<ParamType> {
+        let uint = ParamType::Uint(256);
+        let mut fields = match self.single() {
+            // tokenIn, tokenOut, fee, recipient
+            true => vec![ParamType::Address, ParamType::Address, ParamType::Uint(24),
+                         ParamType::Address],
+            // path, recipient
+            false => vec![ParamType::Bytes, ParamType::Address],
+        };
+        if deadline {
+            fields.push(uint.clone());
+        }
+        // amountIn, amountOutMinimum | amountOut, amountInMaximum
+        fields.extend([uint.clone(), uint]);
+        if self.single() {
+            // sqrtPriceLimitX96
+            fields.push(ParamType::Uint(160));
+        }
+        vec![ParamType::Tuple(fields)]
+    }
+}
+
+/// A V3 router swap, with the path in swap order even for exact output calls
+/// whose encoded path runs backwards.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct V3SwapCall {
+    pub method: V3SwapMethod,
+    // tokens.len() == fees.len() + 1
+    pub tokens: Vec<H160>,
+    pub fees: Vec<u32>,
+    pub recipient: H160,
+    // exact input, or the most the caller pays
+    pub amount_in: U256,
+    // least the caller accepts, or the exact output
+    pub amount_out: U256,
+}
+
+impl V3SwapCall {
+    /// Pool of every hop along the path, as deployed by the factory of `dex`.
+    pub fn pools(&self, dex: &Dex) -> Vec<H160> {
+        self.tokens.windows(2)
+            .zip(self.fees.iter())
+            .map(|(pair, fee)| match pair[0] < pair[1] {
+                true => dex.pool_address(pair[0], pair[1], *fee),
+                false => dex.pool_address(pair[1], pair[0], *fee),
+            })
+            .collect()
+    }
+}
+
+// Packed path: token (20 bytes) followed by fee (3 bytes) and token pairs.
+fn decode_path(path: &[u8]) -> Result<(Vec<H160>, Vec<u32>), DecodeError> {
+    if path.len() < 43 || !(path.len() - 20).is_multiple_of(23) {
+        return Err(DecodeError::InvalidAbi(format!("v3 path of {} bytes", path.len())));
+    }
+    let mut tokens = vec![H160::from_slice(&path[0..20])];
+    let mut fees = vec![];
+    for hop in path[20..].chunks(23) {
+        fees.push(u32::from_be_bytes([0, hop[0], hop[1], hop[2]]));
+        tokens.push(H160::from_slice(&hop[3..23]));
+    }
+    Ok((tokens, fees))
+}
+
+/// Decode V3 router calldata into its swaps. Multicalls are unpacked and
+/// their calls that are not swaps, like `unwrapWETH9` or `refundETH`, are
+/// left out.
+pub fn decode_v3_calls(input: &[u8]) -> Result<Vec<V3SwapCall>, DecodeError> {
+    if input.len() < 4 {
+        return Err(DecodeError::ShortCalldata { needed: 4, len: input.len() });
+    }
+    let selector = &input[0..4];
+    let multicall_params = match selector {
+        s if s == MULTICALL => Some(vec![]),
+        s if s == MULTICALL_DEADLINE => Some(vec![ParamType::Uint(256)]),
+        s if s == MULTICALL_BLOCKHASH => Some(vec![ParamType::FixedBytes(32)]),
+        _ => None,
+    };
+    if let Some(mut params) = multicall_params {
+        params.push(ParamType::Array(Box::new(ParamType::Bytes)));
+        let tokens = ethabi::decode(&params, &input[4..])
+            .map_err(|e| DecodeError::InvalidAbi(format!("multicall: {}", e)))?;
+        let mut calls = vec![];
+        for data in tokens[tokens.len() - 1].clone().into_array().unwrap_or_default() {
+            let data = data.into_bytes().unwrap_or_default();
+            if data.len() >= 4 && V3SwapMethod::from_selector(&data[0..4]).is_some() {
+                calls.extend(decode_v3_calls(&data)?);
+            }
+        }
+        return Ok(calls);
+    }
+
+    let (method, deadline) = V3SwapMethod::from_selector(selector)
+        .ok_or_else(|| DecodeError::UnknownMethod(hex::encode(selector)))?;
+    let invalid = |what: &str| DecodeError::InvalidAbi(format!("{}: {}", method.name(), what));
+    let fields = match ethabi::decode(&method.params(deadline), &input[4..])
+        .map_err(|e| invalid(&e.to_string()))?
+        .remove(0) {
+        Token::Tuple(fields) => fields,
+        _ => return Err(invalid("expected tuple")),
+    };
+    let uint = |token: &Token| token.clone().into_uint().ok_or_else(|| invalid("expected uint"));
+    let addr = |token: &Token| token.clone().into_address().ok_or_else(|| invalid("expected address"));
+
+    let (tokens, fees, rest) = match method.single() {
+        true => (vec![addr(&fields[0])?, addr(&fields[1])?],
+                 vec![uint(&fields[2])?.low_u32()],
+                 &fields[3..]),
+        false => {
+            let path = fields[0].clone().into_bytes().ok_or_else(|| invalid("expected bytes"))?;
+            let (mut tokens, mut fees) = decode_path(&path)?;
+            if !method.exact_input() {
+                tokens.reverse();
+                fees.reverse();
+            }
+            (tokens, fees, &fields[1..])
+        },
+    };
+    let recipient = addr(&rest[0])?;
+    let amounts = match deadline {
+        true => &rest[2..4],
+        false => &rest[1..3],
+    };
+    let (amount_in, amount_out) = match method.exact_input() {
+        true => (uint(&amounts[0])?, uint(&amounts[1])?),
+        // (amountOut, amountInMaximum)
+        false => (uint(&amounts[1])?, uint(&amounts[0])?),
+    };
+    Ok(vec![V3SwapCall { method, tokens, fees, recipient, amount_in, amount_out }])
+}
+
+/// A pool `Swap` event. Amounts are from the pool's side: positive was paid
+/// in, negative was paid out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct V3SwapEvent {
+    pub pool: H160,
+    // position in the receipt logs
+    pub log_index: usize,
+    pub sender: H160,
+    pub recipient: H160,
+    pub amount0: I256,
+    pub amount1: I256,
+    pub sqrt_price_x96: U256,
+    pub liquidity: u128,
+    pub tick: i32,
+}
+
+impl V3SwapEvent {
+    /// (token_in, amount_in, token_out, amount_out) given the pool tokens.
+    pub fn flow(&self, (token0, token1): (H160, H160)) -> (H160, U256, H160, U256) {
+        match self.amount0.is_positive() {
+            true => (token0, self.amount0.abs(), token1, self.amount1.abs()),
+            false => (token1, self.amount1.abs(), token0, self.amount0.abs()),
+        }
+    }
+}
+
+// int24 words and topics are sign extended, so the low 32 bits are the tick.
+fn word_to_tick(word: U256) -> i32 {
+    word.low_u32() as i32
+}
+
+/// Every V3 pool `Swap` in the receipt, in log order.
+pub fn read_v3_swaps(logs: &[Log], fid_vec: &[H256]) -> Result<Vec<V3SwapEvent>, DecodeError> {
+    let mut swaps = vec![];
+    for (log_index, log) in logs.iter().enumerate() {
+        if log.topics.first() != Some(&fid_vec[8]) { // v3_swap_fid
+            continue;
+        }
+        // amount0, amount1, sqrtPriceX96, liquidity, tick
+        let words = log_words(log, 5)?;
+        swaps.push(V3SwapEvent {
+            pool: log.address,
+            log_index,
+            sender: H160::from(log_topic(log, 1)?),
+            recipient: H160::from(log_topic(log, 2)?),
+            amount0: I256::from_word(words[0]),
+            amount1: I256::from_word(words[1]),
+            sqrt_price_x96: words[2],
+            liquidity: words[3].low_u128(),
+            tick: word_to_tick(words[4]),
+        });
+    }
+    Ok(swaps)
+}
+
+/// (token0, token1) of a V3 pool from the tokens it moved in the receipt, or
+/// from earlier receipts.
+pub fn v3_pool_tokens(logs: &[Log], fid_vec: &[H256], pool: H160, pair_tokens: &mut PairTokens)
+-> Option<(H160, H160)> {
+    if let Some(tokens) = pair_tokens.get(&pool) {
+        return Some(*tokens);
+    }
+    let mut moved = logs.iter()
+        .filter(|log| transfer_parties(log, fid_vec)
+                .is_some_and(|(from, to)| from == pool || to == pool))
+        .map(|log| log.address)
+        .collect::<Vec<H160>>();
+    moved.sort();
+    moved.dedup();
+    match moved[..] {
+        [token0, token1] => {
+            pair_tokens.insert(pool, (token0, token1));
+            Some((token0, token1))
+        },
+        _ => None,
+    }
+}
+
+// Trade in the shape of `read_uniswap_tx` from V3 swaps in hop order. V3 hops
+// have no reserves.
+fn v3_trade(hops: &[(V3SwapEvent, (H160, H160))], venue: String) -> UniswapTx {
+    let pool_ratios = hops.iter()
+        .map(|(event, tokens)| {
+            let (token_in, amount_in, token_out, amount_out) = event.flow(*tokens);
+            PoolHop {
+                pool: event.pool,
+                log_index: event.log_index,
+                token_in,
+                token_out,
+                amount_in,
+                amount_out,
+                tokens: *tokens,
+                reserves_before: None,
+                reserves_after: None,
+            }
+        })
+        .collect::<PoolRatios>();
+    let first = &pool_ratios[0];
+    let last = &pool_ratios[pool_ratios.len() - 1];
+    (Some(first.token_in), first.amount_in, Some(last.token_out), last.amount_out,
+     hops[hops.len() - 1].0.recipient, pool_ratios, venue)
+}
+
+/// Decode a V3 router transaction: every swap call in it, matched with the
+/// pool `Swap` events of its path. The router swaps nothing but the paths, one
+/// hop after the other, so the events follow the hops in log order; an event
+/// of any other pool than the hop's skips the transaction. The fee tier of
+/// every pool on the way is passed on to `v3_book`.
+pub fn read_v3_tx(tx: &Transaction, receipt: &TransactionReceipt, fid_vec: &[H256], dex: &Dex,
+                  pair_tokens: &mut PairTokens, v3_book: &mut V3Book)
+-> Result<Vec<UniswapTx>, DecodeError> {
+    if receipt.logs.is_empty() || receipt.status == Some(0_u64.into()) {
+        return Err(DecodeError::Reverted);
+    }
+    let calls = decode_v3_calls(&tx.input.0)?;
+    if calls.is_empty() {
+        return Err(DecodeError::UnknownMethod(hex::encode(&tx.input.0[0..4])));
+    }
+    let mut swaps = read_v3_swaps(&receipt.logs, fid_vec)?.into_iter();
+
+    let mut trades = vec![];
+    for call in calls {
+        let pools = call.pools(dex);
+        let mut hops = vec![];
+        for (hop, (tokens, fee)) in call.tokens.windows(2).zip(call.fees.iter()).enumerate() {
+            // a swap of some other pool, e.g. from a callback, is not this hop
+            let event = swaps.next()
+                .filter(|event| event.pool == pools[hop])
+                .ok_or(DecodeError::MissingEvent { event: "Swap", hop })?;
+            let sorted = match tokens[0] < tokens[1] {
+                true => (tokens[0], tokens[1]),
+                false => (tokens[1], tokens[0]),
+            };
+            pair_tokens.insert(event.pool, sorted);
+            v3_book.fees.insert(event.pool, *fee);
+            hops.push((event, sorted));
+        }
+        trades.push(v3_trade(&hops, dex.name.clone()));
+    }
+    Ok(trades)
+}
+
+/// Every V3 trade in the receipt from pool logs alone. A swap paid to the
+/// contract that makes the next swap, in the token that swap sells, is a hop
+/// of the same trade.
+pub fn read_v3_log_swaps(receipt: &TransactionReceipt, fid_vec: &[H256], dexes: &DexRegistry,
+                         pair_tokens: &mut PairTokens)
+-> Result<Vec<UniswapTx>, DecodeError> {
+    if receipt.status == Some(0_u64.into()) {
+        return Err(DecodeError::Reverted);
+    }
+    let mut chains: Vec<Vec<(V3SwapEvent, (H160, H160))>> = vec![];
+    for swap in read_v3_swaps(&receipt.logs, fid_vec)? {
+        // pools whose tokens cannot be told are skipped
+        let tokens = match v3_pool_tokens(&receipt.logs, fid_vec, swap.pool, pair_tokens) {
+            Some(tokens) => tokens,
+            None => continue,
+        };
+        let (token_in, _, _, _) = swap.flow(tokens);
+        let next_hop_of = chains.iter_mut()
+            .find(|chain| chain.last().is_some_and(|(last, last_tokens)|
+                last.recipient == swap.sender && last.flow(*last_tokens).2 == token_in));
+        match next_hop_of {
+            Some(chain) => chain.push((swap, tokens)),
+            None => chains.push(vec![(swap, tokens)]),
+        }
+    }
+    Ok(chains.iter()
+        .map(|chain| v3_trade(chain, join_venues(chain.iter()
+            .map(|(swap, tokens)| dexes.venue(DexKind::V3, swap.pool, tokens.0, tokens.1)))))
+        .collect())
+}
+
+/// Concentrated liquidity pool state rebuilt from the pool's events.
+///
+/// `Swap` events carry the price, tick and in range liquidity, so those are
+/// exact after any swap. The tick map only holds positions minted or burnt
+/// within the scanned blocks, so quotes that cross into ranges funded before
+/// them can be off.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct V3Pool {
+    pub address: H160,
+    pub token0: H160,
+    pub token1: H160,
+    // hundredths of a bip, None until a router path, the pool address or a
+    // swap gives it away
+    pub fee: Option<u32>,
+    pub sqrt_price_x96: U256,
+    pub liquidity: u128,
+    pub tick: i32,
+    // liquidity added when crossing each tick left to right
+    pub ticks: BTreeMap<i32, i128>,
+}
+
+impl V3Pool {
+    pub fn new(address: H160, token0: H160, token1: H160, fee: Option<u32>) -> V3Pool {
+        V3Pool {
+            address,
+            token0,
+            token1,
+            fee,
+            sqrt_price_x96: U256::zero(),
+            liquidity: 0,
+            tick: 0,
+            ticks: BTreeMap::new(),
+        }
+    }
+
+    pub fn tokens(&self) -> (H160, H160) {
+        (self.token0, self.token1)
+    }
+
+    pub fn initialize(&mut self, sqrt_price_x96: U256, tick: i32) {
+        self.sqrt_price_x96 = sqrt_price_x96;
+        self.tick = tick;
+    }
+
+    /// Add (`delta` > 0, Mint) or remove (Burn) liquidity over a tick range.
+    pub fn update_position(&mut self, tick_lower: i32, tick_upper: i32, delta: i128) {
+        for (tick, net) in [(tick_lower, delta), (tick_upper, -delta)] {
+            let entry = self.ticks.entry(tick).or_insert(0);
+            *entry += net;
+            if *entry == 0 {
+                self.ticks.remove(&tick);
+            }
+        }
+        if tick_lower <= self.tick && self.tick < tick_upper {
+            self.liquidity = add_delta(self.liquidity, delta);
+        }
+    }
+
+    pub fn apply_swap(&mut self, swap: &V3SwapEvent) {
+        self.sqrt_price_x96 = swap.sqrt_price_x96;
+        self.liquidity = swap.liquidity;
+        self.tick = swap.tick;
+    }
+
+    /// Output of an exact input swap of `amount_in` of `token_in`, crossing
+    /// initialized ticks the way the pool does. None for a token the pool
+    /// does not hold, or a pool without a price or fee yet.
+    pub fn quote(&self, token_in: H160, amount_in: U256) -> Option<U256> {
+        let fee = self.fee?;
+        let zero_for_one = match token_in {
+            t if t == self.token0 => true,
+            t if t == self.token1 => false,
+            _ => return None,
+        };
+        if self.sqrt_price_x96.is_zero() {
+            return None;
+        }
+        let mut sqrt_price = self.sqrt_price_x96;
+        let mut liquidity = self.liquidity;
+        let mut tick = self.tick;
+        let mut remaining = amount_in;
+        let mut amount_out = U256::zero();
+
+        while !remaining.is_zero() {
+            let next_tick = match zero_for_one {
+                true => self.ticks.range(..=tick).next_back(),
+                false => self.ticks.range(tick + 1..).next(),
+            };
+            let sqrt_target = match (next_tick, zero_for_one) {
+                (Some((next, _)), _) => get_sqrt_ratio_at_tick((*next).clamp(MIN_TICK, MAX_TICK)),
+                (None, true) => get_sqrt_ratio_at_tick(MIN_TICK) + 1,
+                (None, false) => get_sqrt_ratio_at_tick(MAX_TICK) - 1,
+            };
+            if liquidity > 0 {
+                let (sqrt_next, step_in, step_out, fee) =
+                    compute_swap_step(sqrt_price, sqrt_target, liquidity, remaining, fee);
+                remaining = remaining.saturating_sub(step_in + fee);
+                amount_out += step_out;
+                sqrt_price = sqrt_next;
+                if sqrt_next != sqrt_target {
+                    break;
+                }
+            } else {
+                sqrt_price = sqrt_target;
+            }
+            match next_tick {
+                Some((next, net)) => match zero_for_one {
+                    true => {
+                        liquidity = add_delta(liquidity, -net);
+                        tick = next - 1;
+                    },
+                    false => {
+                        liquidity = add_delta(liquidity, *net);
+                        tick = *next;
+                    },
+                },
+                // out of liquidity at the price limit
+                None => break,
+            }
+        }
+        Some(amount_out)
+    }
+}
+
+fn add_delta(liquidity: u128, delta: i128) -> u128 {
+    match delta < 0 {
+        true => liquidity.saturating_sub(delta.unsigned_abs()),
+        false => liquidity.saturating_add(delta as u128),
+    }
+}
+
+/// Every V3 pool seen so far, keyed by pool address.
+#[derive(Debug, Clone, Default)]
+pub struct V3Book {
+    pub pools: HashMap<H160, V3Pool>,
+    // fee tiers learned from router paths, applied when the pool shows up
+    pub fees: HashMap<H160, u32>,
+}
+
+impl V3Book {
+    pub fn new() -> Self {
+        V3Book::default()
+    }
+
+    /// Apply the `Initialize`, `Mint`, `Burn` and `Swap` events of the
+    /// receipt's V3 pools in log order. New pools take their fee tier from
+    /// the registered factory that deployed them.
+    pub fn observe_logs(&mut self, logs: &[Log], fid_vec: &[H256], dexes: &DexRegistry,
+                        pair_tokens: &mut PairTokens) {
+        for log in logs {
+            let topic = match log.topics.first() {
+                Some(topic) => *topic,
+                None => continue,
+            };
+            // v3_swap_fid, v3_mint_fid, v3_burn_fid, initialize_fid
+            if !fid_vec[8..12].contains(&topic) {
+                continue;
+            }
+            let pool = match self.pools.entry(log.address) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => match v3_pool_tokens(logs, fid_vec, log.address, pair_tokens) {
+                    Some((token0, token1)) => entry.insert(V3Pool::new(
+                        log.address, token0, token1, pool_fee(dexes, log.address, token0, token1))),
+                    None => continue,
+                },
+            };
+            if pool.fee.is_none() {
+                pool.fee = self.fees.get(&log.address).copied();
+            }
+            // a malformed event leaves the pool as it was
+            let _ = apply_pool_log(pool, log, topic, fid_vec);
+        }
+    }
+
+    /// The V3 pool pairing `coin` with `quote_token` that has the most in
+    /// range liquidity.
+    pub fn deepest_pool(&self, coin: H160, quote_token: H160) -> Option<&V3Pool> {
+        self.pools.values()
+            .filter(|pool| pool.tokens() == (coin, quote_token) || pool.tokens() == (quote_token, coin))
+            .filter(|pool| !pool.sqrt_price_x96.is_zero())
+            .max_by_key(|pool| pool.liquidity)
+    }
+}
+
+fn apply_pool_log(pool: &mut V3Pool, log: &Log, topic: H256, fid_vec: &[H256])
+-> Result<(), DecodeError> {
+    if topic == fid_vec[8] {
+        let swap = read_v3_swaps(std::slice::from_ref(log), fid_vec)?.remove(0);
+        if pool.fee.is_none() {
+            pool.fee = infer_fee(&swap);
+        }
+        pool.apply_swap(&swap);
+    } else if topic == fid_vec[11] {
+        // sqrtPriceX96, tick
+        let words = log_words(log, 2)?;
+        pool.initialize(words[0], word_to_tick(words[1]));
+    } else {
+        // Mint(sender, owner, tickLower, tickUpper, amount, ..) has the sender
+        // as data, Burn(owner, tickLower, tickUpper, amount, ..) does not
+        let is_mint = topic == fid_vec[9];
+        let tick_lower = word_to_tick(U256::from_big_endian(log_topic(log, 2)?.as_bytes()));
+        let tick_upper = word_to_tick(U256::from_big_endian(log_topic(log, 3)?.as_bytes()));
+        let amount = match is_mint {
+            true => log_words(log, 2)?[1],
+            false => log_words(log, 1)?[0],
+        }.low_u128() as i128;
+        let delta = match is_mint {
+            true => amount,
+            false => -amount,
+        };
+        pool.update_position(tick_lower, tick_upper, delta);
+    }
+    Ok(())
+}
+
+/// Calldata the V3 routers can hold swaps in: a swap function or a multicall.
+pub fn is_v3_router_call(input: &[u8]) -> bool {
+    input.len() >= 4
+        && (V3SwapMethod::from_selector(&input[0..4]).is_some()
+            || [MULTICALL, MULTICALL_DEADLINE, MULTICALL_BLOCKHASH].iter()
+                .any(|selector| selector[..] == input[0..4]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::v3_math::get_tick_at_sqrt_ratio;
+
+    const WETH: u64 = 1;
+    const USDC: u64 = 2;
+
+    fn address(n: u64) -> H160 {
+        H160::from_low_u64_be(n)
+    }
+
+    fn fid_vec() -> Vec<H256> {
+        let mut fids = vec![H256::zero(); 12];
+        fids[8] = H256::from_slice(&hex::decode(
+            "c42079f94a6350d7e6235f29174924f928cc2ac818eb64fed8004e115fbcca67").unwrap());
+        fids
+    }
+
+    // exactInputSingle of 1000 WETH for USDC through the 0.3% pool.
+    fn exact_input_single() -> Transaction {
+        let fields = vec![Token::Address(address(WETH)), Token::Address(address(USDC)),
+                          Token::Uint(3000.into()), Token::Address(address(0xbb)),
+                          Token::Uint(U256::MAX), Token::Uint(1000.into()), Token::Uint(1.into()),
+                          Token::Uint(U256::zero())];
+        let mut input = V3SwapMethod::ExactInputSingle.selector(true).to_vec();
+        input.extend(ethabi::encode(&[Token::Tuple(fields)]));
+        Transaction { input: input.into(), ..Default::default() }
+    }
+
+    // Swap event of `pool` paying 1000 token0 in and 1990 token1 out.
+    fn swap_log(pool: H160) -> Log {
+        let words = [U256::from(1000), U256::MAX - U256::from(1989), U256::one() << 96,
+                     U256::from(1_000_000), U256::zero()];
+        Log {
+            address: pool,
+            topics: vec![fid_vec()[8], H256::from(address(0xcc)), H256::from(address(0xbb))],
+            data: ethabi::encode(&words.map(Token::Uint)).into(),
+            block_hash: None,
+            block_number: None,
+            transaction_hash: None,
+            transaction_index: None,
+            log_index: None,
+            transaction_log_index: None,
+            log_type: None,
+            removed: None,
+        }
+    }
+
+    fn uniswap() -> Dex {
+        DexRegistry::mainnet().iter().find(|dex| dex.kind == DexKind::V3).cloned().unwrap()
+    }
+
+    // A V3 fork with its own factory and pool creation code.
+    fn fork() -> Dex {
+        Dex {
+            name: "fork-v3".to_string(),
+            kind: DexKind::V3,
+            routers: vec![address(0xf0)],
+            factory: address(0xfac),
+            init_code_hash: H256::from_low_u64_be(0x11),
+            fee_bps: 0,
+        }
+    }
+
+    fn read(dex: &Dex, logs: Vec<Log>) -> Result<Vec<UniswapTx>, DecodeError> {
+        let receipt = TransactionReceipt { logs, status: Some(1_u64.into()), ..Default::default() };
+        read_v3_tx(&exact_input_single(), &receipt, &fid_vec(), dex, &mut PairTokens::new(),
+                   &mut V3Book::new())
+    }
+
+    #[test]
+    fn swaps_of_the_path_pool() {
+        let pool = uniswap().pool_address(address(WETH), address(USDC), 3000);
+        let trades = read(&uniswap(), vec![swap_log(pool)]).unwrap();
+        assert_eq!(trades.len(), 1);
+        let (token_in, amount_in, token_out, amount_out, recipient, hops, _) = &trades[0];
+        assert_eq!((*token_in, *amount_in), (Some(address(WETH)), U256::from(1000)));
+        assert_eq!((*token_out, *amount_out), (Some(address(USDC)), U256::from(1990)));
+        assert_eq!(*recipient, address(0xbb));
+        assert_eq!(hops[0].pool, pool);
+    }
+
+    #[test]
+    fn swaps_of_other_pools_are_skipped() {
+        let other = uniswap().pool_address(address(WETH), address(USDC), 500);
+        assert_eq!(read(&uniswap(), vec![swap_log(other)]).unwrap_err(),
+                   DecodeError::MissingEvent { event: "Swap", hop: 0 });
+    }
+
+    #[test]
+    fn fork_pools_come_from_their_factory() {
+        let pool = fork().pool_address(address(WETH), address(USDC), 3000);
+        let uniswap_pool = uniswap().pool_address(address(WETH), address(USDC), 3000);
+        assert_ne!(pool, uniswap_pool);
+        let trades = read(&fork(), vec![swap_log(pool)]).unwrap();
+        assert_eq!(trades[0].5[0].pool, pool);
+        assert_eq!(trades[0].6, "fork-v3");
+        assert!(read(&fork(), vec![swap_log(uniswap_pool)]).is_err());
+
+        let mut dexes = DexRegistry::mainnet();
+        dexes.insert(fork());
+        assert_eq!(pool_fee(&dexes, pool, address(WETH), address(USDC)), Some(3000));
+        assert_eq!(pool_fee(&DexRegistry::mainnet(), pool, address(WETH), address(USDC)), None);
+        let mut pair_tokens = PairTokens::from([(pool, (address(WETH), address(USDC)))]);
+        let mut book = V3Book::new();
+        book.observe_logs(&[swap_log(pool)], &fid_vec(), &dexes, &mut pair_tokens);
+        assert_eq!(book.pools[&pool].fee, Some(3000));
+    }
+    const LIQUIDITY: u128 = 1_000_000_000_000_000_000;
+
+    // Pool at price 1 with LIQUIDITY over [-120, 120] and as much again over
+    // [-60, 60].
+    fn stacked_pool() -> V3Pool {
+        let mut pool = V3Pool::new(address(0x99), address(WETH), address(USDC), Some(3000));
+        pool.initialize(U256::one() << 96, 0);
+        pool.update_position(-120, 120, LIQUIDITY as i128);
+        pool.update_position(-60, 60, LIQUIDITY as i128);
+        pool
+    }
+
+    #[test]
+    fn quotes_within_one_range() {
+        let pool = stacked_pool();
+        let amount = U256::exp10(12);
+        let (_, _, amount_out, _) = compute_swap_step(U256::one() << 96, get_sqrt_ratio_at_tick(-60),
+                                                      2 * LIQUIDITY, amount, 3000);
+        assert_eq!(pool.quote(address(WETH), amount), Some(amount_out));
+        assert_eq!(pool.quote(address(0x77), amount), None);
+    }
+
+    #[test]
+    fn quotes_cross_initialized_ticks() {
+        let pool = stacked_pool();
+        assert_eq!(pool.liquidity, 2 * LIQUIDITY);
+        let amount = U256::exp10(16);
+        let to_tick = get_sqrt_ratio_at_tick(-60);
+        let (sqrt_next, in_first, out_first, fee_first) =
+            compute_swap_step(U256::one() << 96, to_tick, 2 * LIQUIDITY, amount, 3000);
+        assert_eq!(sqrt_next, to_tick);
+        // past tick -60 only the wider position is left
+        let (_, _, out_second, _) = compute_swap_step(to_tick, get_sqrt_ratio_at_tick(-120),
+                                                      LIQUIDITY, amount - in_first - fee_first,
+                                                      3000);
+        let quote = pool.quote(address(WETH), amount).unwrap();
+        assert_eq!(quote, out_first + out_second);
+        let (_, _, unchanged, _) = compute_swap_step(U256::one() << 96, get_sqrt_ratio_at_tick(-120),
+                                                     2 * LIQUIDITY, amount, 3000);
+        assert!(quote < unchanged);
+    }
+
+    // Swap event of `amount_in` through a pool at price 1 with `fee`.
+    fn swap_with_fee(fee: u32, amount_in: U256, zero_for_one: bool) -> V3SwapEvent {
+        let target = match zero_for_one {
+            true => get_sqrt_ratio_at_tick(MIN_TICK),
+            false => get_sqrt_ratio_at_tick(MAX_TICK),
+        };
+        let (sqrt_next, step_in, amount_out, step_fee) =
+            compute_swap_step(U256::one() << 96, target, LIQUIDITY, amount_in, fee);
+        let paid = I256::from(step_in + step_fee);
+        let received = -I256::from(amount_out);
+        let (amount0, amount1) = match zero_for_one {
+            true => (paid, received),
+            false => (received, paid),
+        };
+        V3SwapEvent {
+            pool: address(0x99),
+            log_index: 0,
+            sender: address(0xcc),
+            recipient: address(0xbb),
+            amount0,
+            amount1,
+            sqrt_price_x96: sqrt_next,
+            liquidity: LIQUIDITY,
+            tick: get_tick_at_sqrt_ratio(sqrt_next),
+        }
+    }
+
+    #[test]
+    fn fees_are_inferred_per_tier() {
+        for fee in FEE_TIERS {
+            for zero_for_one in [true, false] {
+                assert_eq!(infer_fee(&swap_with_fee(fee, U256::exp10(15), zero_for_one)), Some(fee));
+            }
+        }
+        assert_eq!(infer_fee(&swap_with_fee(3000, U256::exp10(5), true)), None);
+    }
+}
@@ -0,0 +1,281 @@
+// Integer math of the Uniswap V3 core contracts (TickMath, SqrtPriceMath and
+// SwapMath), ported to U256 with U512 intermediates so quotes match the pools
+// to the last unit.
+use web3::ethabi::ethereum_types::U512;
+use web3::types::U256;
+
+pub const MIN_TICK: i32 = -887272;
+pub const MAX_TICK: i32 = 887272;
+
+// Fees are in hundredths of a bip.
+const FEE_DENOMINATOR: u64 = 1_000_000;
+
+/// sqrt(1.0001^tick) as a Q64.96 number, exactly as `TickMath.getSqrtRatioAtTick`.
+pub fn get_sqrt_ratio_at_tick(tick: i32) -> U256 {
+    let abs_tick = tick.clamp(MIN_TICK, MAX_TICK).unsigned_abs();
+    let hex = |s: &str| U256::from_str_radix(s, 16).expect("valid tick math constant");
+
+    let mut ratio = match abs_tick & 0x1 != 0 {
+        true => hex("fffcb933bd6fad37aa2d162d1a594001"),
+        false => U256::one() << 128,
+    };
+    // 1 / sqrt(1.0001^(2^i)) as Q128.128 for every other bit of the tick
+    let factors = [
+        (0x2, "fff97272373d413259a46990580e213a"),
+        (0x4, "fff2e50f5f656932ef12357cf3c7fdcc"),
+        (0x8, "ffe5caca7e10e4e61c3624eaa0941cd0"),
+        (0x10, "ffcb9843d60f6159c9db58835c926644"),
+        (0x20, "ff973b41fa98c081472e6896dfb254c0"),
+        (0x40, "ff2ea16466c96a3843ec78b326b52861"),
+        (0x80, "fe5dee046a99a2a811c461f1969c3053"),
+        (0x100, "fcbe86c7900a88aedcffc83b479aa3a4"),
+        (0x200, "f987a7253ac413176f2b074cf7815e54"),
+        (0x400, "f3392b0822b70005940c7a398e4b70f3"),
+        (0x800, "e7159475a2c29b7443b29c7fa6e889d9"),
+        (0x1000, "d097f3bdfd2022b8845ad8f792aa5825"),
+        (0x2000, "a9f746462d870fdf8a65dc1f90e061e5"),
+        (0x4000, "70d869a156d2a1b890bb3df62baf32f7"),
+        (0x8000, "31be135f97d08fd981231505542fcfa6"),
+        (0x10000, "9aa508b5b7a84e1c677de54f3e99bc9"),
+        (0x20000, "5d6af8dedb81196699c329225ee604"),
+        (0x40000, "2216e584f5fa1ea926041bedfe98"),
+        (0x80000, "48a170391f7dc42444e8fa2"),
+    ];
+    for (bit, factor) in factors {
+        if abs_tick & bit != 0 {
+            ratio = (ratio * hex(factor)) >> 128;
+        }
+    }
+    if tick > 0 {
+        ratio = U256::MAX / ratio;
+    }
+    // Q128.128 to Q64.96, rounding up
+    let round_up = match (ratio % (U256::one() << 32)).is_zero() {
+        true => U256::zero(),
+        false => U256::one(),
+    };
+    (ratio >> 32) + round_up
+}
+
+/// Greatest tick whose sqrt ratio is at most `sqrt_price_x96`, the same tick
+/// `TickMath.getTickAtSqrtRatio` returns.
+pub fn get_tick_at_sqrt_ratio(sqrt_price_x96: U256) -> i32 {
+    let (mut low, mut high) = (MIN_TICK, MAX_TICK);
+    while low < high {
+        // round up so the loop always moves
+        let mid = low + (high - low + 1) / 2;
+        match get_sqrt_ratio_at_tick(mid) <= sqrt_price_x96 {
+            true => low = mid,
+            false => high = mid - 1,
+        }
+    }
+    low
+}
+
+fn q96() -> U256 {
+    U256::one() << 96
+}
+
+fn to_u256(value: U512) -> U256 {
+    U256::try_from(value).unwrap_or(U256::MAX)
+}
+
+fn mul_div(a: U256, b: U256, denominator: U256) -> U256 {
+    to_u256(U512::from(a) * U512::from(b) / U512::from(denominator))
+}
+
+fn mul_div_rounding_up(a: U256, b: U256, denominator: U256) -> U256 {
+    let product = U512::from(a) * U512::from(b);
+    let denominator = U512::from(denominator);
+    let round_up = match (product % denominator).is_zero() {
+        true => U512::zero(),
+        false => U512::one(),
+    };
+    to_u256(product / denominator + round_up)
+}
+
+fn div_rounding_up(a: U256, b: U256) -> U256 {
+    let round_up = match (a % b).is_zero() {
+        true => U256::zero(),
+        false => U256::one(),
+    };
+    a / b + round_up
+}
+
+/// Token0 needed to move the price between the two ratios with `liquidity`
+/// in range.
+pub fn get_amount0_delta(sqrt_a: U256, sqrt_b: U256, liquidity: u128, round_up: bool) -> U256 {
+    let (sqrt_a, sqrt_b) = (sqrt_a.min(sqrt_b), sqrt_a.max(sqrt_b));
+    if sqrt_a.is_zero() {
+        return U256::zero();
+    }
+    let numerator1 = U256::from(liquidity) << 96;
+    let numerator2 = sqrt_b - sqrt_a;
+    match round_up {
+        true => div_rounding_up(mul_div_rounding_up(numerator1, numerator2, sqrt_b), sqrt_a),
+        false => mul_div(numerator1, numerator2, sqrt_b) / sqrt_a,
+    }
+}
+
+/// Token1 needed to move the price between the two ratios with `liquidity`
+/// in range.
+pub fn get_amount1_delta(sqrt_a: U256, sqrt_b: U256, liquidity: u128, round_up: bool) -> U256 {
+    let (sqrt_a, sqrt_b) = (sqrt_a.min(sqrt_b), sqrt_a.max(sqrt_b));
+    match round_up {
+        true => mul_div_rounding_up(U256::from(liquidity), sqrt_b - sqrt_a, q96()),
+        false => mul_div(U256::from(liquidity), sqrt_b - sqrt_a, q96()),
+    }
+}
+
+/// Price after adding `amount_in` of token0 (`zero_for_one`) or token1.
+pub fn get_next_sqrt_price_from_input(sqrt_price_x96: U256, liquidity: u128, amount_in: U256,
+                                      zero_for_one: bool) -> U256 {
+    if amount_in.is_zero() {
+        return sqrt_price_x96;
+    }
+    let liquidity = U256::from(liquidity);
+    match zero_for_one {
+        // L * sqrtP / (L + amount * sqrtP), rounding up
+        true => {
+            let numerator1 = U512::from(liquidity) << 96;
+            let denominator = numerator1 + U512::from(amount_in) * U512::from(sqrt_price_x96);
+            let product = numerator1 * U512::from(sqrt_price_x96);
+            let round_up = match (product % denominator).is_zero() {
+                true => U512::zero(),
+                false => U512::one(),
+            };
+            to_u256(product / denominator + round_up)
+        },
+        // sqrtP + amount / L, rounding down
+        false => sqrt_price_x96 + mul_div(amount_in, q96(), liquidity),
+    }
+}
+
+/// One step of an exact input swap towards `sqrt_target`, as
+/// `SwapMath.computeSwapStep`. Returns the new price, the amount taken in
+/// without the fee, the amount paid out and the fee.
+pub fn compute_swap_step(sqrt_current: U256, sqrt_target: U256, liquidity: u128,
+                         amount_remaining: U256, fee_pips: u32)
+-> (U256, U256, U256, U256) {
+    let zero_for_one = sqrt_current >= sqrt_target;
+    let fee_pips = U256::from(fee_pips);
+    let fee_denominator = U256::from(FEE_DENOMINATOR);
+
+    let remaining_less_fee = mul_div(amount_remaining, fee_denominator - fee_pips, fee_denominator);
+    let amount_in_to_target = match zero_for_one {
+        true => get_amount0_delta(sqrt_target, sqrt_current, liquidity, true),
+        false => get_amount1_delta(sqrt_current, sqrt_target, liquidity, true),
+    };
+    let sqrt_next = match remaining_less_fee >= amount_in_to_target {
+        true => sqrt_target,
+        false => get_next_sqrt_price_from_input(sqrt_current, liquidity, remaining_less_fee,
+                                                zero_for_one),
+    };
+    let reached_target = sqrt_next == sqrt_target;
+
+    let (amount_in, amount_out) = match zero_for_one {
+        true => (match reached_target {
+                     true => amount_in_to_target,
+                     false => get_amount0_delta(sqrt_next, sqrt_current, liquidity, true),
+                 },
+                 get_amount1_delta(sqrt_next, sqrt_current, liquidity, false)),
+        false => (match reached_target {
+                      true => amount_in_to_target,
+                      false => get_amount1_delta(sqrt_current, sqrt_next, liquidity, true),
+                  },
+                  get_amount0_delta(sqrt_current, sqrt_next, liquidity, false)),
+    };
+    let fee_amount = match reached_target {
+        true => mul_div_rounding_up(amount_in, fee_pips, fee_denominator - fee_pips),
+        // whatever is left of the input after the swap is the fee
+        false => amount_remaining - amount_in,
+    };
+    (sqrt_next, amount_in, amount_out, fee_amount)
+}
+
+/// Fee in hundredths of a bip implied by a swap that stayed within one tick
+/// range: the price before is rebuilt from the output, and whatever input
+/// exceeds what that price move needs is the fee. None when the amounts do
+/// not fit a single range.
+pub fn implied_fee_pips(sqrt_after: U256, liquidity: u128, amount_in: U256, amount_out: U256,
+                        zero_for_one: bool) -> Option<u64> {
+    if liquidity == 0 || amount_in.is_zero() || sqrt_after.is_zero() {
+        return None;
+    }
+    let liquidity_q96 = U512::from(liquidity) << 96;
+    let sqrt_before = match zero_for_one {
+        // amount1 out = L * (before - after) / Q96
+        true => sqrt_after + mul_div(amount_out, q96(), U256::from(liquidity)),
+        // amount0 out = L * Q96 * (after - before) / (before * after)
+        false => to_u256(liquidity_q96 * U512::from(sqrt_after)
+                         / (U512::from(amount_out) * U512::from(sqrt_after) + liquidity_q96)),
+    };
+    let without_fee = match zero_for_one {
+        true => get_amount0_delta(sqrt_after, sqrt_before, liquidity, true),
+        false => get_amount1_delta(sqrt_before, sqrt_after, liquidity, true),
+    };
+    if without_fee > amount_in {
+        return None;
+    }
+    Some(mul_div(amount_in - without_fee, U256::from(FEE_DENOMINATOR), amount_in).low_u64())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIN_SQRT_RATIO: u64 = 4295128739;
+    const MAX_SQRT_RATIO: &str = "1461446703485210103287273052203988822378723970342";
+
+    #[test]
+    fn sqrt_ratios_at_known_ticks() {
+        assert_eq!(get_sqrt_ratio_at_tick(0), U256::one() << 96);
+        assert_eq!(get_sqrt_ratio_at_tick(MIN_TICK), U256::from(MIN_SQRT_RATIO));
+        assert_eq!(get_sqrt_ratio_at_tick(MAX_TICK), U256::from_dec_str(MAX_SQRT_RATIO).unwrap());
+        assert_eq!(get_sqrt_ratio_at_tick(50),
+                   U256::from_dec_str("79426470787362580746886972461").unwrap());
+        assert_eq!(get_sqrt_ratio_at_tick(100),
+                   U256::from_dec_str("79625275426524748796330556128").unwrap());
+        // ticks past the limits are clamped
+        assert_eq!(get_sqrt_ratio_at_tick(MAX_TICK + 1), get_sqrt_ratio_at_tick(MAX_TICK));
+    }
+
+    #[test]
+    fn ticks_round_trip_through_their_ratio() {
+        for tick in [MIN_TICK, -500_000, -60, -1, 0, 1, 60, 200_000, MAX_TICK - 1] {
+            let ratio = get_sqrt_ratio_at_tick(tick);
+            assert_eq!(get_tick_at_sqrt_ratio(ratio), tick);
+            // anything below the ratio of a tick belongs to the tick under it
+            if tick > MIN_TICK {
+                assert_eq!(get_tick_at_sqrt_ratio(ratio - 1), tick - 1);
+            }
+        }
+    }
+
+    #[test]
+    fn swap_steps_stop_at_the_target() {
+        // SwapMath.spec: one for zero capped at the price target
+        let price = U256::one() << 96;
+        let target = U256::from_dec_str("79623317895830914510639640423").unwrap();
+        let (next, amount_in, amount_out, fee) = compute_swap_step(
+            price, target, 2_000_000_000_000_000_000, U256::exp10(18), 600);
+        assert_eq!(next, target);
+        assert_eq!(amount_in, U256::from(9975124224178055_u64));
+        assert_eq!(amount_out, U256::from(9925619580021728_u64));
+        assert_eq!(fee, U256::from(5988667735148_u64));
+    }
+
+    #[test]
+    fn swap_steps_spend_the_whole_input_short_of_the_target() {
+        let price = U256::one() << 96;
+        let (next, amount_in, amount_out, fee) = compute_swap_step(
+            price, get_sqrt_ratio_at_tick(MIN_TICK), 1_000_000_000_000_000_000, U256::exp10(15),
+            3000);
+        assert!(next < price);
+        assert_eq!(amount_in + fee, U256::exp10(15));
+        assert!(amount_out < amount_in);
+        let pips = implied_fee_pips(next, 1_000_000_000_000_000_000, U256::exp10(15), amount_out,
+                                    true).unwrap();
+        assert!(pips.abs_diff(3000) <= 1);
+    }
+}